@@ -0,0 +1,187 @@
+//! The admin API, for scripts to manage a running server without signals,
+//! served with `--admin-port` on a port of its own that only listens on the
+//! loopback address.
+//!
+//! - `GET /stats` gets counts of connections, requests and bytes sent.
+//! - `POST /purge` empties the caches.
+//! - `POST /maintenance/on` and `POST /maintenance/off` switch maintenance
+//!   mode.
+//! - `POST /shutdown` stops the server.
+//!
+//! Web pages open in the browser can send requests to the loopback address
+//! too, so the `POST`s need the token, given with `--admin-token` or made up
+//! and logged at startup, in an `Authorization: Bearer` header, which a page
+//! can't send to another origin without its agreement. Forms can't send
+//! JSON either, so the bodies they can send are refused. And requests must
+//! name a loopback address as their host, so that a page whose domain has
+//! been pointed at the loopback address can't read the stats.
+
+use crate::negotiate::Format;
+use crate::{maintenance, stats, Body, Config, Result};
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+/// Notified when asked to shut down
+pub static SHUTDOWN: Notify = Notify::const_new();
+
+pub async fn bind(port: u16) -> Result<TcpListener> {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+    info!("admin API: http://{}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// A token for when none was given, logged so that it can be used
+pub fn random_token() -> String {
+    let token = format!(
+        "{:016x}{:016x}",
+        RandomState::new().build_hasher().finish(),
+        RandomState::new().build_hasher().finish()
+    );
+    info!("admin API token: {}", token);
+    token
+}
+
+/// Serve the admin API on the listener, forever, to requests with the token.
+pub async fn serve(listener: TcpListener, config: Config, token: String) {
+    let token: Arc<str> = token.into();
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("admin accept error: {}", e);
+                continue;
+            }
+        };
+        let config = config.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let config = config.clone();
+                let token = token.clone();
+                async move { respond(&config, &token, req) }
+            });
+            let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = conn.await {
+                debug!("admin connection error: {}", e);
+            }
+        });
+    }
+}
+
+fn respond(config: &Config, token: &str, req: Request<Incoming>) -> Result<Response<Body>> {
+    let done = || crate::json_to_response(&serde_json::json!({ "ok": true }), StatusCode::OK);
+    let error = |status| {
+        crate::make_error_response_from_code(status, Format::Json, crate::i18n::Lang::default())
+    };
+    if !has_loopback_host(&req) {
+        return error(StatusCode::FORBIDDEN);
+    }
+    if req.method() == Method::POST {
+        if has_form_body(&req) {
+            return error(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        if !has_token(&req, token) {
+            let mut resp = error(StatusCode::UNAUTHORIZED)?;
+            resp.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return Ok(resp);
+        }
+    }
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/stats") => {
+            let stats = serde_json::to_value(stats::snapshot()).expect("stats are JSON");
+            crate::json_to_response(&stats, StatusCode::OK)
+        }
+        (&Method::POST, "/purge") => {
+            info!("purging caches");
+            config.vfs.purge();
+            config.renders.purge();
+            config.archives.purge();
+            if let Some(digests) = &config.digests {
+                digests.purge();
+            }
+            done()
+        }
+        (&Method::POST, "/maintenance/on") => {
+            maintenance::set_enabled(true);
+            done()
+        }
+        (&Method::POST, "/maintenance/off") => {
+            maintenance::set_enabled(false);
+            done()
+        }
+        (&Method::POST, "/shutdown") => {
+            info!("shutdown requested through the admin API");
+            SHUTDOWN.notify_one();
+            done()
+        }
+        (_, "/stats")
+        | (_, "/purge")
+        | (_, "/maintenance/on")
+        | (_, "/maintenance/off")
+        | (_, "/shutdown") => error(StatusCode::METHOD_NOT_ALLOWED),
+        _ => error(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Whether the request's `Host` is `localhost` or a loopback address
+fn has_loopback_host<B>(req: &Request<B>) -> bool {
+    let host = req.headers().get(header::HOST);
+    let host = match host.and_then(|host| host.to_str().ok()) {
+        Some(host) => host,
+        None => return false,
+    };
+    // Leave out the port, minding the colons of IPv6 addresses in brackets
+    let name = match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Whether the request has a body of a type an HTML form can send, which
+/// browsers send to other origins without asking
+fn has_form_body<B>(req: &Request<B>) -> bool {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok());
+    match content_type {
+        Some(mime) => matches!(
+            (mime.type_(), mime.subtype()),
+            (mime::APPLICATION, mime::WWW_FORM_URLENCODED)
+                | (mime::MULTIPART, mime::FORM_DATA)
+                | (mime::TEXT, mime::PLAIN)
+        ),
+        None => false,
+    }
+}
+
+/// Whether the request has the token, compared without stopping at the
+/// first difference, so that the time taken doesn't give it away
+fn has_token<B>(req: &Request<B>, token: &str) -> bool {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,48 @@
+//! Allowing or refusing requests by their `User-Agent`, with
+//! `--allow-user-agent` and `--deny-user-agent`, to keep out scrapers or to
+//! only let in a test client. Requests without the header are matched as if
+//! it were empty.
+
+use crate::{Error, Result};
+use http::{header, Request};
+use regex::Regex;
+
+#[derive(Clone, Default)]
+pub struct Rules {
+    /// If there are any, the user agent must match one of these
+    allow: Vec<Regex>,
+    /// The user agent must match none of these
+    deny: Vec<Regex>,
+}
+
+impl Rules {
+    pub fn new<'a>(
+        allow: impl Iterator<Item = &'a str>,
+        deny: impl Iterator<Item = &'a str>,
+    ) -> Result<Rules> {
+        let compile = |pattern| Regex::new(pattern).map_err(Error::UserAgentRegex);
+        Ok(Rules {
+            allow: allow.map(compile).collect::<Result<_>>()?,
+            deny: deny.map(compile).collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether the rules let the request's user agent in
+    pub fn allows<B>(&self, req: &Request<B>) -> bool {
+        let agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let allowed = (self.allow.is_empty() || self.allow.iter().any(|re| re.is_match(agent)))
+            && !self.deny.iter().any(|re| re.is_match(agent));
+        if !allowed {
+            info!("refused request from user agent {:?}", agent);
+        }
+        allowed
+    }
+}
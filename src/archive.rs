@@ -0,0 +1,300 @@
+//! Zip and tar archives, for serving an archive as the root directory, and for
+//! browsing inside archives with the developer extensions.
+
+use crate::vfs::{self, MemoryFs, Metadata, Vfs, VfsFile, VfsFuture};
+use crate::{Error, Result};
+use flate2::read::GzDecoder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Bundles made by the `bundle` subcommand are the server executable followed
+/// by a tar file, the tar file's length, and then this.
+pub const BUNDLE_MAGIC: &[u8; 8] = b"bhsbundl";
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Kind {
+    fn from_path(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the file name looks like an archive that can be served.
+pub fn is_archive(path: &Path) -> bool {
+    Kind::from_path(path).is_some()
+}
+
+/// Open an archive as a filesystem whose root directory is the archive's
+/// path. The kind of archive is decided by its extension.
+///
+/// Zip and tar files are indexed, and their files read from disk when
+/// requested, zip files through the central directory read when opening
+/// them. Compressed tar files can't be seeked in, so their files are
+/// read into memory up front.
+pub fn open(path: &Path) -> Result<Arc<dyn Vfs>> {
+    let kind = Kind::from_path(path).ok_or(Error::UnknownArchive)?;
+    let file = File::open(path)?;
+    let modified = file.metadata()?.modified().ok();
+    let file = BufReader::new(file);
+    let vfs: Arc<dyn Vfs> = match kind {
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(Error::Zip)?;
+            Arc::new(Archive {
+                path: path.to_owned(),
+                files: zip_index(&mut zip)?,
+                zip: Some(Arc::new(Mutex::new(zip))),
+                modified,
+            })
+        }
+        Kind::Tar => Arc::new(Archive {
+            path: path.to_owned(),
+            files: tar_index(tar::Archive::new(file), 0)?,
+            zip: None,
+            modified,
+        }),
+        Kind::TarGz => {
+            let mut fs = MemoryFs::new(path, modified.unwrap_or_else(SystemTime::now));
+            let mut tar = tar::Archive::new(GzDecoder::new(file));
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                if let Some(name) = entry_name(&entry)? {
+                    let mut buf = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut buf)?;
+                    fs.insert(Path::new(&name), buf);
+                }
+            }
+            Arc::new(fs)
+        }
+    };
+    Ok(vfs)
+}
+
+/// Open the tar file embedded in a bundle, or `None` if the file isn't a
+/// bundle.
+pub fn open_bundle(path: &Path) -> Result<Option<Arc<dyn Vfs>>> {
+    let mut file = File::open(path)?;
+    let modified = file.metadata()?.modified().ok();
+    let (start, len) = match bundled_tar(&mut file)? {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+    file.seek(SeekFrom::Start(start))?;
+    let tar = tar::Archive::new(BufReader::new(file.take(len)));
+    Ok(Some(Arc::new(Archive {
+        path: path.to_owned(),
+        files: tar_index(tar, start)?,
+        zip: None,
+        modified,
+    })))
+}
+
+/// Find the start and length of the tar file embedded in a bundle.
+pub fn bundled_tar(file: &mut File) -> io::Result<Option<(u64, u64)>> {
+    let file_len = file.metadata()?.len();
+    if file_len < 16 {
+        return Ok(None);
+    }
+    let mut trailer = [0; 16];
+    file.seek(SeekFrom::End(-16))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != BUNDLE_MAGIC {
+        return Ok(None);
+    }
+    let mut len = [0; 8];
+    len.copy_from_slice(&trailer[..8]);
+    let len = u64::from_le_bytes(len);
+    match (file_len - 16).checked_sub(len) {
+        Some(start) => Ok(Some((start, len))),
+        None => Ok(None),
+    }
+}
+
+/// Where to find a file's contents, and its length
+#[derive(Clone, Copy)]
+enum Location {
+    /// The index of the entry in the zip file
+    Zip(usize, u64),
+    /// The offset of the entry's data in the tar file
+    Tar(u64, u64),
+}
+
+/// A zip or tar file, with an in-memory index of the files in it.
+struct Archive {
+    path: PathBuf,
+    files: BTreeMap<String, Location>,
+    /// The open zip file, if it is one
+    zip: Option<Arc<Mutex<ZipArchive>>>,
+    modified: Option<SystemTime>,
+}
+
+type ZipArchive = zip::ZipArchive<BufReader<File>>;
+
+impl Archive {
+    /// Get the name within the archive of a path under the archive's root.
+    fn name(&self, path: &Path) -> Option<String> {
+        let names = path
+            .strip_prefix(&self.path)
+            .ok()?
+            .components()
+            .map(|c| match c {
+                Component::Normal(c) => c.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(names.join("/"))
+    }
+
+    /// Directories are implied by the files within them, and the empty name
+    /// is the root directory.
+    fn is_dir(&self, name: &str) -> bool {
+        name.is_empty() || !self.children(name).is_empty()
+    }
+
+    /// The names of the files and directories directly within a directory.
+    fn children(&self, name: &str) -> BTreeSet<&str> {
+        let dir = if name.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", name)
+        };
+        self.files
+            .range::<str, _>((Bound::Included(dir.as_str()), Bound::Unbounded))
+            .map(|(n, _)| n.as_str())
+            .take_while(|n| n.starts_with(&dir))
+            .filter_map(|n| n[dir.len()..].split('/').next())
+            .collect()
+    }
+}
+
+impl Vfs for Archive {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        Box::pin(async move {
+            let location = self
+                .name(path)
+                .and_then(|n| self.files.get(&n).copied())
+                .ok_or_else(vfs::not_found)?;
+            let path = self.path.clone();
+            let zip = self.zip.clone();
+            let buf = crate::blocking(move || read_location(&path, zip, location)).await?;
+            Ok(Box::new(Cursor::new(buf)) as VfsFile)
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        let name = match self.name(path) {
+            Some(name) => name,
+            None => return Box::pin(async { Err(vfs::not_found()) }),
+        };
+        let metadata = match self.files.get(&name) {
+            Some(Location::Zip(_, len)) | Some(Location::Tar(_, len)) => Ok(Metadata {
+                is_dir: false,
+                len: *len,
+                modified: self.modified,
+            }),
+            None if self.is_dir(&name) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: self.modified,
+            }),
+            None => Err(vfs::not_found()),
+        };
+        Box::pin(async { metadata })
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        let entries = match self.name(path) {
+            Some(name) if self.is_dir(&name) => {
+                let dir = self.path.join(&name);
+                Ok(self.children(&name).iter().map(|c| dir.join(c)).collect())
+            }
+            _ => Err(vfs::not_found()),
+        };
+        Box::pin(async { entries })
+    }
+}
+
+fn read_location(
+    path: &Path,
+    zip: Option<Arc<Mutex<ZipArchive>>>,
+    location: Location,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match location {
+        Location::Zip(i, _) => {
+            let zip = zip.expect("zip file entries are in zip files");
+            let mut zip = zip.lock().unwrap();
+            let mut entry = zip.by_index(i).map_err(Error::Zip)?;
+            entry.read_to_end(&mut buf)?;
+        }
+        Location::Tar(offset, len) => {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(len).read_to_end(&mut buf)?;
+        }
+    }
+    Ok(buf)
+}
+
+fn zip_index(zip: &mut ZipArchive) -> Result<BTreeMap<String, Location>> {
+    let mut files = BTreeMap::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index(i).map_err(Error::Zip)?;
+        if entry.is_file() {
+            let name = entry.name().map_err(Error::Zip)?;
+            let location = Location::Zip(i, entry.size());
+            files.insert(normalize(&name).to_string(), location);
+        }
+    }
+    Ok(files)
+}
+
+/// Index a tar file that starts at `offset` in the archive file.
+fn tar_index<R: Read>(mut tar: tar::Archive<R>, offset: u64) -> Result<BTreeMap<String, Location>> {
+    let mut files = BTreeMap::new();
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if let Some(name) = entry_name(&entry)? {
+            let location = Location::Tar(offset + entry.raw_file_position(), entry.size());
+            files.insert(name, location);
+        }
+    }
+    Ok(files)
+}
+
+/// Get the name of a file in a tar file, or `None` if the entry isn't a file
+/// that can be served.
+fn entry_name<R: Read>(entry: &tar::Entry<R>) -> Result<Option<String>> {
+    if !entry.header().entry_type().is_file() {
+        return Ok(None);
+    }
+    match entry.path()?.to_str() {
+        Some(name) => Ok(Some(normalize(name).to_string())),
+        None => {
+            warn!("non-unicode path in archive: {}", entry.path()?.display());
+            Ok(None)
+        }
+    }
+}
+
+fn normalize(name: &str) -> &str {
+    name.trim_start_matches("./").trim_start_matches('/')
+}
@@ -0,0 +1,293 @@
+//! The arguments for serving, which are given to the server with or without
+//! `serve`, and to `check`, `gen-config` and `bench`, and most of which can
+//! be kept in a config file.
+//!
+//! They're listed once here, and both the clap arguments and the keys of
+//! config files are made from the list, so that clap's internals never have
+//! to be looked into to find out what its arguments are.
+
+use clap::Arg;
+
+/// An argument for serving
+pub struct ServeArg {
+    /// The name it's looked up by in matches
+    pub name: &'static str,
+    /// Its key in config files, which is also its long name, but for the
+    /// root dir, which is positional
+    pub key: &'static str,
+    short: Option<char>,
+    pub kind: Kind,
+    /// What its value is called in `--help`
+    pub value_name: &'static str,
+    pub help: &'static str,
+    /// The only values it takes, if not any
+    possible_values: &'static [&'static str],
+    /// Whether it may be given without a value, so that one must follow "="
+    optional_value: bool,
+    /// Whether a repeated option's values are split on commas, as well as
+    /// given separately
+    delimited: bool,
+    conflicts_with: &'static [&'static str],
+    /// Whether it can be given in a config file
+    pub configurable: bool,
+}
+
+pub enum Kind {
+    Flag,
+    /// A flag that counts how often it's given, like `-vv`
+    Count,
+    /// An option, which may be repeated
+    Option {
+        multiple: bool,
+    },
+    /// The root dir
+    Positional,
+}
+
+const fn arg(
+    key: &'static str,
+    value_name: &'static str,
+    help: &'static str,
+    kind: Kind,
+) -> ServeArg {
+    ServeArg {
+        name: key,
+        key,
+        short: None,
+        kind,
+        value_name,
+        help,
+        possible_values: &[],
+        optional_value: false,
+        delimited: true,
+        conflicts_with: &[],
+        configurable: true,
+    }
+}
+
+const fn flag(key: &'static str, help: &'static str) -> ServeArg {
+    arg(key, "", help, Kind::Flag)
+}
+
+const fn count(key: &'static str, help: &'static str) -> ServeArg {
+    arg(key, "", help, Kind::Count)
+}
+
+const fn option(key: &'static str, value_name: &'static str, help: &'static str) -> ServeArg {
+    arg(key, value_name, help, Kind::Option { multiple: false })
+}
+
+const fn multiple(key: &'static str, value_name: &'static str, help: &'static str) -> ServeArg {
+    arg(key, value_name, help, Kind::Option { multiple: true })
+}
+
+/// The root dir, named in matches by its value name
+const fn positional(key: &'static str, value_name: &'static str, help: &'static str) -> ServeArg {
+    ServeArg {
+        name: value_name,
+        ..arg(key, value_name, help, Kind::Positional)
+    }
+}
+
+impl ServeArg {
+    const fn named(self, name: &'static str) -> ServeArg {
+        ServeArg { name, ..self }
+    }
+
+    const fn short(self, short: char) -> ServeArg {
+        ServeArg {
+            short: Some(short),
+            ..self
+        }
+    }
+
+    const fn possible_values(self, possible_values: &'static [&'static str]) -> ServeArg {
+        ServeArg {
+            possible_values,
+            ..self
+        }
+    }
+
+    /// Let the value be left out, as for `--open`, which may be given a
+    /// path, but only with "=", so that it doesn't take the root dir
+    const fn optional_value(self) -> ServeArg {
+        ServeArg {
+            optional_value: true,
+            ..self
+        }
+    }
+
+    /// Take each value whole, as for values that may have commas in them,
+    /// like `--route="/docs ext=markdown,highlight"`
+    const fn undelimited(self) -> ServeArg {
+        ServeArg {
+            delimited: false,
+            ..self
+        }
+    }
+
+    const fn conflicts_with(self, conflicts_with: &'static [&'static str]) -> ServeArg {
+        ServeArg {
+            conflicts_with,
+            ..self
+        }
+    }
+
+    /// Keep it out of config files, as it only makes sense on the command
+    /// line
+    const fn not_configurable(self) -> ServeArg {
+        ServeArg {
+            configurable: false,
+            ..self
+        }
+    }
+
+    /// The clap argument
+    pub fn to_clap(&self) -> Arg<'static, 'static> {
+        let mut arg = Arg::with_name(self.name).help(self.help);
+        if let Some(short) = self.short {
+            arg = arg.short(short.to_string());
+        }
+        match self.kind {
+            Kind::Flag => arg = arg.long(self.key),
+            Kind::Count => arg = arg.long(self.key).multiple(true),
+            Kind::Option { multiple } => {
+                arg = arg
+                    .long(self.key)
+                    .takes_value(true)
+                    .value_name(self.value_name);
+                // Each value is given with the option, so that repeating it
+                // doesn't swallow the root dir
+                if multiple {
+                    arg = arg
+                        .multiple(true)
+                        .use_delimiter(self.delimited)
+                        .number_of_values(1);
+                }
+            }
+            Kind::Positional => {}
+        }
+        if !self.possible_values.is_empty() {
+            arg = arg.possible_values(self.possible_values);
+        }
+        if self.optional_value {
+            arg = arg.min_values(0).require_equals(true);
+        }
+        if !self.conflicts_with.is_empty() {
+            arg = arg.conflicts_with_all(self.conflicts_with);
+        }
+        arg
+    }
+}
+
+/// The arguments that write files, which `--read-only` refuses
+const WRITING_ARGS: &[&str] = &[
+    "addr-file",
+    "info-file",
+    "record",
+    "summary-json",
+    "compress-cache-dir",
+    #[cfg(unix)]
+    "pid-file",
+    #[cfg(unix)]
+    "log-file",
+];
+
+/// The arguments for serving, in the order they're listed in config files
+pub static SERVE_ARGS: &[ServeArg] = &[
+    positional("root", "ROOT", "Sets the root dir or archive, or a file to serve for every request (default \".\")"),
+    flag("extensions", "Enable developer extensions").named("EXT").short('x'),
+    option("ext", "EXTENSIONS", "Enables these comma-separated extensions: markdown, listing, gallery, playlist, highlight, json, table, resize, hexdump and archive"),
+    option("config", "FILE", "Reads arguments from this TOML file, which those given here override").not_configurable(),
+    flag("check", "Checks the arguments and the files they name, and exits without serving").not_configurable(),
+    flag("gallery", "Show directories of mostly images as a gallery (with -x)").named("GALLERY"),
+    option("admin-port", "PORT", "Serves the admin API on this port on the loopback address"),
+    option("admin-token", "TOKEN", "Sets the token the admin API needs for changes, instead of a random one"),
+    flag("quiet", "Only logs errors").short('q'),
+    count("verbose", "Prints a line for each request, and with -vv, debug logging too").short('v'),
+    flag("tui", "Shows a dashboard of live traffic in the terminal instead of logging"),
+    option("mock", "DIR", "Serves the mock API routes defined by the JSON and YAML fixtures in DIR"),
+    option("record", "FILE", "Appends each request to FILE as a line of JSON, to send again with replay"),
+    flag("record-responses", "Records responses, with their bodies, along with requests"),
+    option("mirror", "URL", "Sends a copy of each request to this http:// URL in the background"),
+    flag("wasm-dev", "Serves for WebAssembly development, with caching off, cross-origin isolation, and index.html for missing pages"),
+    flag("case-insensitive", "Redirects paths that are not found to files whose names differ only in case"),
+    option("auto-refresh", "DURATION", "Reloads HTML pages in the browser this often, like 2s or 500ms"),
+    flag("stdin", "Serves standard input at /, instead of the root dir"),
+    option("content-type", "TYPE", "Sets the content type of --stdin (default HTML, text or binary, by its content)"),
+    flag("once", "Exits once --stdin has been downloaded"),
+    flag("hit-counters", "Serves the number of times each file has been downloaded at /__stats"),
+    flag("summary", "Prints a summary of the requests served on exit"),
+    option("summary-json", "FILE", "Writes a summary of the requests served on exit to FILE as JSON"),
+    option("exit-after", "DURATION", "Exits after this long, like 30s or 5m"),
+    option("exit-after-requests", "N", "Exits once N requests have been answered"),
+    flag("maintenance", "Starts in maintenance mode, answering every request with a 503"),
+    option("maintenance-page", "PATH", "Serves this HTML page in maintenance mode"),
+    option("template-dir", "DIR", "Renders generated pages with page.hbs and partials from DIR, reloaded when they change"),
+    option("addr-file", "PATH", "Writes the addresses listened on to this file, as JSON"),
+    option("info-file", "PATH", "Writes what --print-info prints to this file instead, implying --print-info=json"),
+    option("port-fallback", "N", "Tries up to N following ports if the port is in use"),
+    flag("qr", "Prints a QR code of the URL for other devices on the network"),
+    flag("reuseport", "Binds with SO_REUSEPORT, so other processes can share the port"),
+    flag("nodelay", "Sets TCP_NODELAY on connections"),
+    flag("proxy-protocol", "Reads the client address from a PROXY protocol header on each connection"),
+    flag("request-ids", "Gives each request an ID, sent in X-Request-Id and added to request lines and the request log, keeping the one a trusted proxy sent"),
+    option("allowed-hosts", "HOSTS", "Only serves requests for these comma-separated hosts, and subdomains of those starting with a dot"),
+    option("metadata-ttl", "SECS", "Caches file metadata for this many seconds"),
+    option("cache-size", "MB", "Caches the contents of small files in this much memory"),
+    option("cache-file-size", "KB", "Caches files up to this size when caching (default 64)"),
+    option("default-language", "LANG", "Serves FILE.LANG variants of missing files by Accept-Language, or in LANG by default"),
+    option("lang", "LANG", "Shows error pages and listings in LANG, one of en, de, es, fr and pt, instead of by Accept-Language"),
+    flag("digest", "Sends SHA-256 and MD5 digests of files, and serves FILE.sha256 checksums"),
+    option("mmap-threshold", "KB", "Maps files of at least this size into memory to serve them, crashing if one is truncated meanwhile"),
+    flag("compress", "Compresses text responses with gzip or deflate, for clients that accept them"),
+    option("compress-min-size", "BYTES", "Compresses responses of at least this size (default 1024), implying --compress"),
+    option("compress-types", "TYPES", "Compresses these comma-separated types, like text/*,application/json (default text, JSON, JavaScript, XML, SVG and wasm), implying --compress"),
+    option("no-compress-types", "TYPES", "Never compresses these comma-separated types, implying --compress"),
+    option("compress-cache", "MB", "Caches compressed files in this much memory, implying --compress"),
+    option("compress-cache-dir", "DIR", "Caches compressed files in DIR, implying --compress"),
+    option("threads", "N", "Sets the number of server threads (default one per core)"),
+    option("blocking-threads", "N", "Sets the most threads for file reads and other blocking work"),
+    option("acceptors", "N", "Accepts connections on this many sockets, bound with SO_REUSEPORT"),
+    option("backlog", "N", "Sets the most connections waiting to be accepted (default 128)"),
+    option("send-buffer", "KB", "Sets the socket send buffer size"),
+    option("recv-buffer", "KB", "Sets the socket receive buffer size"),
+    multiple("addr", "ADDR", "Sets the IP:PORT, IP, :PORT or HOST:PORT to listen on, or unix:PATH for a unix socket, and may be repeated (default \"127.0.0.1:4000\")").named("ADDR").short('a'),
+    flag("doc", "Serves the docs built by cargo doc for the workspace here, and opens them").conflicts_with(&["ROOT"]),
+    flag("read-only", "Refuses the arguments that write files, and on Linux, forbids writing any").conflicts_with(WRITING_ARGS),
+    option("open", "PATH", "Opens the site, or PATH on it, in the browser").optional_value(),
+    multiple("trusted-proxy", "CIDR", "Takes the client address from Forwarded headers on requests from these addresses, and may be repeated"),
+    multiple("inject-script", "FILE", "Injects this script into HTML pages, before </body>, and may be repeated").undelimited(),
+    multiple("inject-css", "FILE", "Injects this stylesheet into HTML pages, before </head>, and may be repeated").undelimited(),
+    multiple("allow-user-agent", "REGEX", "Only serves user agents matching one of these, and may be repeated").undelimited(),
+    multiple("deny-user-agent", "REGEX", "Refuses user agents matching any of these, and may be repeated").undelimited(),
+    option("print-info", "FORMAT", "Prints the addresses listened on, the PID and the arguments, once listening, in this format, which is json").possible_values(&["json"]),
+    option("etag", "STRATEGY", "Sends entity tags of files, from their modification time and size, or with \"strong\", from their contents").possible_values(&["off", "weak", "strong"]),
+    option("robots", "POLICY", "Serves a robots.txt denying crawlers, or allowing them with \"allow\", if the site has none").possible_values(&["deny", "allow"]).optional_value(),
+    multiple("webhook", "WEBHOOK", "Posts JSON about the server starting and stopping, and paths not found, to this http:// URL, or only about the events before it, as in started,not-found=URL, and may be repeated").undelimited(),
+    multiple("geoip", "FILE", "Adds the country and network of clients to request lines, the request log and stats, from this MaxMind database, and may be repeated").undelimited(),
+    option("log-format", "FORMAT", "Prints request lines in this format, like $remote_addr $status $request_time, or common or combined, implying -v"),
+    multiple("route", "ROUTE", "Uses other settings under a URL path, as in \"/docs ext=markdown,highlight no-cache\", and may be repeated").undelimited(),
+    multiple("preload", "GLOB", "Reads the files matching GLOB, like **/*.js, into the caches before serving, and may be repeated").undelimited(),
+    multiple("compress-level", "LEVEL", "Compresses at this level from 0 to 9 (default 6), or at a level for one algorithm like gzip=9, implying --compress, and may be repeated"),
+    option("request-log", "N", "Keeps the last N requests (default 200) in memory, and serves them at /__log").optional_value(),
+    option("feed", "DIR", "Serves an Atom feed of the files in this directory at /feed.xml (default the root)").optional_value(),
+    multiple("no-listings", "PREFIX", "Forbids listing directories under this URL path, which may be repeated (default every directory)").optional_value().undelimited(),
+    option("mdns", "NAME", "Announces the server on the network with mDNS (default name \"basic-http-server\")").optional_value(),
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    flag("io-uring", "Reads files with io_uring"),
+    #[cfg(unix)]
+    option("user", "USER", "Switches to this user once listening, to bind privileged ports as root"),
+    #[cfg(unix)]
+    option("group", "GROUP", "Switches to this group once listening (default the user's group)"),
+    #[cfg(unix)]
+    flag("daemon", "Runs in the background, detached from the terminal"),
+    #[cfg(unix)]
+    option("pid-file", "PATH", "Writes the process ID to this file"),
+    #[cfg(unix)]
+    flag("exit-with-parent", "Exits when the process that started the server exits"),
+    #[cfg(unix)]
+    option("log-file", "PATH", "Appends log output to this file"),
+    #[cfg(target_os = "linux")]
+    flag("sandbox", "Confines the server to reading the root, with Landlock"),
+];
@@ -0,0 +1,201 @@
+//! The `bench` subcommand, which sends requests to a server as fast as it
+//! answers them, over a number of connections at once, and reports how many
+//! it answered a second, and how long they took. Without a URL, it measures
+//! this server, serving the root dir with the arguments it's given, on a
+//! free port, so that changes to the server can be measured without other
+//! tools. The server and the requests to it then share the machine, so the
+//! numbers are for comparing with each other, more than with other servers.
+
+use crate::client::{Connection, Target};
+use crate::listen::Listener;
+use crate::{Body, Config, Error, Result};
+use clap::ArgMatches;
+use http::{Request, StatusCode};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+pub struct Options {
+    /// The server to send requests to, or none for this one
+    target: Option<Target>,
+    /// The path and query to request
+    path: String,
+    /// The number of connections to send requests on at once
+    concurrency: usize,
+    limit: Limit,
+}
+
+/// When to stop sending requests
+#[derive(Clone, Copy)]
+enum Limit {
+    Requests(u64),
+    Duration(Duration),
+}
+
+/// What one connection's requests came to
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, u64>,
+    bytes: u64,
+    errors: u64,
+}
+
+impl Options {
+    pub fn parse(matches: &ArgMatches) -> Result<Options> {
+        let url = matches.value_of("url").unwrap_or("/");
+        let (target, path) = if url.starts_with('/') {
+            (None, url.to_owned())
+        } else {
+            let target = Target::parse(url).ok_or_else(|| Error::BenchTarget(url.to_owned()))?;
+            let uri: http::Uri = url.parse().map_err(http::Error::from)?;
+            let path = uri.path_and_query().map_or("/", |pq| pq.as_str());
+            (Some(target), path.to_owned())
+        };
+        let concurrency = match matches.value_of("concurrency") {
+            Some(n) => n.parse().map_err(Error::ConcurrencyParse)?,
+            None => 10,
+        };
+        let limit = match (matches.value_of("duration"), matches.value_of("requests")) {
+            (Some(duration), _) => Limit::Duration(
+                crate::parse_duration(duration)
+                    .ok_or_else(|| Error::DurationParse(duration.to_owned()))?,
+            ),
+            (None, Some(n)) => Limit::Requests(n.parse().map_err(Error::RequestsParse)?),
+            (None, None) => Limit::Requests(1000),
+        };
+        Ok(Options {
+            target,
+            path,
+            concurrency: usize::max(concurrency, 1),
+            limit,
+        })
+    }
+}
+
+pub fn run(config: Config, options: Options) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let target = match &options.target {
+            Some(target) => target.clone(),
+            None => {
+                let listener = TcpListener::bind("127.0.0.1:0").await?;
+                let addr = listener.local_addr()?;
+                crate::prepare(&config)?;
+                if !config.preload.is_empty() {
+                    crate::preload::preload(&config).await;
+                }
+                info!("serving {} at http://{}", config.root_dir.display(), addr);
+                tokio::spawn(crate::accept_loop(Listener::Tcp(listener), config));
+                Target::parse(&format!("http://{}", addr)).expect("valid URL")
+            }
+        };
+
+        let sent = Arc::new(AtomicU64::new(0));
+        let started = Instant::now();
+        let workers: Vec<_> = (0..options.concurrency)
+            .map(|_| {
+                let target = target.clone();
+                let path = options.path.clone();
+                let sent = sent.clone();
+                tokio::spawn(send_requests(target, path, options.limit, sent, started))
+            })
+            .collect();
+        let mut results = Results::default();
+        for worker in workers {
+            let worker = worker.await.expect("benchmark task")?;
+            results.latencies.extend(worker.latencies);
+            results.bytes += worker.bytes;
+            results.errors += worker.errors;
+            for (status, count) in worker.statuses {
+                *results.statuses.entry(status).or_default() += count;
+            }
+        }
+        report(&mut results, started.elapsed());
+        Ok(())
+    })
+}
+
+/// Send requests on one connection until there are enough, opening another
+/// if the server closes it
+async fn send_requests(
+    target: Target,
+    path: String,
+    limit: Limit,
+    sent: Arc<AtomicU64>,
+    started: Instant,
+) -> Result<Results> {
+    let mut results = Results::default();
+    let mut conn: Option<Connection> = None;
+    loop {
+        let done = match limit {
+            Limit::Requests(n) => sent.fetch_add(1, Ordering::Relaxed) >= n,
+            Limit::Duration(duration) => started.elapsed() >= duration,
+        };
+        if done {
+            return Ok(results);
+        }
+        let connection = match &mut conn {
+            Some(conn) => conn,
+            None => conn.insert(target.connect().await?),
+        };
+        let req = Request::get(&path).body(Body::default())?;
+        let request_started = Instant::now();
+        match connection.send(req).await {
+            Ok((status, bytes)) => {
+                results.latencies.push(request_started.elapsed());
+                *results.statuses.entry(status.as_u16()).or_default() += 1;
+                results.bytes += bytes as u64;
+            }
+            Err(e) => {
+                debug!("request failed: {}", e);
+                results.errors += 1;
+                conn = None;
+            }
+        }
+    }
+}
+
+fn report(results: &mut Results, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    let answered = results.latencies.len();
+    println!(
+        "requests:    {} in {:.2} s, {:.1}/s",
+        answered,
+        secs,
+        answered as f64 / secs
+    );
+    println!(
+        "transferred: {}, {}/s",
+        crate::stats::format_bytes(results.bytes as f64),
+        crate::stats::format_bytes(results.bytes as f64 / secs)
+    );
+    let statuses: Vec<_> = results
+        .statuses
+        .iter()
+        .map(|(&status, count)| {
+            let status = StatusCode::from_u16(status).map_or(status.to_string(), |s| s.to_string());
+            format!("{} x {}", status, count)
+        })
+        .collect();
+    println!("statuses:    {}", statuses.join(", "));
+    println!("errors:      {}", results.errors);
+    if answered == 0 {
+        return;
+    }
+    results.latencies.sort();
+    let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    let percentile = |p: f64| ms(results.latencies[((answered - 1) as f64 * p).round() as usize]);
+    println!(
+        "latency:     min {:.2} ms, p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, max {:.2} ms",
+        ms(results.latencies[0]),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        ms(results.latencies[answered - 1])
+    );
+}
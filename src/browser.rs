@@ -0,0 +1,52 @@
+//! Opening the served site in the user's browser, with `--open`.
+
+use crate::listen::ListenAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::Command;
+
+/// The URL of `path` on the first TCP address listened on. A server listening
+/// on all interfaces is opened on the loopback address.
+#[cfg_attr(not(unix), allow(clippy::unnecessary_find_map))]
+pub fn url(addrs: &[ListenAddr], path: &str) -> Option<String> {
+    let addr = addrs.iter().find_map(|addr| match addr {
+        ListenAddr::Tcp(addr) => Some(*addr),
+        #[cfg(unix)]
+        ListenAddr::Unix(_) => None,
+    })?;
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let slash = if path.starts_with('/') { "" } else { "/" };
+    Some(format!(
+        "http://{}{}{}",
+        SocketAddr::new(ip, addr.port()),
+        slash,
+        path
+    ))
+}
+
+/// Open the URL in the default browser, without waiting for it.
+pub fn open(url: &str) {
+    info!("opening {}", url);
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        // The empty argument is the window title, which `start` would
+        // otherwise take from the quoted URL
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    match command.arg(url).spawn() {
+        // Reap the opener once it exits, which it does when the browser has
+        // the URL
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("failed to open browser: {}", e),
+    }
+}
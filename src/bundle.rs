@@ -0,0 +1,58 @@
+//! The `bundle` subcommand, which makes a copy of the server with a directory
+//! embedded in it, so that a whole site can be shared as a single executable.
+//!
+//! The directory is appended to the executable as a tar file. When a bundle
+//! runs it finds the tar file at its own end, and serves it like any other
+//! archive.
+
+use crate::archive::{self, BUNDLE_MAGIC};
+use crate::Result;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+pub fn bundle(dir: &Path, output: &Path) -> Result<()> {
+    let exe_path = env::current_exe()?;
+    let mut exe = File::open(&exe_path)?;
+
+    // If this is already a bundle, only copy the server itself
+    let exe_len = match archive::bundled_tar(&mut exe)? {
+        Some((start, _)) => start,
+        None => exe.metadata()?.len(),
+    };
+    exe.rewind()?;
+
+    let mut out = File::create(output)?;
+    io::copy(&mut exe.take(exe_len), &mut out)?;
+
+    let mut builder = tar::Builder::new(out);
+    builder.mode(tar::HeaderMode::Deterministic);
+    builder.append_dir_all(".", dir)?;
+    let mut out = builder.into_inner()?;
+
+    let tar_len = out.stream_position()? - exe_len;
+    out.write_all(&tar_len.to_le_bytes())?;
+    out.write_all(BUNDLE_MAGIC)?;
+    set_executable(&out)?;
+
+    info!(
+        "bundled {} into {} ({} bytes)",
+        dir.display(),
+        output.display(),
+        exe_len + tar_len + 16
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_executable(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_file: &File) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! Caching in front of a filesystem, for serving the same files at high
+//! request rates.
+
+use crate::vfs::{self, Metadata, Vfs, VfsFile, VfsFuture};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The cache is emptied when it holds more entries than this.
+const MAX_METADATA_ENTRIES: usize = 16 * 1024;
+
+/// Remembers file metadata for a while, so that requests don't each need to
+/// `stat` the file. Changes to a file aren't noticed until its entry expires.
+pub struct MetadataCache {
+    inner: Arc<dyn Vfs>,
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, (Instant, Metadata)>>,
+}
+
+impl MetadataCache {
+    pub fn new(inner: Arc<dyn Vfs>, ttl: Duration) -> MetadataCache {
+        MetadataCache {
+            inner,
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    fn get(&self, path: &Path) -> Option<Metadata> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some((expires, metadata)) if *expires > Instant::now() => Some(metadata.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Vfs for MetadataCache {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        self.inner.open(path)
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        Box::pin(async move {
+            if let Some(metadata) = self.get(path) {
+                trace!("metadata cache hit: {}", path.display());
+                return Ok(metadata);
+            }
+
+            let expires = Instant::now() + self.ttl;
+            let metadata = self.inner.metadata(path).await?;
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= MAX_METADATA_ENTRIES {
+                debug!("metadata cache full, clearing");
+                entries.clear();
+            }
+            entries.insert(path.to_owned(), (expires, metadata.clone()));
+            Ok(metadata)
+        })
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+
+    fn purge(&self) {
+        self.entries.lock().unwrap().clear();
+        self.inner.purge();
+    }
+}
+
+/// Keeps the contents of small files in memory, evicting the least recently
+/// used files when full. Files are looked up by path and modification time,
+/// so a changed file is read afresh.
+pub struct ContentCache {
+    inner: Arc<dyn Vfs>,
+    max_file_len: u64,
+    lru: Mutex<Lru>,
+}
+
+struct Lru {
+    files: HashMap<PathBuf, CachedFile>,
+    /// The files' paths in order of last use, keyed by `clock` when used
+    used: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    len: usize,
+    max_len: usize,
+}
+
+struct CachedFile {
+    modified: Option<SystemTime>,
+    data: Arc<[u8]>,
+    last_used: u64,
+}
+
+impl ContentCache {
+    /// Cache files of up to `max_file_len` bytes, and up to `max_len` bytes
+    /// in total.
+    pub fn new(inner: Arc<dyn Vfs>, max_len: usize, max_file_len: u64) -> ContentCache {
+        ContentCache {
+            inner,
+            max_file_len,
+            lru: Mutex::new(Lru {
+                files: HashMap::new(),
+                used: BTreeMap::new(),
+                clock: 0,
+                len: 0,
+                max_len,
+            }),
+        }
+    }
+}
+
+impl Lru {
+    fn get(&mut self, path: &Path, modified: Option<SystemTime>) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let file = self.files.get_mut(path)?;
+        if file.modified != modified {
+            return None;
+        }
+        self.used.remove(&file.last_used);
+        self.used.insert(self.clock, path.to_owned());
+        file.last_used = self.clock;
+        Some(file.data.clone())
+    }
+
+    fn insert(&mut self, path: PathBuf, modified: Option<SystemTime>, data: Arc<[u8]>) {
+        if data.len() > self.max_len {
+            return;
+        }
+        self.remove(&path);
+        while self.len + data.len() > self.max_len {
+            let oldest = match self.used.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            let path = self.used[&oldest].clone();
+            trace!("evicting from content cache: {}", path.display());
+            self.remove(&path);
+        }
+        self.clock += 1;
+        self.len += data.len();
+        self.used.insert(self.clock, path.clone());
+        self.files.insert(
+            path,
+            CachedFile {
+                modified,
+                data,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(file) = self.files.remove(path) {
+            self.used.remove(&file.last_used);
+            self.len -= file.data.len();
+        }
+    }
+}
+
+impl Vfs for ContentCache {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        Box::pin(async move {
+            let metadata = self.inner.metadata(path).await?;
+            if metadata.is_dir || metadata.len > self.max_file_len {
+                return self.inner.open(path).await;
+            }
+            let cached = self.lru.lock().unwrap().get(path, metadata.modified);
+            if let Some(data) = cached {
+                trace!("content cache hit: {}", path.display());
+                return Ok(Box::new(Cursor::new(data)) as VfsFile);
+            }
+            let data: Arc<[u8]> = vfs::read(&*self.inner, path).await?.into();
+            self.lru
+                .lock()
+                .unwrap()
+                .insert(path.to_owned(), metadata.modified, data.clone());
+            Ok(Box::new(Cursor::new(data)) as VfsFile)
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+
+    fn purge(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.files.clear();
+        lru.used.clear();
+        lru.len = 0;
+        drop(lru);
+        self.inner.purge();
+    }
+}
@@ -0,0 +1,81 @@
+//! Finding files whatever the case of the letters in the URL, with
+//! `--case-insensitive`, for sites written on Windows or macOS, whose
+//! filesystems ignore case, that link to `Logo.PNG` as `logo.png`. A request
+//! for a path that isn't found is redirected to the path with the case of
+//! the files on disk, so that each file has one URL.
+
+use crate::vfs::Vfs;
+use crate::{Body, Config, Error, Result};
+use http::{header, Response, StatusCode, Uri};
+use std::path::{Component, Path, PathBuf};
+
+/// A redirect to the path with the case of the files on disk, if the path
+/// of a request that wasn't found names files in another case
+pub async fn redirect(config: &Config, uri: &Uri) -> Result<Option<Response<Body>>> {
+    let path = match crate::local_path_for_request(uri, &config.root_dir) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let names = match path.strip_prefix(&config.root_dir) {
+        Ok(names) => names,
+        Err(_) => return Ok(None),
+    };
+    let mut dir = config.root_dir.clone();
+    let mut changed = false;
+    for name in names.components() {
+        let name = match name {
+            Component::Normal(name) => name,
+            _ => return Ok(None),
+        };
+        let found = match config.vfs.metadata(&dir.join(name)).await {
+            Ok(_) => dir.join(name),
+            Err(_) => match find(&*config.vfs, &dir, name.to_str().unwrap_or("")).await {
+                Some(found) => {
+                    changed = true;
+                    found
+                }
+                None => return Ok(None),
+            },
+        };
+        dir = found;
+    }
+    if !changed {
+        return Ok(None);
+    }
+
+    let mut location = String::new();
+    for name in dir.strip_prefix(&config.root_dir).unwrap_or(&dir).iter() {
+        location.push('/');
+        location.push_str(&crate::percent_encode(&name.to_string_lossy()));
+    }
+    if location.is_empty() || uri.path().ends_with('/') {
+        location.push('/');
+    }
+    if let Some(query) = uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    info!("redirecting {} to {}", uri, location);
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, location)
+        .body(Body::default())
+        .map(Some)
+        .map_err(Error::from)
+}
+
+/// The entry of a directory with a name, ignoring case. If there are
+/// several, like `README` and `readme`, there's no telling which is meant,
+/// so it's none of them.
+async fn find(vfs: &dyn Vfs, dir: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.to_lowercase();
+    let entries = vfs.read_dir(dir).await.ok()?;
+    let mut matches = entries.into_iter().filter(|entry| {
+        let entry_name = entry.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        entry_name.to_lowercase() == name
+    });
+    match (matches.next(), matches.next()) {
+        (Some(found), None) => Some(found),
+        _ => None,
+    }
+}
@@ -0,0 +1,219 @@
+//! Checking the configuration to serve with, with the `check` subcommand or
+//! `--check`, without binding any sockets, so that a deployment's
+//! configuration can be tried out first. Mistakes in the arguments themselves
+//! are found while parsing them; this reports on each of the files and
+//! directories they name, and finds every problem rather than the first.
+
+use crate::{Config, Error, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The results of the checks, as they're logged
+#[derive(Default)]
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn ok(&self, what: String) {
+        info!("ok: {}", what);
+    }
+
+    fn problem(&mut self, what: String) {
+        error!("problem: {}", what);
+        self.problems += 1;
+    }
+}
+
+pub fn check(config: &Config) -> Result<()> {
+    let mut report = Report::default();
+
+    let root = config.root_dir.display();
+    if config.stdin.is_some() {
+        report.ok("will serve standard input".to_string());
+    } else if config.single_file {
+        check_input(&mut report, "file", &config.root_dir);
+    } else if config.vfs.is_local() {
+        match fs::read_dir(&config.root_dir) {
+            Ok(entries) => report.ok(format!(
+                "root dir {} is readable, with {} entries",
+                root,
+                entries.count()
+            )),
+            Err(e) => report.problem(format!("root dir {} can't be read: {}", root, e)),
+        }
+    } else {
+        // Archives and bundles were opened while parsing the arguments
+        report.ok(format!("root archive {} was opened", root));
+    }
+
+    for addr in &config.addrs {
+        report.ok(format!("will listen on {}", addr));
+    }
+    if let Some(port) = config.admin_port {
+        report.ok(format!("will serve the admin API on port {}", port));
+    }
+    if !config.allowed_hosts.is_empty() {
+        report.ok(format!(
+            "will only serve the hosts {}",
+            config.allowed_hosts.join(", ")
+        ));
+    }
+    if !config.trusted_proxies.is_empty() {
+        report.ok(format!(
+            "will trust {} proxy address ranges",
+            config.trusted_proxies.len()
+        ));
+    }
+    if let Some(dir) = config
+        .compression
+        .as_ref()
+        .and_then(|compression| compression.cache_dir())
+    {
+        report.ok(format!("will cache compressed files in {}", dir.display()));
+    }
+    if let Some(request_log) = &config.request_log {
+        report.ok(format!(
+            "will keep the last {} requests at {}",
+            request_log.capacity(),
+            crate::request_log::PATH
+        ));
+    }
+    if let Some(geoip) = &config.geoip {
+        report.ok(format!(
+            "will look up clients in {} GeoIP databases",
+            geoip.len()
+        ));
+    }
+    if !config.preload.is_empty() {
+        report.ok(format!(
+            "will preload the files matching {}",
+            config.preload.join(", ")
+        ));
+    }
+    if config.request_ids {
+        report.ok("will give each request an ID".to_string());
+    }
+    if config.log_format.is_some() {
+        report.ok("will print request lines in the given format".to_string());
+    }
+    if !config.routes.is_empty() {
+        report.ok(format!("will use {} routes", config.routes.len()));
+    }
+    if !config.webhooks.is_empty() {
+        report.ok(format!("will post events to {} webhooks", config.webhooks.len()));
+    }
+
+    if let Some(dir) = &config.mock {
+        match crate::mock::check(dir) {
+            Ok(routes) => report.ok(format!("mock API {} has {} routes", dir.display(), routes)),
+            Err(e) => report.problem(format!("mock API {}: {}", dir.display(), e)),
+        }
+    }
+    if let Some(dir) = &config.feed {
+        let path = config.root_dir.join(dir);
+        if !config.vfs.is_local() {
+            report.ok(format!("will serve a feed of {}", dir));
+        } else if path.is_dir() {
+            report.ok(format!("will serve a feed of {}", path.display()));
+        } else {
+            report.problem(format!("feed directory {} doesn't exist", path.display()));
+        }
+    }
+    if config.read_only {
+        report.ok("won't write any files".to_string());
+    }
+    if let Some(after) = config.exit.after {
+        report.ok(format!("will exit after {:?}", after));
+    }
+    if let Some(requests) = config.exit.requests {
+        report.ok(format!("will exit after {} requests", requests));
+    }
+    #[cfg(unix)]
+    {
+        if config.exit.with_parent.is_some() {
+            report.ok("will exit when its parent process does".to_string());
+        }
+    }
+    if let Some(page) = &config.maintenance_page {
+        check_input(&mut report, "maintenance page", page);
+    }
+    match &config.no_listings {
+        Some(prefixes) if prefixes.is_empty() => report.ok("won't list directories".to_string()),
+        Some(prefixes) => report.ok(format!(
+            "won't list directories under {}",
+            prefixes.join(", ")
+        )),
+        None => {}
+    }
+    if let Some(dir) = &config.template_dir {
+        report.ok(format!(
+            "will render pages with the templates in {}",
+            dir.display()
+        ));
+    }
+    for path in config.injections.paths() {
+        check_input(&mut report, "injected file", path);
+    }
+
+    let outputs = [
+        ("address file", config.addr_file.as_deref()),
+        (
+            "info file",
+            config
+                .info
+                .as_ref()
+                .and_then(|info| info.file())
+                .map(PathBuf::as_path),
+        ),
+        ("summary file", config.summary_json.as_deref()),
+        (
+            "record file",
+            config.record.as_ref().map(|record| record.path()),
+        ),
+        #[cfg(unix)]
+        ("PID file", config.daemon.pid_file.as_deref()),
+        #[cfg(unix)]
+        ("log file", config.daemon.log_file.as_deref()),
+    ];
+    for (what, path) in outputs {
+        if let Some(path) = path {
+            check_output(&mut report, what, path);
+        }
+    }
+
+    match report.problems {
+        0 => {
+            info!("configuration OK");
+            Ok(())
+        }
+        problems => Err(Error::CheckFailed(problems)),
+    }
+}
+
+/// Check that a file the server will read can be.
+fn check_input(report: &mut Report, what: &str, path: &Path) {
+    match fs::File::open(path) {
+        Ok(_) => report.ok(format!("{} {} is readable", what, path.display())),
+        Err(e) => report.problem(format!("{} {} can't be read: {}", what, path.display(), e)),
+    }
+}
+
+/// Check that a file the server will write can be created, which it can if
+/// its directory exists.
+fn check_output(report: &mut Report, what: &str, path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    if dir.is_dir() {
+        report.ok(format!("{} {} can be created", what, path.display()));
+    } else {
+        report.problem(format!(
+            "{} {} can't be created, as {} isn't a directory",
+            what,
+            path.display(),
+            dir.display()
+        ));
+    }
+}
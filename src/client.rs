@@ -0,0 +1,72 @@
+//! A minimal HTTP client, for replaying recorded requests, mirroring
+//! traffic to another server, and benchmarking one.
+
+use crate::{Body, Error, Result};
+use http::uri::Authority;
+use http::{header, HeaderValue, Request, StatusCode, Uri};
+use http_body_util::BodyExt;
+use hyper::client::conn::http1::SendRequest;
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+
+/// A server to send requests to, given as an `http://` URL
+#[derive(Clone, Debug)]
+pub struct Target {
+    authority: Authority,
+}
+
+impl Target {
+    pub fn parse(url: &str) -> Option<Target> {
+        let uri: Uri = url.parse().ok()?;
+        match (uri.scheme_str(), uri.authority()) {
+            (Some("http"), Some(authority)) => Some(Target {
+                authority: authority.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Send a request on a connection of its own, and read the whole
+    /// response.
+    pub async fn send(&self, req: Request<Body>) -> Result<StatusCode> {
+        let (status, _) = self.connect().await?.send(req).await?;
+        Ok(status)
+    }
+
+    /// Open a connection, to send requests on one after another.
+    pub async fn connect(&self) -> Result<Connection> {
+        let port = self.authority.port_u16().unwrap_or(80);
+        let stream = TcpStream::connect((self.authority.host(), port)).await?;
+        let (sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(Error::Client)?;
+        tokio::spawn(conn);
+        Ok(Connection {
+            sender,
+            authority: self.authority.clone(),
+        })
+    }
+}
+
+/// An open connection to a target
+pub struct Connection {
+    sender: SendRequest<Body>,
+    authority: Authority,
+}
+
+impl Connection {
+    /// Send a request, and read the whole response, returning its status
+    /// and the length of its body. Requests without a `Host` header get the
+    /// target's.
+    pub async fn send(&mut self, mut req: Request<Body>) -> Result<(StatusCode, usize)> {
+        if !req.headers().contains_key(header::HOST) {
+            let host = HeaderValue::from_str(self.authority.as_str()).map_err(http::Error::from)?;
+            req.headers_mut().insert(header::HOST, host);
+        }
+        self.sender.ready().await.map_err(Error::Client)?;
+        let resp = self.sender.send_request(req).await.map_err(Error::Client)?;
+        let status = resp.status();
+        let body = resp.into_body().collect().await.map_err(Error::Client)?;
+        Ok((status, body.to_bytes().len()))
+    }
+}
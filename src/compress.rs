@@ -0,0 +1,438 @@
+//! Compressing responses with gzip or deflate, with `--compress`, for the
+//! clients that accept them. Compression costs CPU for every response, so
+//! it's tuned with the level for each algorithm, the smallest body worth
+//! compressing, and which types are compressed, since images, video and
+//! archives are already compressed and only get bigger.
+//!
+//! Compressed files can be cached, with `--compress-cache` in memory or with
+//! `--compress-cache-dir` on disk, so that the same file isn't compressed
+//! again for every request. They're known by a SHA-256 of what was
+//! compressed, so a file that has changed, or a page that differs between
+//! requests, like one with injections or in another language, is compressed
+//! afresh. Only files are cached: generated pages, which have no
+//! modification time, would fill the cache with versions nobody asks for
+//! again.
+
+use crate::{Body, Error, Result};
+use bytes::Bytes;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use http::{header, HeaderValue, Response, StatusCode};
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The types compressed unless others are given, which are the text ones
+const DEFAULT_TYPES: &[&str] = &[
+    "text/*",
+    "application/atom+xml",
+    "application/javascript",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// The level used for algorithms without one, which is flate2's default
+const DEFAULT_LEVEL: u32 = 6;
+
+/// The smallest body compressed unless another is given, below which the
+/// saving doesn't make up for the overhead
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Algorithm {
+    Gzip,
+    /// zlib, which is what HTTP calls deflate
+    Deflate,
+}
+
+impl Algorithm {
+    const ALL: [Algorithm; 2] = [Algorithm::Gzip, Algorithm::Deflate];
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Gzip => "gzip",
+            Algorithm::Deflate => "deflate",
+        }
+    }
+
+    /// The extension of files compressed with it in the cache directory
+    fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Gzip => "gz",
+            Algorithm::Deflate => "zz",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Compression {
+    gzip_level: u32,
+    deflate_level: u32,
+    min_size: u64,
+    /// Media type patterns, like `text/*`, to compress
+    types: Vec<String>,
+    /// Media type patterns never to compress, even if in `types`
+    excluded_types: Vec<String>,
+    cache: Option<Cache>,
+}
+
+/// Where compressed files are kept
+#[derive(Debug)]
+enum Cache {
+    Memory(Mutex<MemoryCache>),
+    /// A directory of files named by the SHA-256, level and algorithm
+    Dir(PathBuf),
+}
+
+/// The latest compressed version of each path, by algorithm, with the
+/// SHA-256 of what was compressed. The cache is emptied when it would hold
+/// more than `max_len` bytes.
+#[derive(Debug)]
+struct MemoryCache {
+    entries: HashMap<(String, Algorithm), ([u8; 32], Bytes)>,
+    len: usize,
+    max_len: usize,
+}
+
+impl Compression {
+    /// Compression with the given levels, each either a level from 0 to 9
+    /// for every algorithm, or one for an algorithm like `gzip=9`, and the
+    /// given comma-separated type patterns.
+    pub fn new<'a>(
+        levels: impl IntoIterator<Item = &'a str>,
+        min_size: u64,
+        types: Option<&str>,
+        excluded_types: Option<&str>,
+    ) -> Result<Compression> {
+        let mut compression = Compression {
+            gzip_level: DEFAULT_LEVEL,
+            deflate_level: DEFAULT_LEVEL,
+            min_size,
+            types: match types {
+                Some(types) => parse_types(types),
+                None => DEFAULT_TYPES.iter().map(|&t| t.to_owned()).collect(),
+            },
+            excluded_types: excluded_types.map(parse_types).unwrap_or_default(),
+            cache: None,
+        };
+        for value in levels {
+            let invalid = || Error::CompressLevelParse(value.to_owned());
+            let (algorithms, level) = match value.split_once('=') {
+                Some((name, level)) => {
+                    let algorithm = Algorithm::ALL
+                        .iter()
+                        .copied()
+                        .find(|algorithm| algorithm.name() == name.trim())
+                        .ok_or_else(invalid)?;
+                    (vec![algorithm], level)
+                }
+                None => (Algorithm::ALL.to_vec(), value),
+            };
+            let level: u32 = level.trim().parse().map_err(|_| invalid())?;
+            if level > 9 {
+                return Err(invalid());
+            }
+            for algorithm in algorithms {
+                match algorithm {
+                    Algorithm::Gzip => compression.gzip_level = level,
+                    Algorithm::Deflate => compression.deflate_level = level,
+                }
+            }
+        }
+        Ok(compression)
+    }
+
+    /// Cache up to `max_len` bytes of compressed files in memory.
+    pub fn cache_in_memory(&mut self, max_len: usize) {
+        self.cache = Some(Cache::Memory(Mutex::new(MemoryCache {
+            entries: HashMap::new(),
+            len: 0,
+            max_len,
+        })));
+    }
+
+    /// Cache compressed files in a directory, which `create_cache_dir`
+    /// creates if need be. Nothing is ever removed from it.
+    pub fn cache_in_dir(&mut self, dir: PathBuf) {
+        self.cache = Some(Cache::Dir(dir));
+    }
+
+    /// Create the directory compressed files are cached in, if any, for
+    /// serving.
+    pub fn create_cache_dir(&self) -> io::Result<()> {
+        match self.cache_dir() {
+            Some(dir) => std::fs::create_dir_all(dir),
+            None => Ok(()),
+        }
+    }
+
+    /// The directory compressed files are cached in, if any
+    pub fn cache_dir(&self) -> Option<&PathBuf> {
+        match &self.cache {
+            Some(Cache::Dir(dir)) => Some(dir),
+            _ => None,
+        }
+    }
+
+    /// Compress a file's response with each algorithm, as for a request for
+    /// the path, so that the cache has them before they're asked for
+    pub async fn preload(&self, path: &str, resp: &Response<Body>) {
+        if self.cache.is_none() {
+            return;
+        }
+        for algorithm in Algorithm::ALL {
+            let mut copy = Response::new(resp.body().clone());
+            *copy.headers_mut() = resp.headers().clone();
+            let accept_encoding = HeaderValue::from_static(algorithm.name());
+            self.apply(path, Some(&accept_encoding), copy).await;
+        }
+    }
+
+    /// Compress the response to a request for the path, from a client that
+    /// sent this `Accept-Encoding`, if it accepts an algorithm and the
+    /// response is worth compressing.
+    pub async fn apply(
+        &self,
+        path: &str,
+        accept_encoding: Option<&HeaderValue>,
+        resp: Response<Body>,
+    ) -> Response<Body> {
+        let algorithm = match accept_encoding.and_then(choose) {
+            Some(algorithm) if self.wants(&resp) => algorithm,
+            _ => return resp,
+        };
+        let level = match algorithm {
+            Algorithm::Gzip => self.gzip_level,
+            Algorithm::Deflate => self.deflate_level,
+        };
+        let (mut parts, body) = resp.into_parts();
+        let body = body.collect().await.expect("infallible").to_bytes();
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| parts.headers.contains_key(header::LAST_MODIFIED));
+        let compressed = match cache {
+            Some(cache) => cache.get_or_compress(path, algorithm, level, &body).await,
+            None => compress_blocking(algorithm, level, body.clone()).await,
+        };
+        let compressed = match compressed {
+            Ok(compressed) if compressed.len() < body.len() => compressed,
+            Ok(_) => return Response::from_parts(parts, Body::from(body)),
+            Err(e) => {
+                warn!("failed to compress response: {}", e);
+                return Response::from_parts(parts, Body::from(body));
+            }
+        };
+
+        let headers = &mut parts.headers;
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(algorithm.name()),
+        );
+        headers.insert(header::CONTENT_LENGTH, compressed.len().into());
+        // The compressed body is another representation, so the file's
+        // digests and tag don't describe it
+        headers.remove("repr-digest");
+        headers.remove("content-md5");
+        if let Some(etag) = headers
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+        {
+            let etag = match etag.strip_suffix('"') {
+                Some(etag) => format!("{}-{}\"", etag, algorithm.name()),
+                None => etag.to_owned(),
+            };
+            if let Ok(etag) = HeaderValue::from_str(&etag) {
+                headers.insert(header::ETAG, etag);
+            }
+        }
+        Response::from_parts(parts, Body::from(compressed))
+    }
+
+    /// Whether the response is a whole body of a type to compress, big
+    /// enough to be worth it, and not already compressed
+    fn wants(&self, resp: &Response<Body>) -> bool {
+        use hyper::body::Body as _;
+
+        let headers = resp.headers();
+        let no_transform = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("no-transform"));
+        let essence = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase());
+        let essence = match essence {
+            Some(essence) => essence,
+            None => return false,
+        };
+        resp.status() == StatusCode::OK
+            && !no_transform
+            && !headers.contains_key(header::CONTENT_ENCODING)
+            && resp.body().size_hint().exact().unwrap_or(0) >= self.min_size
+            && self.types.iter().any(|pattern| matches(pattern, &essence))
+            && !self
+                .excluded_types
+                .iter()
+                .any(|pattern| matches(pattern, &essence))
+    }
+}
+
+fn parse_types(types: &str) -> Vec<String> {
+    types
+        .split(',')
+        .map(|pattern| pattern.trim().to_ascii_lowercase())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+/// Whether a media type matches a pattern like `text/html`, `text/*` or `*/*`
+fn matches(pattern: &str, essence: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(type_) => essence.split('/').next() == Some(type_),
+        None => pattern == essence,
+    }
+}
+
+/// The algorithm an `Accept-Encoding` header prefers, of those it accepts
+fn choose(accept_encoding: &HeaderValue) -> Option<Algorithm> {
+    let accept_encoding = accept_encoding.to_str().ok()?;
+    let quality = |name: &str| {
+        let mut best = None;
+        for coding in accept_encoding.split(',') {
+            let mut params = coding.split(';');
+            let coding = params.next().unwrap_or("").trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if coding.eq_ignore_ascii_case(name) {
+                return q;
+            } else if coding == "*" {
+                best = Some(q);
+            }
+        }
+        best.unwrap_or(0.0)
+    };
+    Algorithm::ALL
+        .iter()
+        .map(|&algorithm| (algorithm, quality(algorithm.name())))
+        .filter(|&(_, q)| q > 0.0)
+        .fold(
+            None,
+            |best: Option<(Algorithm, f32)>, (algorithm, q)| match best {
+                Some((_, best_q)) if best_q >= q => best,
+                _ => Some((algorithm, q)),
+            },
+        )
+        .map(|(algorithm, _)| algorithm)
+}
+
+fn compress(algorithm: Algorithm, level: u32, body: &Bytes) -> std::io::Result<Vec<u8>> {
+    let level = flate2::Compression::new(level);
+    match algorithm {
+        Algorithm::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        Algorithm::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Compress on a blocking thread, since large bodies take a while
+async fn compress_blocking(algorithm: Algorithm, level: u32, body: Bytes) -> Result<Bytes> {
+    crate::blocking(move || Ok(compress(algorithm, level, &body)?.into())).await
+}
+
+impl Cache {
+    /// The body compressed, from the cache if it's there, or else compressed
+    /// and cached
+    async fn get_or_compress(
+        &self,
+        path: &str,
+        algorithm: Algorithm,
+        level: u32,
+        body: &Bytes,
+    ) -> Result<Bytes> {
+        let sha256: [u8; 32] = Sha256::digest(body).into();
+        match self {
+            Cache::Memory(cache) => {
+                let key = (path.to_owned(), algorithm);
+                if let Some((hash, compressed)) = cache.lock().unwrap().entries.get(&key) {
+                    if *hash == sha256 {
+                        trace!("compression cache hit: {}", path);
+                        return Ok(compressed.clone());
+                    }
+                }
+                let compressed = compress_blocking(algorithm, level, body.clone()).await?;
+                let mut cache = cache.lock().unwrap();
+                if cache.len + compressed.len() > cache.max_len {
+                    debug!("compression cache full, clearing");
+                    cache.entries.clear();
+                    cache.len = 0;
+                }
+                if compressed.len() <= cache.max_len {
+                    cache.len += compressed.len();
+                    if let Some((_, old)) = cache.entries.insert(key, (sha256, compressed.clone()))
+                    {
+                        cache.len -= old.len();
+                    }
+                }
+                Ok(compressed)
+            }
+            Cache::Dir(dir) => {
+                let mut name = String::new();
+                for byte in &sha256 {
+                    write!(name, "{:02x}", byte).expect("write to string");
+                }
+                let file = dir.join(format!("{}-{}.{}", name, level, algorithm.extension()));
+                match tokio::fs::read(&file).await {
+                    Ok(compressed) => {
+                        trace!("compression cache hit: {}", path);
+                        return Ok(compressed.into());
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => warn!("failed to read {} from cache: {}", file.display(), e),
+                }
+                let compressed = compress_blocking(algorithm, level, body.clone()).await?;
+                if let Err(e) = write_atomically(&file, &compressed).await {
+                    warn!("failed to write {} to cache: {}", file.display(), e);
+                }
+                Ok(compressed)
+            }
+        }
+    }
+}
+
+/// Write a file under another name and rename it, so that a request at the
+/// same time never reads half of it
+async fn write_atomically(path: &std::path::Path, contents: &[u8]) -> io::Result<()> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let temp = path.with_extension(format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::write(&temp, contents).await?;
+    let renamed = tokio::fs::rename(&temp, path).await;
+    if renamed.is_err() {
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    renamed
+}
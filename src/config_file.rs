@@ -0,0 +1,174 @@
+//! Config files, read with `--config` and written by the `gen-config`
+//! subcommand. A config file is TOML, and its keys are the long names of the
+//! arguments for serving, with `root` for the root dir, e.g.
+//!
+//! ```toml
+//! root = "public"
+//! addr = ["0.0.0.0:80", "[::]:80"]
+//! extensions = true
+//! metadata-ttl = 5
+//! ```
+//!
+//! Rather than having a second way to configure everything, the file is
+//! turned into command line arguments, which go before those actually given,
+//! and are left out where those give the same argument. A table of tables,
+//! like `[route."/docs"]` with `no-cache = true`, is an argument for each of
+//! them, like `--route="/docs no-cache"`.
+
+use crate::args::{Kind, ServeArg, SERVE_ARGS};
+use crate::{Error, Result};
+use clap::ArgMatches;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// The arguments for serving that can be given in a config file
+fn config_args() -> impl Iterator<Item = &'static ServeArg> {
+    SERVE_ARGS.iter().filter(|arg| arg.configurable)
+}
+
+/// Insert the arguments from the config file into the command line, before
+/// any `--`, leaving out those that `matches` shows were given.
+pub fn add_args(path: &Path, matches: &ArgMatches, cmdline: &mut Vec<OsString>) -> Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let table: Table = text.parse().map_err(Error::ConfigParse)?;
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let arg = config_args()
+            .find(|arg| arg.key == *key)
+            .ok_or_else(|| Error::ConfigKey(key.clone()))?;
+        if matches.is_present(arg.name) {
+            continue;
+        }
+        let invalid = || Error::ConfigValue(key.clone());
+        match (&arg.kind, value) {
+            (_, Value::Boolean(false)) => {}
+            // Options with optional values, like `--open`, can be given alone
+            (Kind::Flag | Kind::Count | Kind::Option { .. }, Value::Boolean(true)) => {
+                args.push(format!("--{}", key))
+            }
+            (Kind::Option { multiple: true }, Value::Array(values)) => {
+                for value in values {
+                    args.push(format!("--{}={}", key, scalar(value).ok_or_else(invalid)?));
+                }
+            }
+            // Repeated options made of a name and settings, like `--route`,
+            // can be tables of the settings by name, as in `[route."/docs"]`
+            (Kind::Option { multiple: true }, Value::Table(entries)) => {
+                for (name, settings) in entries {
+                    let settings = settings.as_table().ok_or_else(invalid)?;
+                    let mut value = name.clone();
+                    for (setting, setting_value) in settings {
+                        let setting = match setting_value {
+                            Value::Boolean(true) => setting.clone(),
+                            Value::Boolean(false) => format!("{}=false", setting),
+                            v => format!("{}={}", setting, scalar(v).ok_or_else(invalid)?),
+                        };
+                        value.push(' ');
+                        value.push_str(&setting);
+                    }
+                    args.push(format!("--{}={}", key, value));
+                }
+            }
+            (Kind::Option { .. }, value) => {
+                args.push(format!("--{}={}", key, scalar(value).ok_or_else(invalid)?))
+            }
+            (Kind::Positional, value) => args.push(scalar(value).ok_or_else(invalid)?),
+            (Kind::Flag | Kind::Count, _) => return Err(invalid()),
+        }
+    }
+
+    let at = cmdline
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(cmdline.len());
+    cmdline.splice(at..at, args.into_iter().map(OsString::from));
+    Ok(())
+}
+
+/// A value as it would be written on the command line
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+/// A config file with every argument for serving, commented out, and with
+/// their help as comments, except for those given in `matches`, which are
+/// set as given.
+pub fn generate(matches: &ArgMatches) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# basic-http-server {} configuration, for use with --config=FILE.\n\
+         # Arguments given on the command line override those here.",
+        env!("CARGO_PKG_VERSION")
+    )
+    .map_err(Error::WriteInConfig)?;
+
+    for arg in config_args() {
+        let key = &arg.key;
+        let given: Vec<&str> = matches
+            .values_of(arg.name)
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let line = match arg.kind {
+            Kind::Flag | Kind::Count if matches.is_present(arg.name) => format!("{} = true", key),
+            Kind::Flag | Kind::Count => format!("# {} = false", key),
+            _ if given.is_empty() && matches.is_present(arg.name) => format!("{} = true", key),
+            Kind::Option { multiple: true } if given.is_empty() => {
+                format!("# {} = [\"{}\"]", key, arg.value_name)
+            }
+            _ if given.is_empty() => format!("# {} = \"{}\"", key, arg.value_name),
+            Kind::Option { multiple: true } => {
+                let values: Vec<String> = given.iter().map(|value| toml_value(value)).collect();
+                format!("{} = [{}]", key, values.join(", "))
+            }
+            _ => format!("{} = {}", key, toml_value(given[0])),
+        };
+        writeln!(out, "\n# {}\n{}", arg.help, line).map_err(Error::WriteInConfig)?;
+    }
+    Ok(out)
+}
+
+/// The arguments given, on the command line or in a config file, as a JSON
+/// object with the keys of a config file
+pub fn to_json(matches: &ArgMatches) -> serde_json::Map<String, serde_json::Value> {
+    let mut object = serde_json::Map::new();
+    for arg in config_args() {
+        if !matches.is_present(arg.name) {
+            continue;
+        }
+        let given: Vec<&str> = matches
+            .values_of(arg.name)
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let value = match arg.kind {
+            _ if given.is_empty() => serde_json::Value::Bool(true),
+            Kind::Option { multiple: true } => given.into_iter().map(json_value).collect(),
+            _ => json_value(given[0]),
+        };
+        object.insert(arg.key.to_owned(), value);
+    }
+    object
+}
+
+/// A value for JSON, as a number if it looks like one, or else as a string
+fn json_value(value: &str) -> serde_json::Value {
+    match value.parse::<i64>() {
+        Ok(i) => i.into(),
+        Err(_) => value.into(),
+    }
+}
+
+/// A value for TOML, as a number if it looks like one, or else as a string
+fn toml_value(value: &str) -> String {
+    match value.parse::<i64>() {
+        Ok(i) => i.to_string(),
+        Err(_) => Value::String(value.to_owned()).to_string(),
+    }
+}
@@ -0,0 +1,98 @@
+//! How much is written to the terminal: only errors with `-q`, a line for
+//! each request as it's answered with `-v`, and debug logging as well with
+//! `-vv`.
+
+use crate::geoip::Location;
+use crate::request_id::RequestId;
+use crate::stats;
+use http::{Method, Response, StatusCode};
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors
+    Quiet,
+    /// The configuration on startup, and anything that goes wrong
+    Normal,
+    /// A line for each request too
+    Requests,
+    /// Debug logging too
+    Debug,
+}
+
+impl Verbosity {
+    pub fn new(quiet: bool, verbose: u64) -> Verbosity {
+        match verbose {
+            _ if quiet => Verbosity::Quiet,
+            0 => Verbosity::Normal,
+            1 => Verbosity::Requests,
+            _ => Verbosity::Debug,
+        }
+    }
+
+    /// The level this crate logs at, unless `RUST_LOG` says otherwise
+    pub fn log_level(self) -> &'static str {
+        match self {
+            Verbosity::Quiet => "error",
+            Verbosity::Normal | Verbosity::Requests => "info",
+            Verbosity::Debug => "debug",
+        }
+    }
+}
+
+/// Print the line for a request, with its method, path, status, how long it
+/// took and the size of the response body, and where the client is and the
+/// request's ID, if they're known.
+pub fn request<B: hyper::body::Body>(
+    method: &Method,
+    path: &str,
+    resp: &Response<B>,
+    elapsed: Duration,
+    location: Option<&Location>,
+    id: Option<&RequestId>,
+) {
+    let status = resp.status();
+    let bytes = resp.body().size_hint().exact().unwrap_or(0);
+    let millis = elapsed.as_secs_f64() * 1000.0;
+    let mut size = stats::format_bytes(bytes as f64);
+    if let Some(location) = location.filter(|location| !location.is_empty()) {
+        size = format!("{} {}", size, location);
+    }
+    if let Some(id) = id {
+        size = format!("{} {}", size, id);
+    }
+    if colors() {
+        eprintln!(
+            "\x1b[1m{}\x1b[0m {} \x1b[{}m{}\x1b[0m \x1b[2m{:.1} ms {}\x1b[0m",
+            method,
+            path,
+            status_color(status),
+            status.as_u16(),
+            millis,
+            size
+        );
+    } else {
+        eprintln!("{} {} {} {:.1} ms {}", method, path, status.as_u16(), millis, size);
+    }
+}
+
+/// Whether to color the lines, which is when they go to a terminal, unless
+/// `NO_COLOR` is set.
+fn colors() -> bool {
+    static COLORS: OnceLock<bool> = OnceLock::new();
+    *COLORS.get_or_init(|| io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+}
+
+/// The ANSI color code for a status: green for success, cyan for redirects,
+/// yellow for client errors and red for server errors
+fn status_color(status: StatusCode) -> u8 {
+    match status.as_u16() {
+        200..=299 => 32,
+        300..=399 => 36,
+        400..=499 => 33,
+        500..=599 => 31,
+        _ => 39,
+    }
+}
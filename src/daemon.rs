@@ -0,0 +1,76 @@
+//! Running as a simple background service on unix, with `--daemon`,
+//! `--pid-file` and `--log-file`, without needing a supervisor.
+//!
+//! Forking is only safe while the process has one thread, so all of this
+//! happens before the runtime starts.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Detach from the terminal and run in the background
+    pub detach: bool,
+    pub pid_file: Option<PathBuf>,
+    /// Where log output goes, instead of the terminal
+    pub log_file: Option<PathBuf>,
+}
+
+/// Detach from the terminal if asked to, and send output to the log file.
+/// Without a log file, a detached server's output is discarded.
+pub fn start(options: &Options) -> io::Result<()> {
+    if options.detach {
+        // The first fork returns control to the shell, and the new session
+        // detaches from the terminal. The second fork leaves a process that
+        // isn't a session leader, so can never take a terminal again.
+        fork_into_child()?;
+        if unsafe { libc::setsid() } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        fork_into_child()?;
+        redirect(&File::open("/dev/null")?, &[libc::STDIN_FILENO])?;
+    }
+
+    let output = match &options.log_file {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None if options.detach => Some(OpenOptions::new().write(true).open("/dev/null")?),
+        None => None,
+    };
+    if let Some(output) = output {
+        redirect(&output, &[libc::STDOUT_FILENO, libc::STDERR_FILENO])?;
+    }
+
+    if let Some(path) = &options.pid_file {
+        std::fs::write(path, format!("{}\n", std::process::id()))?;
+    }
+    Ok(())
+}
+
+/// Remove the PID file, once the server has stopped.
+pub fn stop(options: &Options) {
+    if let Some(path) = &options.pid_file {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("failed to remove PID file {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Fork, with the parent exiting and the child returning.
+fn fork_into_child() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+fn redirect(file: &File, fds: &[libc::c_int]) -> io::Result<()> {
+    for &fd in fds {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Digests of the files served, sent with `--digest` in `Repr-Digest` and
+//! `Content-MD5` headers so that clients can check their downloads. A
+//! request for `<file>.sha256`, when there is no such file, gets the file's
+//! checksum in the format of `sha256sum`.
+//!
+//! Hashing a large file takes a while, so digests are remembered until the
+//! file's modification time or length changes.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use http::{header, HeaderValue, Response, StatusCode};
+use http_body_util::BodyExt;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The cache is emptied when it holds more entries than this.
+const MAX_ENTRIES: usize = 16 * 1024;
+
+#[derive(Clone)]
+pub struct Digests {
+    pub sha256: [u8; 32],
+    pub md5: [u8; 16],
+}
+
+impl Digests {
+    fn of(data: &[u8]) -> Digests {
+        Digests {
+            sha256: Sha256::digest(data).into(),
+            md5: Md5::digest(data).into(),
+        }
+    }
+}
+
+/// The digests of files, by path, with the modification time and length they
+/// were computed for
+#[derive(Default)]
+pub struct DigestCache {
+    entries: Mutex<HashMap<PathBuf, (SystemTime, u64, Digests)>>,
+}
+
+impl DigestCache {
+    /// The digests of a file's contents, computed unless already known for
+    /// this version of the file. Files without a modification time are always
+    /// hashed afresh.
+    pub async fn get(
+        &self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        contents: Bytes,
+    ) -> Result<Digests> {
+        let len = contents.len() as u64;
+        if let Some(modified) = modified {
+            let entries = self.entries.lock().unwrap();
+            if let Some((m, l, digests)) = entries.get(path) {
+                if *m == modified && *l == len {
+                    trace!("digest cache hit: {}", path.display());
+                    return Ok(digests.clone());
+                }
+            }
+        }
+
+        let digests = crate::blocking(move || Ok(Digests::of(&contents))).await?;
+        if let Some(modified) = modified {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= MAX_ENTRIES {
+                debug!("digest cache full, clearing");
+                entries.clear();
+            }
+            entries.insert(path.to_owned(), (modified, len, digests.clone()));
+        }
+        Ok(digests)
+    }
+
+    pub fn purge(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Add digest headers to the response for a file.
+    pub async fn add_headers(
+        &self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        resp: Response<Body>,
+    ) -> Result<Response<Body>> {
+        let (mut parts, body) = resp.into_parts();
+        let contents = body.collect().await.expect("infallible").to_bytes();
+        let digests = self.get(path, modified, contents.clone()).await?;
+
+        let repr_digest = format!("sha-256=:{}:", BASE64.encode(digests.sha256));
+        let content_md5 = BASE64.encode(digests.md5);
+        let headers = &mut parts.headers;
+        headers.insert(
+            "repr-digest",
+            HeaderValue::from_str(&repr_digest).expect("base64"),
+        );
+        headers.insert(
+            "content-md5",
+            HeaderValue::from_str(&content_md5).expect("base64"),
+        );
+        Ok(Response::from_parts(parts, Body::from(contents)))
+    }
+
+    /// Respond to a request for the `.sha256` sidecar of a file, if that's
+    /// what the path is and there isn't a real file there.
+    pub async fn serve_sidecar(
+        &self,
+        vfs: &dyn Vfs,
+        path: &Path,
+    ) -> Result<Option<Response<Body>>> {
+        if path.extension() != Some(OsStr::new("sha256")) || vfs.metadata(path).await.is_ok() {
+            return Ok(None);
+        }
+        let file_path = path.with_extension("");
+        let metadata = match vfs.metadata(&file_path).await {
+            Ok(metadata) if !metadata.is_dir => metadata,
+            _ => return Ok(None),
+        };
+        let contents = crate::read_file(vfs.open(&file_path).await?).await?;
+        let digests = self
+            .get(&file_path, metadata.modified, contents.into())
+            .await?;
+
+        let mut body = String::new();
+        for byte in &digests.sha256 {
+            write!(body, "{:02x}", byte).map_err(Error::WriteInChecksum)?;
+        }
+        let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        writeln!(body, "  {}", name).map_err(Error::WriteInChecksum)?;
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, body.len())
+            .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.as_ref())
+            .body(Body::from(body))
+            .map(Some)
+            .map_err(Error::from)
+    }
+}
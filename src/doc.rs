@@ -0,0 +1,82 @@
+//! Serving the docs built by `cargo doc` with `--doc`. Rustdoc's search and
+//! settings don't work from `file://` URLs, so its pages are better read
+//! from a server. Cargo says where the docs are and which crates the
+//! workspace has, and the crate in the current directory, or else the
+//! workspace's first, is opened.
+
+use crate::{Error, Result};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// What `cargo metadata` says about the workspace, as far as it's needed
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// The workspace's docs
+pub struct Docs {
+    pub dir: PathBuf,
+    /// The path of the page to open
+    pub index: String,
+}
+
+/// Find the docs of the workspace in the current directory.
+pub fn find() -> Result<Docs> {
+    let output = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .map_err(|e| Error::CargoMetadata(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::CargoMetadata(stderr.trim().to_owned()));
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Error::CargoMetadata(e.to_string()))?;
+
+    let dir = metadata.target_directory.join("doc");
+    if !dir.is_dir() {
+        return Err(Error::DocsMissing(dir.display().to_string()));
+    }
+
+    let cwd = std::env::current_dir()?;
+    let package = metadata
+        .packages
+        .iter()
+        .find(|package| package.manifest_path.parent() == Some(cwd.as_path()))
+        .or_else(|| metadata.packages.first());
+    let index = match package.and_then(documented_target) {
+        Some(name) if dir.join(&name).join("index.html").is_file() => {
+            format!("/{}/index.html", name)
+        }
+        _ => "/".to_owned(),
+    };
+    Ok(Docs { dir, index })
+}
+
+/// The name of the directory that the package's docs are in, which is its
+/// library's, or else its first binary's
+fn documented_target(package: &Package) -> Option<String> {
+    let is = |target: &&Target, kinds: &[&str]| {
+        target.kind.iter().any(|kind| kinds.contains(&kind.as_str()))
+    };
+    package
+        .targets
+        .iter()
+        .find(|target| is(target, &["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"]))
+        .or_else(|| package.targets.iter().find(|target| is(target, &["bin"])))
+        .map(|target| target.name.replace('-', "_"))
+}
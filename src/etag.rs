@@ -0,0 +1,110 @@
+//! Entity tags for files, with `--etag`, so that caches and CDNs in front of
+//! the server can ask whether a file has changed with `If-None-Match`, and
+//! get a 304 if it hasn't. Weak tags are made from a file's modification
+//! time and length, which is cheap, but changes when a file is touched or
+//! copied without changing. Strong tags are a hash of the contents, which
+//! only changes with them, computed the first time a file is served and
+//! remembered until it's modified.
+
+use crate::digest::DigestCache;
+use crate::{Body, Result};
+use bytes::Bytes;
+use http::{header, HeaderValue, Response, StatusCode};
+use http_body_util::BodyExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The headers a 304 keeps from the response it stands for
+const NOT_MODIFIED_HEADERS: &[header::HeaderName] = &[
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+#[derive(Clone, Default)]
+pub enum ETags {
+    #[default]
+    Off,
+    /// From the modification time and length
+    Weak,
+    /// From the contents, with the hashes remembered in the cache
+    Strong(Arc<DigestCache>),
+}
+
+impl ETags {
+    pub fn is_off(&self) -> bool {
+        matches!(self, ETags::Off)
+    }
+
+    /// Add an entity tag to the response for a file.
+    pub async fn add_header(
+        &self,
+        path: &Path,
+        modified: Option<SystemTime>,
+        resp: Response<Body>,
+    ) -> Result<Response<Body>> {
+        let (mut parts, body) = resp.into_parts();
+        let contents = body.collect().await.expect("infallible").to_bytes();
+        let etag = match self {
+            ETags::Off => None,
+            // Files without a modification time can't be told apart from
+            // their changed selves
+            ETags::Weak => modified.map(|modified| {
+                let secs = modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                format!("W/\"{:x}-{:x}\"", secs, contents.len())
+            }),
+            ETags::Strong(digests) => {
+                let digests = digests.get(path, modified, Bytes::clone(&contents)).await?;
+                let hash: String = digests.sha256[..16]
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                Some(format!("\"{}\"", hash))
+            }
+        };
+        if let Some(etag) = etag {
+            parts.headers.insert(
+                header::ETAG,
+                HeaderValue::from_str(&etag).expect("valid header"),
+            );
+        }
+        Ok(Response::from_parts(parts, Body::from(contents)))
+    }
+}
+
+/// Whether an `If-None-Match` header matches the response's entity tag, by
+/// the weak comparison it calls for
+pub fn none_match(if_none_match: &HeaderValue, resp: &Response<Body>) -> bool {
+    let etag = resp.headers().get(header::ETAG);
+    let etag = match etag.and_then(|etag| etag.to_str().ok()) {
+        Some(etag) => etag.trim_start_matches("W/"),
+        None => return false,
+    };
+    let if_none_match = match if_none_match.to_str() {
+        Ok(if_none_match) => if_none_match,
+        Err(_) => return false,
+    };
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|candidate| candidate.trim().trim_start_matches("W/") == etag)
+}
+
+/// A 304 in place of the response, for a client whose copy is current
+pub fn not_modified(resp: &Response<Body>) -> Response<Body> {
+    let mut not_modified = Response::new(Body::default());
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+    for name in NOT_MODIFIED_HEADERS {
+        if let Some(value) = resp.headers().get(name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+    not_modified
+}
@@ -0,0 +1,116 @@
+//! Stopping without being asked, for CI jobs that start the server for
+//! browser tests and might otherwise leave it running: after a while, with
+//! `--exit-after`, after a number of requests, with `--exit-after-requests`,
+//! or on unix, once the process that started it has exited, with
+//! `--exit-with-parent`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The number of requests answered
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Set once the last request has been answered
+static SERVED: AtomicBool = AtomicBool::new(false);
+
+/// How often to check whether the parent process has exited
+#[cfg(unix)]
+const PARENT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// When to stop
+#[derive(Clone)]
+pub struct Conditions {
+    pub after: Option<Duration>,
+    pub requests: Option<u64>,
+    /// Stop when the process with this ID, the server's parent when it
+    /// started, before `--daemon` detached it, exits
+    #[cfg(unix)]
+    pub with_parent: Option<u32>,
+}
+
+impl Conditions {
+    /// Count a request that's been answered. Whether it's the last, or after
+    /// the last, in which case its connection mustn't be kept open, since the
+    /// server stops once it's closed.
+    pub fn count_request(&self) -> bool {
+        let limit = match self.requests {
+            Some(limit) => limit,
+            None => return false,
+        };
+        if REQUESTS.fetch_add(1, Ordering::Relaxed) + 1 < limit {
+            return false;
+        }
+        SERVED.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Wait until the time is up or the parent process has exited, or
+    /// forever if the server isn't to stop for those.
+    pub async fn wait(&self) {
+        let time_up = async {
+            match self.after {
+                Some(after) => {
+                    tokio::time::sleep(after).await;
+                    info!("exiting after {:?}", after);
+                }
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(unix)]
+        let parent_exited = async {
+            if let Some(parent) = self.with_parent {
+                parent_exited(parent).await;
+                info!("exiting, as the parent process has exited");
+            } else {
+                std::future::pending().await
+            }
+        };
+        #[cfg(not(unix))]
+        let parent_exited = std::future::pending::<()>();
+        tokio::select! {
+            _ = time_up => {}
+            _ = parent_exited => {}
+        }
+    }
+}
+
+/// Whether the server should stop, having answered the requests it was to.
+/// It's asked as each connection closes, so that the last response isn't cut
+/// off.
+pub fn requests_served() -> bool {
+    SERVED.load(Ordering::Relaxed)
+}
+
+/// Wait for the process that started the server to exit. An orphan is
+/// adopted by init, or a subreaper, so its parent process ID changes. Once
+/// `--daemon` has detached the server, though, that process is no longer its
+/// parent, and is looked for by its ID instead.
+#[cfg(unix)]
+async fn parent_exited(parent: u32) {
+    let parent_id = std::os::unix::process::parent_id;
+    let detached = parent_id() != parent;
+    let mut interval = tokio::time::interval(PARENT_POLL_INTERVAL);
+    loop {
+        let exited = if detached {
+            !is_running(parent)
+        } else {
+            parent_id() != parent
+        };
+        if exited {
+            return;
+        }
+        interval.tick().await;
+    }
+}
+
+/// Whether there's a process with this ID
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Safety: signal 0 isn't sent, only checked for permission to send it.
+    // Process IDs are `pid_t`s, which `parent_id` returns as a `u32`.
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    // A process of another user exists, but can't be signalled
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
@@ -1,151 +1,516 @@
 //! Developer extensions for basic-http-server
 
-use super::{Config, HtmlCfg};
+use super::i18n::{Lang, Text};
+use super::negotiate::{self, Format};
+use super::vfs::{Vfs, VfsFile};
+use super::{Body, Config, HtmlCfg};
 use super::{Error, Result};
 use comrak::ComrakOptions;
-use futures::{future, future::Either, Future, Stream};
-use http::{Request, Response, StatusCode};
-use hyper::{header, Body};
+use http::{header, Request, Response, StatusCode};
+use hyper::body::Incoming;
 use std::ffi::OsStr;
 use std::fmt::Write;
 use std::io;
 use std::path::{Path, PathBuf};
-use tokio::fs::{self, File};
-use tokio_fs::DirEntry;
+use syntect::parsing::SyntaxReference;
+use tokio::io::AsyncReadExt;
 
-pub fn serve(
+mod archive;
+mod gallery;
+mod hexdump;
+mod json;
+mod playlist;
+mod render_cache;
+mod resize;
+mod source;
+mod table;
+
+pub use archive::ArchiveCache;
+pub use render_cache::RenderCache;
+
+/// An extension that can be enabled on its own, with `--ext`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Extension {
+    Markdown,
+    Listing,
+    Gallery,
+    Playlist,
+    Highlight,
+    Json,
+    Table,
+    Resize,
+    Hexdump,
+    Archive,
+}
+
+/// The extensions by name, in the order they're listed in
+const EXTENSIONS: &[(&str, Extension)] = &[
+    ("markdown", Extension::Markdown),
+    ("listing", Extension::Listing),
+    ("gallery", Extension::Gallery),
+    ("playlist", Extension::Playlist),
+    ("highlight", Extension::Highlight),
+    ("json", Extension::Json),
+    ("table", Extension::Table),
+    ("resize", Extension::Resize),
+    ("hexdump", Extension::Hexdump),
+    ("archive", Extension::Archive),
+];
+
+/// The set of extensions that are enabled, all of them with `-x`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Extensions(u16);
+
+impl Extensions {
+    pub fn all() -> Extensions {
+        Extensions((1 << EXTENSIONS.len()) - 1)
+    }
+
+    /// Parse a comma-separated list of extension names, failing with the
+    /// first name that isn't one.
+    pub fn parse(list: &str) -> std::result::Result<Extensions, String> {
+        let mut extensions = Extensions::default();
+        let names = list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+        for name in names {
+            let i = EXTENSIONS
+                .iter()
+                .position(|(n, _)| n.eq_ignore_ascii_case(name))
+                .ok_or_else(|| name.to_string())?;
+            extensions.0 |= 1 << i;
+        }
+        Ok(extensions)
+    }
+
+    pub fn contains(self, extension: Extension) -> bool {
+        let i = EXTENSIONS.iter().position(|&(_, e)| e == extension);
+        self.0 & (1 << i.expect("every extension is named")) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::fmt::Display for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names: Vec<&str> = EXTENSIONS
+            .iter()
+            .filter(|&&(_, extension)| self.contains(extension))
+            .map(|&(name, _)| name)
+            .collect();
+        match &names[..] {
+            [] => write!(f, "none"),
+            names => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+pub async fn serve(
     config: Config,
-    req: Request<Body>,
+    req: Request<Incoming>,
     resp: super::Result<Response<Body>>,
-) -> Box<Future<Item = Response<Body>, Error = Error> + Send + 'static> {
+) -> Result<Response<Body>> {
     trace!("checking extensions");
 
-    if !config.use_extensions {
-        return Box::new(future::result(resp));
+    if config.extensions.is_empty() {
+        return resp;
     }
 
-    let path = super::local_path_for_request(&req.uri(), &config.root_dir);
+    let path = super::path_for_request(&config, req.uri());
     if path.is_none() {
-        return Box::new(future::result(resp));
+        return resp;
     }
     let path = path.unwrap();
+
+    // Paths into archives fail to open, as e.g. "bundle.zip" is not a directory
+    if resp.is_err() && config.extensions.contains(Extension::Archive) {
+        if let Some(archive_path) = archive::find_archive(&config.root_dir, &path) {
+            trace!("using archive extension");
+            return archive::serve_entry(config, req, path, archive_path).await;
+        }
+    }
+
+    serve_file(&config, &req, path, resp).await
+}
+
+/// Apply the extensions to the response for a file, which may be within an
+/// archive.
+async fn serve_file(
+    config: &Config,
+    req: &Request<Incoming>,
+    path: PathBuf,
+    resp: super::Result<Response<Body>>,
+) -> Result<Response<Body>> {
+    let vfs = &*config.vfs;
+    let extensions = config.extensions;
     let file_ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
 
-    if file_ext == "md" {
+    if file_ext == "md" && extensions.contains(Extension::Markdown) {
         trace!("using markdown extension");
-        return Box::new(md_path_to_html(&path));
-    }
-
-    if let Err(e) = resp {
-        match e {
-            Error::Io(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Box::new(maybe_list_dir(&config.root_dir, &path).and_then(
-                        move |list_dir_resp| {
-                            trace!("using directory list extension");
-                            if let Some(f) = list_dir_resp {
-                                Either::A(future::ok(f))
-                            } else {
-                                Either::B(future::err(Error::from(e)))
-                            }
-                        },
-                    ))
-                } else {
-                    return Box::new(future::err(Error::from(e)));
+        return md_path_to_html(config, &path).await;
+    }
+
+    match resp {
+        // Leave redirects alone
+        Ok(resp) if resp.status() != StatusCode::OK => Ok(resp),
+        Ok(resp) => {
+            // The gallery's thumbnails are resized images
+            let resizes = extensions.contains(Extension::Resize)
+                || extensions.contains(Extension::Gallery) && query_has_param(req.uri(), "thumb");
+            if gallery::is_image(&path) && resizes {
+                if let Some(resize) = resize::Resize::from_uri(req.uri()) {
+                    trace!("using image resize extension");
+                    return resize::respond_with_resized(vfs, path, resize, resp).await;
+                }
+            }
+            // Only files that have a view depend on what was asked for, so
+            // only they vary by `Accept`
+            let view = match find_view(extensions, &path, file_ext) {
+                Some(view) if !query_has_param(req.uri(), "raw") && wants_html(req) => view,
+                _ => return Ok(resp),
+            };
+            match view {
+                View::Json => {
+                    trace!("using JSON viewer extension");
+                    json::respond_with_json(vfs, &path, resp).await
+                }
+                View::Table(delimiter) => {
+                    trace!("using table viewer extension");
+                    table::respond_with_table(vfs, &path, delimiter, resp).await
+                }
+                View::Source(syntax) => {
+                    trace!("using source viewer extension");
+                    source::respond_with_source(vfs, &path, syntax, resp).await
+                }
+                View::Hexdump => {
+                    trace!("using hex dump extension");
+                    let page = query_param(req.uri(), "page")
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(0);
+                    hexdump::respond_with_hexdump(vfs, &path, page, resp).await
                 }
             }
-            _ => {
-                return Box::new(future::err(e));
+        }
+        Err(Error::Io(e)) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                trace!("using directory list extension");
+                match maybe_list_dir(config, req, &path).await? {
+                    Some(resp) => Ok(resp),
+                    None => Err(Error::from(e)),
+                }
+            } else {
+                Err(Error::from(e))
             }
         }
-    } else {
-        Box::new(future::result(resp))
+        Err(e) => Err(e),
+    }
+}
+
+/// A page showing a file that isn't HTML, for browsers
+enum View {
+    Json,
+    Table(u8),
+    Source(&'static SyntaxReference),
+    Hexdump,
+}
+
+/// The view of a file, if it has one among the extensions
+fn find_view(extensions: Extensions, path: &Path, file_ext: &str) -> Option<View> {
+    if file_ext == "json" && extensions.contains(Extension::Json) {
+        return Some(View::Json);
+    }
+    if let Some(delimiter) = table::delimiter(file_ext) {
+        if extensions.contains(Extension::Table) {
+            return Some(View::Table(delimiter));
+        }
     }
+    if let Some(syntax) = source::find_syntax(path) {
+        if extensions.contains(Extension::Highlight) {
+            return Some(View::Source(syntax));
+        }
+    }
+    if hexdump::is_candidate(path) && extensions.contains(Extension::Hexdump) {
+        return Some(View::Hexdump);
+    }
+    None
+}
+
+/// Whether the request comes from a browser navigating to a page, as opposed
+/// to e.g. a `<script>` tag or `fetch` call. Renderings of non-HTML files must
+/// only be served to the former, or pages that load those files will break.
+pub fn wants_html(req: &Request<Incoming>) -> bool {
+    crate::vary::header(req, header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false)
+}
+
+/// Whether the URI's query string contains the named parameter, with or
+/// without a value, e.g. `?raw` or `?raw=1`.
+fn query_has_param(uri: &http::Uri, name: &str) -> bool {
+    query_param(uri, name).is_some()
 }
 
-fn md_path_to_html(path: &Path) -> impl Future<Item = Response<Body>, Error = Error> {
-    File::open(path.to_owned()).then(move |open_result| match open_result {
-        Ok(file) => Either::A(md_file_to_html(file)),
-        Err(e) => Either::B(future::err(Error::Io(e))),
+/// Get the value of the named query parameter. Parameters without a value
+/// have the empty string as their value.
+fn query_param<'a>(uri: &'a http::Uri, name: &str) -> Option<&'a str> {
+    uri.query()?.split('&').find_map(|pair| {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() == Some(name) {
+            Some(kv.next().unwrap_or(""))
+        } else {
+            None
+        }
     })
 }
 
-fn md_file_to_html(file: File) -> impl Future<Item = Response<Body>, Error = Error> {
-    let mut options = ComrakOptions::default();
+/// Re-read a file that an extension wants to render as text. Resolves to
+/// `None` if the file is larger than `max_len` or is not UTF-8, in which case
+/// the extension should fall back to the original response.
+async fn read_text_file(vfs: &dyn Vfs, path: &Path, max_len: usize) -> Result<Option<String>> {
+    let buf = super::vfs::read(vfs, path).await?;
+    if buf.len() > max_len {
+        debug!("file too large to render");
+        return Ok(None);
+    }
+    Ok(String::from_utf8(buf)
+        .map_err(|_| debug!("file is not UTF-8"))
+        .ok())
+}
+
+/// Re-read at most `max_len` bytes from the start of a file. Resolves to the
+/// bytes read and whether there was more of the file left unread.
+async fn read_file_prefix(vfs: &dyn Vfs, path: &Path, max_len: usize) -> Result<(Vec<u8>, bool)> {
+    let file = vfs.open(path).await?;
+    let mut buf = Vec::new();
+    file.take(max_len as u64 + 1).read_to_end(&mut buf).await?;
+    let truncated = buf.len() > max_len;
+    buf.truncate(max_len);
+    Ok((buf, truncated))
+}
+
+/// Render a markdown file, or reuse its page if it hasn't changed since it
+/// was last rendered.
+async fn md_path_to_html(config: &Config, path: &Path) -> Result<Response<Body>> {
+    let m = config.vfs.metadata(path).await?;
+    let version = render_cache::version((m.modified, m.len));
+    let kind = render_cache::Kind::Markdown;
+    let html = match config.renders.get(path, kind, version) {
+        Some(html) => html,
+        None => {
+            let file = config.vfs.open(path).await?;
+            let html = md_file_to_html(file).await?;
+            config.renders.insert(path, kind, version, html.clone());
+            html
+        }
+    };
+    super::html_str_to_response(html, StatusCode::OK)
+}
+
+async fn md_file_to_html(file: VfsFile) -> Result<String> {
     // be like GitHub
-    options.ext_autolink = true;
-    options.ext_header_ids = None;
-    options.ext_table = true;
-    options.ext_strikethrough = true;
-    options.ext_tagfilter = true;
-    options.ext_tasklist = true;
-    options.github_pre_lang = true;
-    options.ext_header_ids = Some("user-content-".to_string());
-
-    super::read_file(file)
-        .and_then(|s| String::from_utf8(s).map_err(|_| Error::MarkdownUtf8))
-        .and_then(move |s: String| {
-            let html = comrak::markdown_to_html(&s, &options);
-            let cfg = HtmlCfg {
-                title: String::new(),
-                body: html,
-            };
-            super::render_html(cfg)
-        })
-        .and_then(move |html| {
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_LENGTH, html.len() as u64)
-                .header(header::CONTENT_TYPE, mime::TEXT_HTML.as_ref())
-                .body(Body::from(html))
-                .map_err(Error::from)
-        })
-}
-
-fn maybe_list_dir(
+    let options = ComrakOptions {
+        ext_autolink: true,
+        ext_table: true,
+        ext_strikethrough: true,
+        ext_tagfilter: true,
+        ext_tasklist: true,
+        github_pre_lang: true,
+        ext_header_ids: Some("user-content-".to_string()),
+        ..ComrakOptions::default()
+    };
+
+    let buf = super::read_file(file).await?;
+    let s = String::from_utf8(buf).map_err(|_| Error::MarkdownUtf8)?;
+    let html = comrak::markdown_to_html(&s, &options);
+    let cfg = HtmlCfg {
+        title: String::new(),
+        body: html,
+    };
+    super::render_html(cfg)
+}
+
+async fn maybe_list_dir(
+    config: &Config,
+    req: &Request<Incoming>,
+    path: &Path,
+) -> Result<Option<Response<Body>>> {
+    let extensions = config.extensions;
+    let lists = extensions.contains(Extension::Listing) || extensions.contains(Extension::Gallery);
+    if !lists && !extensions.contains(Extension::Playlist) {
+        return Ok(None);
+    }
+    let m = config.vfs.metadata(path).await?;
+    if !m.is_dir {
+        return Ok(None);
+    }
+    if !listing_allowed(config, path) {
+        trace!("directory listing forbidden");
+        let lang = crate::i18n::for_request(config, req);
+        let format = negotiate::format(req);
+        let resp = crate::make_error_response_from_code(StatusCode::FORBIDDEN, format, lang)?;
+        return Ok(Some(resp));
+    }
+    let playlist = query_param(req.uri(), "playlist").and_then(playlist::Kind::from_param);
+    if let Some(kind) = playlist.filter(|_| extensions.contains(Extension::Playlist)) {
+        trace!("using playlist extension");
+        let resp = playlist::respond(&*config.vfs, &config.root_dir, req, path, kind).await?;
+        return Ok(Some(resp));
+    }
+    match negotiate::format(req) {
+        _ if !lists => Ok(None),
+        Format::Html => {
+            let view = ListView::for_request(config, req.uri());
+            let lang = crate::i18n::for_request(config, req);
+            list_dir(config, path, view, lang).await
+        }
+        Format::Json if extensions.contains(Extension::Listing) => {
+            list_dir_json(&*config.vfs, &config.root_dir, path).await
+        }
+        Format::Json => Ok(None),
+    }
+}
+
+/// Whether `--no-listings` allows a directory to be listed, in any view.
+fn listing_allowed(config: &Config, path: &Path) -> bool {
+    let prefixes = match &config.no_listings {
+        Some(prefixes) if prefixes.is_empty() => return false,
+        Some(prefixes) => prefixes,
+        None => return true,
+    };
+    let path = path.strip_prefix(&config.root_dir).unwrap_or(path);
+    !prefixes
+        .iter()
+        .any(|prefix| path.starts_with(prefix.trim_start_matches('/')))
+}
+
+/// How the request asked for a directory to be listed
+#[derive(Clone, Copy)]
+enum ListView {
+    List,
+    Gallery,
+    /// The gallery if the directory is mostly images, else the list
+    Auto,
+}
+
+impl ListView {
+    /// `?list` and `?gallery` override the configured default, when both
+    /// views are enabled.
+    fn for_request(config: &Config, uri: &http::Uri) -> ListView {
+        if !config.extensions.contains(Extension::Gallery) {
+            ListView::List
+        } else if !config.extensions.contains(Extension::Listing) {
+            ListView::Gallery
+        } else if query_has_param(uri, "list") {
+            ListView::List
+        } else if query_has_param(uri, "gallery") {
+            ListView::Gallery
+        } else if config.gallery {
+            ListView::Auto
+        } else {
+            ListView::List
+        }
+    }
+}
+
+/// List a directory, or reuse its page if its entries haven't changed since
+/// it was last listed in the language.
+async fn list_dir(
+    config: &Config,
+    path: &Path,
+    view: ListView,
+    lang: Lang,
+) -> Result<Option<Response<Body>>> {
+    let root_dir = &config.root_dir;
+    let up_dir = path.join("..");
+    let mut paths = config.vfs.read_dir(path).await?;
+    paths.sort();
+    let mostly_images = gallery::is_mostly_images(&paths);
+    let kind = match view {
+        ListView::Gallery => render_cache::Kind::Gallery,
+        ListView::Auto if mostly_images => render_cache::Kind::Gallery,
+        _ => render_cache::Kind::List,
+    };
+    let version = render_cache::version((&paths, lang));
+    if let Some(html) = config.renders.get(path, kind, version) {
+        return super::html_str_to_response(html, StatusCode::OK).map(Some);
+    }
+    // Link to the other views only if they're enabled
+    let extensions = config.extensions;
+    let list_view = extensions.contains(Extension::Listing);
+    let gallery_view = mostly_images && extensions.contains(Extension::Gallery);
+    let has_media = extensions.contains(Extension::Playlist)
+        && paths.iter().any(|path| crate::media::is_media(path));
+    let html = match kind {
+        render_cache::Kind::Gallery => {
+            gallery::make_gallery_body(root_dir, &paths, list_view, lang)?
+        }
+        _ => {
+            let paths = Some(up_dir).into_iter().chain(paths);
+            let paths: Vec<_> = paths.collect();
+            make_dir_list_body(root_dir, &paths, gallery_view, has_media, lang)?
+        }
+    };
+    config.renders.insert(path, kind, version, html.clone());
+    super::html_str_to_response(html, StatusCode::OK).map(Some)
+}
+
+/// List a directory as JSON, for scripts, with the URL of each entry and
+/// the size of each file.
+async fn list_dir_json(
+    vfs: &dyn Vfs,
     root_dir: &Path,
     path: &Path,
-) -> impl Future<Item = Option<Response<Body>>, Error = Error> {
-    let root_dir = root_dir.to_owned();
-    let path = path.to_owned();
-    fs::metadata(path.clone())
-        .map_err(Error::from)
-        .and_then(move |m| {
-            if m.is_dir() {
-                Either::A(list_dir(&root_dir, &path))
-            } else {
-                Either::B(future::ok(None))
+) -> Result<Option<Response<Body>>> {
+    let mut paths = vfs.read_dir(path).await?;
+    paths.sort();
+    let mut entries = Vec::new();
+    for path in paths {
+        let name = match path.file_name().and_then(OsStr::to_str) {
+            Some(name) => name,
+            None => {
+                warn!("non-unicode path: {}", path.display());
+                continue;
             }
-        })
-        .map_err(Error::from)
+        };
+        let url = path
+            .strip_prefix(root_dir)
+            .map_err(Error::StripPrefixInDirList)?;
+        let m = vfs.metadata(&path).await?;
+        entries.push(serde_json::json!({
+            "name": name,
+            "url": format!("/{}", url.display()),
+            "dir": m.is_dir,
+            "size": if m.is_dir { None } else { Some(m.len) },
+        }));
+    }
+    let listing = serde_json::json!({ "entries": entries });
+    crate::json_to_response(&listing, StatusCode::OK).map(Some)
 }
 
-fn list_dir(
+fn make_dir_list_body(
     root_dir: &Path,
-    path: &Path,
-) -> impl Future<Item = Option<Response<Body>>, Error = Error> {
-    let root_dir = root_dir.to_owned();
-    let up_dir = path.join("..");
-    fs::read_dir(path.to_owned())
-        .map_err(Error::from)
-        .and_then(move |read_dir| {
-            let root_dir = root_dir.to_owned();
-            read_dir
-                .collect()
-                .map_err(Error::from)
-                .and_then(move |dents| {
-                    let paths = dents.iter().map(DirEntry::path);
-                    let paths = Some(up_dir).into_iter().chain(paths);
-                    let paths: Vec<_> = paths.collect();
-                    make_dir_list_body(&root_dir, &paths).map_err(Error::from)
-                })
-                .and_then(|html| super::html_str_to_response(html, StatusCode::OK).map(Some))
-        })
-}
-
-fn make_dir_list_body(root_dir: &Path, paths: &[PathBuf]) -> Result<String> {
+    paths: &[PathBuf],
+    gallery_view: bool,
+    has_media: bool,
+    lang: Lang,
+) -> Result<String> {
     let mut buf = String::new();
 
+    if gallery_view {
+        let gallery_view = lang.text(Text::GalleryView);
+        writeln!(buf, "<p><a href='?gallery'>{}</a></p>", gallery_view)
+            .map_err(Error::WriteInDirList)?;
+    }
+    if has_media {
+        let playlist = lang.text(Text::Playlist);
+        writeln!(buf, "<p><a href='?playlist=m3u8'>{}</a></p>", playlist)
+            .map_err(Error::WriteInDirList)?;
+    }
+
     writeln!(buf, "<div>").map_err(Error::WriteInDirList)?;
 
     let dot_dot = OsStr::new("..");
@@ -0,0 +1,94 @@
+//! Browsing inside zip and tar archives, for requests like
+//! `/bundle.zip/inner/path.txt`, without extracting them to disk.
+//!
+//! Archives are kept open between requests, so that a page with many files
+//! from one archive doesn't index it, or decompress it, for each of them.
+
+use crate::vfs::Vfs;
+use crate::{archive, Body, Config, Result};
+use http::{Request, Response};
+use hyper::body::Incoming;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The cache is emptied when it holds more archives than this.
+const MAX_ARCHIVES: usize = 16;
+
+/// The archives opened for serving files from inside them
+#[derive(Default)]
+pub struct ArchiveCache {
+    archives: Mutex<HashMap<PathBuf, Opened>>,
+}
+
+/// An archive, with the modification time and length it had when it was
+/// opened, so that it's opened again when it changes
+struct Opened {
+    version: (Option<SystemTime>, u64),
+    vfs: Arc<dyn Vfs>,
+}
+
+impl ArchiveCache {
+    async fn open(&self, path: PathBuf) -> Result<Arc<dyn Vfs>> {
+        let metadata = tokio::fs::metadata(&path).await?;
+        let version = (metadata.modified().ok(), metadata.len());
+        if let Some(opened) = self.archives.lock().unwrap().get(&path) {
+            if opened.version == version {
+                trace!("archive cache hit: {}", path.display());
+                return Ok(opened.vfs.clone());
+            }
+        }
+
+        let archive_path = path.clone();
+        let vfs = crate::blocking(move || archive::open(&archive_path)).await?;
+        let mut archives = self.archives.lock().unwrap();
+        if archives.len() >= MAX_ARCHIVES {
+            debug!("archive cache full, clearing");
+            archives.clear();
+        }
+        let opened = Opened {
+            version,
+            vfs: vfs.clone(),
+        };
+        archives.insert(path, opened);
+        Ok(vfs)
+    }
+
+    pub fn purge(&self) {
+        self.archives.lock().unwrap().clear();
+    }
+}
+
+/// Find the archive that a local path that couldn't be opened leads into, if
+/// any, by looking for an archive file among the path and its ancestors.
+pub fn find_archive(root_dir: &Path, path: &Path) -> Option<PathBuf> {
+    for archive in path.ancestors() {
+        if !archive.starts_with(root_dir) || archive == root_dir {
+            break;
+        }
+        if !archive::is_archive(archive) {
+            continue;
+        }
+        // Drop any trailing "/", which would stop the archive being a file
+        let archive: PathBuf = archive.components().collect();
+        if archive.is_file() {
+            return Some(archive);
+        }
+    }
+    None
+}
+
+/// Serve a file from within the archive, as if the archive were a directory,
+/// including with the other extensions.
+pub async fn serve_entry(
+    config: Config,
+    req: Request<Incoming>,
+    path: PathBuf,
+    archive_path: PathBuf,
+) -> Result<Response<Body>> {
+    let vfs = config.archives.open(archive_path).await?;
+    let config = Config { vfs, ..config };
+    let resp = crate::serve_path(&config, &req, path.clone()).await;
+    super::serve_file(&config, &req, path, resp).await
+}
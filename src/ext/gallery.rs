@@ -0,0 +1,159 @@
+//! Gallery view of directories, rendering images as a grid of thumbnails that
+//! open in a lightbox.
+
+use crate::i18n::{Lang, Text};
+use crate::{Error, HtmlCfg, Result};
+use handlebars::html_escape;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+const IMAGE_EXTENSIONS: &[&str] = &["bmp", "gif", "ico", "jpeg", "jpg", "png", "svg", "webp"];
+
+static GALLERY_STYLE: &str = "<style type=\"text/css\">
+    .gallery { display: grid; grid-template-columns: repeat(auto-fill, minmax(150px, 1fr)); gap: 1ch; }
+    .gallery figure { margin: 0; text-align: center; overflow: hidden; }
+    .gallery img { width: 150px; height: 150px; object-fit: cover; cursor: zoom-in; }
+    .gallery figcaption { font-size: small; white-space: nowrap; overflow: hidden; text-overflow: ellipsis; }
+    .lightbox { display: none; position: fixed; inset: 0; background: rgba(0, 0, 0, 0.9); }
+    .lightbox.open { display: flex; align-items: center; justify-content: center; }
+    .lightbox img { max-width: 95vw; max-height: 95vh; }
+    .lightbox button { position: fixed; top: 50%; font-size: 3em; color: white; background: none; border: none; cursor: pointer; }
+    .lightbox .prev { left: 1ch; }
+    .lightbox .next { right: 1ch; }
+</style>";
+
+/// Open images in the lightbox, stepping through them with the buttons or the
+/// arrow keys, and closing it with escape or a click on the backdrop.
+static GALLERY_SCRIPT: &str = "<script>
+    (function () {
+        var images = Array.from(document.querySelectorAll('.gallery img'));
+        var lightbox = document.querySelector('.lightbox');
+        var current = 0;
+        function show(i) {
+            current = (i + images.length) % images.length;
+            lightbox.querySelector('img').src = images[current].dataset.full;
+            lightbox.classList.add('open');
+        }
+        function close() {
+            lightbox.classList.remove('open');
+        }
+        images.forEach(function (img, i) {
+            img.addEventListener('click', function (e) {
+                e.preventDefault();
+                show(i);
+            });
+        });
+        lightbox.querySelector('.prev').addEventListener('click', function (e) {
+            e.stopPropagation();
+            show(current - 1);
+        });
+        lightbox.querySelector('.next').addEventListener('click', function (e) {
+            e.stopPropagation();
+            show(current + 1);
+        });
+        lightbox.addEventListener('click', close);
+        document.addEventListener('keydown', function (e) {
+            if (!lightbox.classList.contains('open')) return;
+            if (e.key === 'ArrowLeft') show(current - 1);
+            else if (e.key === 'ArrowRight') show(current + 1);
+            else if (e.key === 'Escape') close();
+        });
+    })();
+</script>";
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Whether more than half of the directory entries are images, in which case
+/// a gallery is likely more useful than a list.
+pub fn is_mostly_images(paths: &[PathBuf]) -> bool {
+    let images = paths.iter().filter(|p| is_image(p)).count();
+    images * 2 > paths.len()
+}
+
+/// Render a directory as a grid of its images. Other entries are listed below
+/// the grid, so that the gallery can still be navigated.
+pub fn make_gallery_body(
+    root_dir: &Path,
+    paths: &[PathBuf],
+    list_view: bool,
+    lang: Lang,
+) -> Result<String> {
+    let mut paths = paths.to_vec();
+    paths.sort();
+    let (images, others): (Vec<_>, Vec<_>) = paths.iter().partition(|p| is_image(p));
+
+    let mut buf = String::new();
+
+    writeln!(buf, "{}", GALLERY_STYLE).map_err(Error::WriteInGallery)?;
+    if list_view {
+        writeln!(
+            buf,
+            "<p><a href='..'>..</a> · <a href='?list'>{}</a></p>",
+            lang.text(Text::ListView)
+        )
+        .map_err(Error::WriteInGallery)?;
+    } else {
+        writeln!(buf, "<p><a href='..'>..</a></p>").map_err(Error::WriteInGallery)?;
+    }
+    writeln!(buf, "<div class='gallery'>").map_err(Error::WriteInGallery)?;
+    for path in images {
+        let (url, name) = match url_and_name(root_dir, path)? {
+            Some(u) => u,
+            None => continue,
+        };
+        writeln!(
+            buf,
+            "<figure><a href='{url}'><img src='{url}?thumb' data-full='{url}' alt='{name}' loading='lazy'></a><figcaption>{name}</figcaption></figure>",
+            url = url,
+            name = name
+        )
+        .map_err(Error::WriteInGallery)?;
+    }
+    writeln!(buf, "</div>").map_err(Error::WriteInGallery)?;
+
+    if !others.is_empty() {
+        writeln!(buf, "<div>").map_err(Error::WriteInGallery)?;
+        for path in others {
+            if let Some((url, name)) = url_and_name(root_dir, path)? {
+                writeln!(buf, "<div><a href='{}'>{}</a></div>", url, name)
+                    .map_err(Error::WriteInGallery)?;
+            }
+        }
+        writeln!(buf, "</div>").map_err(Error::WriteInGallery)?;
+    }
+
+    writeln!(
+        buf,
+        "<div class='lightbox'><button class='prev'>&lsaquo;</button><img alt=''><button class='next'>&rsaquo;</button></div>"
+    )
+    .map_err(Error::WriteInGallery)?;
+    writeln!(buf, "{}", GALLERY_SCRIPT).map_err(Error::WriteInGallery)?;
+
+    crate::render_html(HtmlCfg {
+        title: String::new(),
+        body: buf,
+    })
+}
+
+/// Get the absolute URL and the escaped display name of a directory entry.
+fn url_and_name(root_dir: &Path, path: &Path) -> Result<Option<(String, String)>> {
+    let full_url = path
+        .strip_prefix(root_dir)
+        .map_err(Error::StripPrefixInDirList)?;
+    match path.file_name().and_then(OsStr::to_str) {
+        Some(file_name) => Ok(Some((
+            html_escape(&format!("/{}", full_url.display())),
+            html_escape(file_name),
+        ))),
+        None => {
+            warn!("non-unicode path: {}", path.display());
+            Ok(None)
+        }
+    }
+}
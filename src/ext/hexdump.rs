@@ -0,0 +1,147 @@
+//! Hex dump viewer, rendering binary files of unknown type as pages of hex and
+//! ASCII instead of as garbled text.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, HtmlCfg, Result};
+use http::{Response, StatusCode};
+use std::fmt::Write;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+const BYTES_PER_ROW: usize = 16;
+
+const BYTES_PER_PAGE: usize = BYTES_PER_ROW * 256;
+
+/// Files are sniffed for binary content within this many leading bytes.
+const SNIFF_LEN: usize = 8192;
+
+static HEXDUMP_STYLE: &str = "<style type=\"text/css\">
+    .hexdump { overflow-x: auto; }
+    .hexdump table { border-collapse: collapse; font-family: monospace; white-space: pre; }
+    .hexdump td { padding: 0 1ch; }
+    .hexdump td.offset a { color: #999; text-decoration: none; }
+    .hexdump td.ascii { color: #555; }
+    .hexdump tr:target { background-color: #fffbdd; }
+</style>";
+
+/// Whether a file might be rendered as a hex dump. Only files that the server
+/// doesn't otherwise know the type of are considered.
+pub fn is_candidate(path: &Path) -> bool {
+    crate::file_path_mime(path) == mime::TEXT_PLAIN
+}
+
+/// Sniff the file, and if it is binary render the requested page as a hex
+/// dump. Text files are served unchanged.
+pub async fn respond_with_hexdump(
+    vfs: &dyn Vfs,
+    path: &Path,
+    page: usize,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    let metadata = vfs.metadata(path).await?;
+    let (sniffed, _) = super::read_file_prefix(vfs, path, SNIFF_LEN).await?;
+    if !is_binary(&sniffed) {
+        return Ok(resp);
+    }
+
+    let pages = (metadata.len as usize).div_ceil(BYTES_PER_PAGE).max(1);
+    let page = page.min(pages - 1);
+    let start = page * BYTES_PER_PAGE;
+    let buf = read_page(vfs, path, start as u64).await?;
+    let html = render_hexdump(path, &buf, start, page, pages)?;
+    crate::html_str_to_response(html, StatusCode::OK)
+}
+
+/// Read the page of the file starting at `start`. Files can't be seeked
+/// within, so the bytes before the page are read and thrown away.
+async fn read_page(vfs: &dyn Vfs, path: &Path, start: u64) -> Result<Vec<u8>> {
+    let mut file = vfs.open(path).await?;
+    tokio::io::copy(&mut (&mut file).take(start), &mut tokio::io::sink()).await?;
+    let mut buf = Vec::with_capacity(BYTES_PER_PAGE);
+    file.take(BYTES_PER_PAGE as u64)
+        .read_to_end(&mut buf)
+        .await?;
+    Ok(buf)
+}
+
+/// Like git, consider a file binary if it contains a NUL byte. Failing that,
+/// it is binary if it isn't UTF-8, ignoring a character possibly cut in half
+/// at the end of the sniffed bytes.
+fn is_binary(buf: &[u8]) -> bool {
+    if buf.contains(&0) {
+        return true;
+    }
+    match std::str::from_utf8(buf) {
+        Ok(_) => false,
+        Err(e) => e.error_len().is_some(),
+    }
+}
+
+fn render_hexdump(
+    path: &Path,
+    buf: &[u8],
+    start: usize,
+    page: usize,
+    pages: usize,
+) -> Result<String> {
+    let mut html = String::new();
+
+    writeln!(html, "{}", HEXDUMP_STYLE).map_err(Error::WriteInHexdump)?;
+    write!(html, "<p><a href='?raw' download>raw</a>").map_err(Error::WriteInHexdump)?;
+    if pages > 1 {
+        write!(html, " · page {} of {}", page + 1, pages).map_err(Error::WriteInHexdump)?;
+        if page > 0 {
+            write!(html, " · <a href='?page={}'>previous</a>", page - 1)
+                .map_err(Error::WriteInHexdump)?;
+        }
+        if page + 1 < pages {
+            write!(html, " · <a href='?page={}'>next</a>", page + 1)
+                .map_err(Error::WriteInHexdump)?;
+        }
+    }
+    writeln!(html, "</p>").map_err(Error::WriteInHexdump)?;
+    writeln!(html, "<div class='hexdump'><table>").map_err(Error::WriteInHexdump)?;
+
+    for (i, row) in buf.chunks(BYTES_PER_ROW).enumerate() {
+        let offset = start + i * BYTES_PER_ROW;
+        write_row(&mut html, offset, row).map_err(Error::WriteInHexdump)?;
+    }
+
+    writeln!(html, "</table></div>").map_err(Error::WriteInHexdump)?;
+
+    let title = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    crate::render_html(HtmlCfg { title, body: html })
+}
+
+/// Write a row of the dump, carrying an `o<offset>` id so that fragments like
+/// `#o00000120` link to individual rows.
+fn write_row(html: &mut String, offset: usize, row: &[u8]) -> std::fmt::Result {
+    write!(
+        html,
+        "<tr id='o{offset:08x}'><td class='offset'><a href='#o{offset:08x}'>{offset:08x}</a></td><td>",
+        offset = offset
+    )?;
+    for i in 0..BYTES_PER_ROW {
+        match row.get(i) {
+            Some(b) => write!(html, "{:02x} ", b)?,
+            None => write!(html, "   ")?,
+        }
+        if i == BYTES_PER_ROW / 2 - 1 {
+            write!(html, " ")?;
+        }
+    }
+    write!(html, "</td><td class='ascii'>")?;
+    for &b in row {
+        match b {
+            b'<' => write!(html, "&lt;")?,
+            b'>' => write!(html, "&gt;")?,
+            b'&' => write!(html, "&amp;")?,
+            b' '..=b'~' => html.push(b as char),
+            _ => html.push('.'),
+        }
+    }
+    writeln!(html, "</td></tr>")
+}
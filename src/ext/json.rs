@@ -0,0 +1,116 @@
+//! JSON viewer, rendering JSON files as a collapsible tree.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, HtmlCfg, Result};
+use handlebars::html_escape;
+use http::{Response, StatusCode};
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Files larger than this are served raw.
+const MAX_JSON_LEN: usize = 4 * 1024 * 1024;
+
+static JSON_STYLE: &str = "<style type=\"text/css\">
+    .json { font-family: monospace; overflow-x: auto; }
+    .json ul { list-style: none; margin: 0; padding-left: 2ch; }
+    .json details > summary { cursor: pointer; }
+    .json details:not([open]) > summary::after { content: \" \\2026 \"; }
+    .json .count { color: #999; }
+    .json .key { color: #63a35c; }
+    .json .string { color: #183691; }
+    .json .number { color: #0086b3; }
+    .json .bool, .json .null { color: #a71d5d; }
+</style>";
+
+/// Re-read a JSON file and render it as an HTML tree. Files that can't be
+/// parsed are served unchanged.
+pub async fn respond_with_json(
+    vfs: &dyn Vfs,
+    path: &Path,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    let src = super::read_text_file(vfs, path, MAX_JSON_LEN).await?;
+    let value = src.and_then(|src| {
+        serde_json::from_str::<Value>(&src)
+            .map_err(|e| debug!("not rendering invalid JSON: {}", e))
+            .ok()
+    });
+    match value {
+        Some(value) => {
+            let html = render_json(path, &value)?;
+            crate::html_str_to_response(html, StatusCode::OK)
+        }
+        None => Ok(resp),
+    }
+}
+
+fn render_json(path: &Path, value: &Value) -> Result<String> {
+    let mut buf = String::new();
+
+    writeln!(buf, "{}", JSON_STYLE).map_err(Error::WriteInJson)?;
+    writeln!(buf, "<p><a href='?raw'>raw</a></p>").map_err(Error::WriteInJson)?;
+    write!(buf, "<div class='json'>").map_err(Error::WriteInJson)?;
+    write_value(&mut buf, value).map_err(Error::WriteInJson)?;
+    writeln!(buf, "</div>").map_err(Error::WriteInJson)?;
+
+    let title = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    crate::render_html(HtmlCfg { title, body: buf })
+}
+
+/// Write a JSON value as HTML. Non-empty arrays and objects become `<details>`
+/// elements, so that the browser takes care of expanding and collapsing them.
+fn write_value(buf: &mut String, value: &Value) -> std::fmt::Result {
+    match value {
+        Value::Null => write!(buf, "<span class='null'>null</span>"),
+        Value::Bool(b) => write!(buf, "<span class='bool'>{}</span>", b),
+        Value::Number(n) => write!(buf, "<span class='number'>{}</span>", n),
+        Value::String(s) => write!(buf, "<span class='string'>{}</span>", quote(s)),
+        Value::Array(a) if a.is_empty() => write!(buf, "[]"),
+        Value::Object(o) if o.is_empty() => write!(buf, "{{}}"),
+        Value::Array(a) => {
+            write_open(buf, "[", a.len(), "item")?;
+            for (i, v) in a.iter().enumerate() {
+                write!(buf, "<li>")?;
+                write_value(buf, v)?;
+                write_comma(buf, i, a.len())?;
+                write!(buf, "</li>")?;
+            }
+            write!(buf, "</ul>]</details>")
+        }
+        Value::Object(o) => {
+            write_open(buf, "{", o.len(), "key")?;
+            for (i, (k, v)) in o.iter().enumerate() {
+                write!(buf, "<li><span class='key'>{}</span>: ", quote(k))?;
+                write_value(buf, v)?;
+                write_comma(buf, i, o.len())?;
+                write!(buf, "</li>")?;
+            }
+            write!(buf, "</ul>}}</details>")
+        }
+    }
+}
+
+fn write_open(buf: &mut String, bracket: &str, len: usize, noun: &str) -> std::fmt::Result {
+    let plural = if len == 1 { "" } else { "s" };
+    write!(
+        buf,
+        "<details open><summary>{} <span class='count'>{} {}{}</span></summary><ul>",
+        bracket, len, noun, plural
+    )
+}
+
+fn write_comma(buf: &mut String, i: usize, len: usize) -> std::fmt::Result {
+    if i + 1 < len {
+        write!(buf, ",")?;
+    }
+    Ok(())
+}
+
+/// Quote a string as JSON does, escaped for HTML.
+fn quote(s: &str) -> String {
+    html_escape(&Value::String(s.to_string()).to_string())
+}
@@ -0,0 +1,84 @@
+//! Playlists of the audio and video files in a directory, at
+//! `?playlist=m3u8` or `?playlist=m3u`, so that the directory can be opened
+//! in a player like VLC. The URLs in them are absolute, since the playlist
+//! may be saved and opened from elsewhere.
+
+use crate::media;
+use crate::vfs::Vfs;
+use crate::{Body, Error, Result};
+use http::{header, Request, Response, StatusCode};
+use std::fmt::Write;
+use std::path::{Component, Path};
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    M3u,
+    /// M3U in UTF-8
+    M3u8,
+}
+
+impl Kind {
+    /// The kind asked for with the `playlist` parameter, which is M3U8 if
+    /// it has no value
+    pub fn from_param(value: &str) -> Option<Kind> {
+        match value {
+            "" | "m3u8" => Some(Kind::M3u8),
+            "m3u" => Some(Kind::M3u),
+            _ => None,
+        }
+    }
+}
+
+pub async fn respond<B>(
+    vfs: &dyn Vfs,
+    root_dir: &Path,
+    req: &Request<B>,
+    path: &Path,
+    kind: Kind,
+) -> Result<Response<Body>> {
+    // Without a host to make URLs absolute with, they're at least absolute
+    // paths
+    let origin = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("http://{}", host))
+        .unwrap_or_default();
+
+    let mut paths = vfs.read_dir(path).await?;
+    paths.sort();
+    let mut buf = String::from("#EXTM3U\n");
+    for path in paths.iter().filter(|path| media::is_media(path)) {
+        let url_path = path
+            .strip_prefix(root_dir)
+            .map_err(Error::StripPrefixInDirList)?;
+        let mut url = origin.clone();
+        for component in url_path.components() {
+            if let Component::Normal(name) = component {
+                url.push('/');
+                url.push_str(&crate::percent_encode(&name.to_string_lossy()));
+            }
+        }
+        let title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        writeln!(buf, "#EXTINF:-1,{}\n{}", title, url).map_err(Error::WriteInDirList)?;
+    }
+
+    let (content_type, extension) = match kind {
+        Kind::M3u => ("audio/x-mpegurl", "m3u"),
+        Kind::M3u8 => ("audio/x-mpegurl; charset=utf-8", "m3u8"),
+    };
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, buf.len())
+        .header(header::CONTENT_TYPE, content_type);
+    // Saved, the playlist is named after the directory
+    let name = path.file_name().unwrap_or_else(|| "playlist".as_ref());
+    let file_name = format!("{}.{}", name.to_string_lossy(), extension);
+    if let Some(disposition) = crate::content_disposition(Path::new(&file_name)) {
+        builder = builder.header(header::CONTENT_DISPOSITION, disposition);
+    }
+    builder.body(Body::from(buf)).map_err(Error::from)
+}
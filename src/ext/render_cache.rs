@@ -0,0 +1,81 @@
+//! Remembering the pages the extensions render, so that viewing a large
+//! markdown document, or a big directory, again doesn't render it again.
+//! Markdown pages are remembered for their file's modification time and
+//! length, and listings for a hash of the directory's entries, both along
+//! with the templates they were rendered with, so changes to any of them
+//! are shown on the next view.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The cache is emptied when it holds more than this many bytes of pages.
+const MAX_LEN: usize = 32 << 20;
+
+/// What a page shows of its path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Markdown,
+    List,
+    Gallery,
+}
+
+#[derive(Default)]
+pub struct RenderCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// The latest page for each path and kind, with the version of the file
+    /// or directory it was rendered from
+    pages: HashMap<(PathBuf, Kind), (u64, String)>,
+    len: usize,
+}
+
+impl RenderCache {
+    /// The page for this version of the path, if it was rendered before
+    pub fn get(&self, path: &Path, kind: Kind, version: u64) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        match inner.pages.get(&(path.to_owned(), kind)) {
+            Some((v, page)) if *v == version => {
+                trace!("render cache hit: {}", path.display());
+                Some(page.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, path: &Path, kind: Kind, version: u64, page: String) {
+        if page.len() > MAX_LEN {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.len + page.len() > MAX_LEN {
+            debug!("render cache full, clearing");
+            inner.pages.clear();
+            inner.len = 0;
+        }
+        inner.len += page.len();
+        if let Some((_, old)) = inner.pages.insert((path.to_owned(), kind), (version, page)) {
+            inner.len -= old.len();
+        }
+    }
+
+    pub fn purge(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pages.clear();
+        inner.len = 0;
+    }
+}
+
+/// The version of a page, from whatever changes when it does, and the
+/// templates it's rendered with
+pub fn version(of: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    of.hash(&mut hasher);
+    crate::template::generation().hash(&mut hasher);
+    hasher.finish()
+}
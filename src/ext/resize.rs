@@ -0,0 +1,210 @@
+//! Server-side image resizing, for `?w=400&h=300&fit=cover` requests, and for
+//! `?thumb` requests from the gallery, so that it doesn't have to download
+//! every full-size image.
+
+use crate::vfs::{self, Vfs};
+use crate::{Body, Error, Result};
+use http::{header, Response, StatusCode, Uri};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Thumbnails fit within a square of this many pixels.
+const THUMBNAIL_SIZE: u32 = 300;
+
+/// Requests for images larger than this in either dimension are refused, so
+/// that a typo can't exhaust the server's memory.
+const MAX_DIMENSION: u32 = 8192;
+
+/// Images larger than this are served unchanged rather than decoded.
+const MAX_IMAGE_LEN: u64 = 64 * 1024 * 1024;
+
+/// The cache is emptied when its images add up to more than this.
+const MAX_CACHE_LEN: usize = 64 * 1024 * 1024;
+
+/// How an image is made to fit the requested size
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Fit {
+    /// Scale to fit within the size, preserving the aspect ratio
+    Contain,
+    /// Scale to cover the size, preserving the aspect ratio, and crop the rest
+    Cover,
+    /// Stretch to exactly the size
+    Fill,
+}
+
+/// The size requested for an image. A missing dimension is derived from the
+/// other one and the image's aspect ratio.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Resize {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: Fit,
+}
+
+impl Resize {
+    /// Get the resize requested by the URI's query string, if any. Invalid
+    /// requests are ignored, and the image served unchanged.
+    pub fn from_uri(uri: &Uri) -> Option<Resize> {
+        if super::query_has_param(uri, "thumb") {
+            return Some(Resize {
+                width: Some(THUMBNAIL_SIZE),
+                height: Some(THUMBNAIL_SIZE),
+                fit: Fit::Contain,
+            });
+        }
+
+        let dimension = |name| {
+            super::query_param(uri, name).and_then(|v| match v.parse() {
+                Ok(n) if n > 0 && n <= MAX_DIMENSION => Some(n),
+                _ => {
+                    debug!("ignoring invalid image {}: {}", name, v);
+                    None
+                }
+            })
+        };
+        let width = dimension("w");
+        let height = dimension("h");
+        if width.is_none() && height.is_none() {
+            return None;
+        }
+        let fit = match super::query_param(uri, "fit") {
+            None | Some("contain") => Fit::Contain,
+            Some("cover") => Fit::Cover,
+            Some("fill") => Fit::Fill,
+            Some(fit) => {
+                debug!("ignoring invalid image fit: {}", fit);
+                return None;
+            }
+        };
+
+        Some(Resize { width, height, fit })
+    }
+
+    fn apply(self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(img.height(), w, img.width())),
+            (None, Some(h)) => (scale(img.width(), h, img.height()), h),
+            (None, None) => return img.clone(),
+        };
+        let filter = FilterType::Triangle;
+        match self.fit {
+            Fit::Contain => img.resize(width, height, filter),
+            Fit::Cover => img.resize_to_fill(width, height, filter),
+            Fit::Fill => img.resize_exact(width, height, filter),
+        }
+    }
+}
+
+/// Scale `len` by `to / from`, without letting it reach zero.
+fn scale(len: u32, to: u32, from: u32) -> u32 {
+    let scaled = u64::from(len) * u64::from(to) / u64::from(from.max(1));
+    (scaled as u32).clamp(1, MAX_DIMENSION)
+}
+
+#[derive(Clone)]
+struct Resized {
+    data: Vec<u8>,
+    mime_type: mime::Mime,
+}
+
+/// Resized images keyed by image path, modification time and size, so that
+/// editing an image creates a fresh copy.
+type Key = (PathBuf, Option<SystemTime>, Resize);
+
+#[derive(Default)]
+struct Cache {
+    images: HashMap<Key, Resized>,
+    len: usize,
+}
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Respond with the resized image, from the cache if possible. Images that
+/// can't be decoded, like SVGs, are served unchanged.
+pub async fn respond_with_resized(
+    vfs: &dyn Vfs,
+    path: PathBuf,
+    resize: Resize,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    let metadata = vfs.metadata(&path).await?;
+    if metadata.len > MAX_IMAGE_LEN {
+        debug!("image too large to resize");
+        return Ok(resp);
+    }
+    let key = (path, metadata.modified, resize);
+
+    let cached = cache().lock().unwrap().images.get(&key).cloned();
+    let resized = match cached {
+        Some(resized) => resized,
+        None => {
+            let data = vfs::read(vfs, &key.0).await?;
+            match crate::blocking(move || make_resized(&data, resize)).await? {
+                Some(resized) => {
+                    insert(key, resized.clone());
+                    resized
+                }
+                None => return Ok(resp),
+            }
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, resized.data.len() as u64)
+        .header(header::CONTENT_TYPE, resized.mime_type.as_ref())
+        .body(Body::from(resized.data))
+        .map_err(Error::from)
+}
+
+/// Decode and resize the image. Images with transparency become PNGs, and
+/// everything else JPEGs, which are much smaller for photos.
+fn make_resized(data: &[u8], resize: Resize) -> Result<Option<Resized>> {
+    let img = match image::load_from_memory(data) {
+        Ok(img) => img,
+        Err(e) => {
+            debug!("not resizing undecodable image: {}", e);
+            return Ok(None);
+        }
+    };
+    let resized = resize.apply(&img);
+
+    let mut data = Cursor::new(Vec::new());
+    let mime_type = if resized.color().has_alpha() {
+        resized
+            .write_to(&mut data, ImageFormat::Png)
+            .map_err(Error::ImageEncode)?;
+        mime::IMAGE_PNG
+    } else {
+        DynamicImage::ImageRgb8(resized.to_rgb8())
+            .write_to(&mut data, ImageFormat::Jpeg)
+            .map_err(Error::ImageEncode)?;
+        mime::IMAGE_JPEG
+    };
+
+    Ok(Some(Resized {
+        data: data.into_inner(),
+        mime_type,
+    }))
+}
+
+fn insert(key: Key, resized: Resized) {
+    let mut cache = cache().lock().unwrap();
+    if cache.len + resized.data.len() > MAX_CACHE_LEN {
+        debug!("resized image cache full, clearing");
+        *cache = Cache::default();
+    }
+    cache.len += resized.data.len();
+    if let Some(old) = cache.images.insert(key, resized) {
+        cache.len -= old.data.len();
+    }
+}
@@ -0,0 +1,121 @@
+//! Source code viewer, rendering source files as syntax-highlighted HTML with
+//! line numbers.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, HtmlCfg, Result};
+use http::{Response, StatusCode};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+/// Files larger than this are served raw; highlighting them is slow and the
+/// resulting page unwieldy.
+const MAX_SOURCE_LEN: usize = 1024 * 1024;
+
+/// Extensions that syntect knows about, but that browsers have a better way
+/// to display.
+const EXCLUDED_EXTENSIONS: &[&str] = &["htm", "html", "md", "svg"];
+
+static SOURCE_STYLE: &str = "<style type=\"text/css\">
+    .source { overflow-x: auto; }
+    .source table { border-collapse: collapse; font-family: monospace; }
+    .source td.line-number { text-align: right; padding-right: 2ch; user-select: none; }
+    .source td.line-number a { color: #999; text-decoration: none; }
+    .source td.line { white-space: pre; }
+    .source tr:target { background-color: #fffbdd; }
+</style>";
+
+/// Loading the syntax definitions is expensive, so do it once. These are the
+/// syntaxes that `bat` uses, which are a superset of syntect's defaults and
+/// include e.g. TOML.
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(two_face::syntax::extra_no_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        themes
+            .remove("InspiredGitHub")
+            .expect("syntect default theme")
+    })
+}
+
+/// Find the syntax used to highlight a file, if it is a source file that the
+/// viewer should render. Files are matched by extension, then by full file
+/// name (for e.g. "Makefile").
+pub fn find_syntax(path: &Path) -> Option<&'static SyntaxReference> {
+    let ss = syntax_set();
+    let file_ext = path.extension().and_then(OsStr::to_str);
+    if let Some(file_ext) = file_ext {
+        if EXCLUDED_EXTENSIONS.contains(&file_ext) {
+            return None;
+        }
+    }
+    let file_name = path.file_name().and_then(OsStr::to_str);
+    file_ext
+        .and_then(|e| ss.find_syntax_by_extension(e))
+        .or_else(|| file_name.and_then(|n| ss.find_syntax_by_extension(n)))
+        .filter(|syntax| syntax.name != "Plain Text")
+}
+
+/// Re-read a source file and render it as highlighted HTML. If the file turns
+/// out not to be suitable for display, e.g. because it is not UTF-8, then the
+/// original response is returned unchanged.
+pub async fn respond_with_source(
+    vfs: &dyn Vfs,
+    path: &Path,
+    syntax: &'static SyntaxReference,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    match super::read_text_file(vfs, path, MAX_SOURCE_LEN).await? {
+        Some(src) => {
+            let html = render_source(path, syntax, &src)?;
+            crate::html_str_to_response(html, StatusCode::OK)
+        }
+        None => Ok(resp),
+    }
+}
+
+/// Render the source as a table with one row per line, each row carrying an
+/// `L<n>` id so that fragments like `#L42` link to individual lines.
+fn render_source(path: &Path, syntax: &SyntaxReference, src: &str) -> Result<String> {
+    let ss = syntax_set();
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let mut buf = String::new();
+
+    writeln!(buf, "{}", SOURCE_STYLE).map_err(Error::WriteInSource)?;
+    writeln!(buf, "<p><a href='?raw'>raw</a></p>").map_err(Error::WriteInSource)?;
+    writeln!(buf, "<div class='source'><table>").map_err(Error::WriteInSource)?;
+
+    for (i, line) in src.lines().enumerate() {
+        let regions = highlighter
+            .highlight_line(line, ss)
+            .map_err(Error::Highlight)?;
+        let html = styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+            .map_err(Error::Highlight)?;
+        let n = i + 1;
+        writeln!(
+            buf,
+            "<tr id='L{n}'><td class='line-number'><a href='#L{n}'>{n}</a></td><td class='line'>{html}</td></tr>",
+            n = n,
+            html = html
+        )
+        .map_err(Error::WriteInSource)?;
+    }
+
+    writeln!(buf, "</table></div>").map_err(Error::WriteInSource)?;
+
+    let title = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    crate::render_html(HtmlCfg { title, body: buf })
+}
@@ -0,0 +1,150 @@
+//! Table viewer, rendering CSV and TSV files as sortable HTML tables.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, HtmlCfg, Result};
+use handlebars::html_escape;
+use http::{Response, StatusCode};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Only this much of a file is read. Anything after it is left for the
+/// "download" link.
+const MAX_TABLE_LEN: usize = 2 * 1024 * 1024;
+
+/// Browsers struggle with tables much longer than this.
+const MAX_ROWS: usize = 5000;
+
+static TABLE_STYLE: &str = "<style type=\"text/css\">
+    .table { overflow-x: auto; }
+    .table table { border-collapse: collapse; font-family: monospace; }
+    .table th, .table td { border: 1px solid #ddd; padding: 0.2em 0.6ch; white-space: pre; }
+    .table th { background-color: #f6f8fa; cursor: pointer; user-select: none; }
+    .table th[data-sort=asc]::after { content: \" \\25b2\"; }
+    .table th[data-sort=desc]::after { content: \" \\25bc\"; }
+</style>";
+
+/// Sort the table body by the clicked column, numerically if every cell in
+/// the column is a number. Clicking again reverses the order.
+static TABLE_SCRIPT: &str = "<script>
+    document.querySelectorAll('.table th').forEach(function (th) {
+        th.addEventListener('click', function () {
+            var col = th.cellIndex;
+            var tbody = th.closest('table').tBodies[0];
+            var rows = Array.from(tbody.rows);
+            var text = function (row) {
+                return row.cells[col] ? row.cells[col].textContent : '';
+            };
+            var numeric = rows.every(function (row) {
+                return text(row).trim() !== '' && !isNaN(text(row));
+            });
+            var dir = th.dataset.sort === 'asc' ? -1 : 1;
+            rows.sort(function (a, b) {
+                var x = text(a), y = text(b);
+                return dir * (numeric ? x - y : x.localeCompare(y));
+            });
+            th.parentNode.querySelectorAll('th').forEach(function (other) {
+                delete other.dataset.sort;
+            });
+            th.dataset.sort = dir === 1 ? 'asc' : 'desc';
+            rows.forEach(function (row) { tbody.appendChild(row); });
+        });
+    });
+</script>";
+
+/// Get the field delimiter for files with the given extension, if they are
+/// tables that the viewer should render.
+pub fn delimiter(file_ext: &str) -> Option<u8> {
+    match file_ext {
+        "csv" => Some(b','),
+        "tsv" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+/// Re-read the start of a table file and render it as HTML. Files that can't
+/// be parsed are served unchanged.
+pub async fn respond_with_table(
+    vfs: &dyn Vfs,
+    path: &Path,
+    delimiter: u8,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    let (mut buf, truncated) = super::read_file_prefix(vfs, path, MAX_TABLE_LEN).await?;
+    if truncated {
+        // Don't show a half-read final row
+        let end = buf.iter().rposition(|&b| b == b'\n').unwrap_or(0);
+        buf.truncate(end);
+    }
+    match parse_table(&buf, delimiter) {
+        Some((rows, more_rows)) => {
+            let html = render_table(path, &rows, truncated || more_rows)?;
+            crate::html_str_to_response(html, StatusCode::OK)
+        }
+        None => Ok(resp),
+    }
+}
+
+/// Parse up to `MAX_ROWS` rows, returning them along with whether there were
+/// more rows left over.
+fn parse_table(buf: &[u8], delimiter: u8) -> Option<(Vec<Vec<String>>, bool)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(buf);
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        if rows.len() == MAX_ROWS {
+            return Some((rows, true));
+        }
+        let record = record
+            .map_err(|e| debug!("not rendering invalid table: {}", e))
+            .ok()?;
+        rows.push(record.iter().map(str::to_string).collect());
+    }
+    Some((rows, false))
+}
+
+/// Render the table, using the first row as the header.
+fn render_table(path: &Path, rows: &[Vec<String>], truncated: bool) -> Result<String> {
+    let mut buf = String::new();
+
+    writeln!(buf, "{}", TABLE_STYLE).map_err(Error::WriteInTable)?;
+    writeln!(buf, "<p><a href='?raw'>raw</a></p>").map_err(Error::WriteInTable)?;
+    if truncated {
+        writeln!(
+            buf,
+            "<p>Showing the first {} rows. <a href='?raw' download>Download the full file</a>.</p>",
+            rows.len().saturating_sub(1)
+        )
+        .map_err(Error::WriteInTable)?;
+    }
+    writeln!(buf, "<div class='table'><table>").map_err(Error::WriteInTable)?;
+
+    let mut rows = rows.iter();
+    if let Some(header) = rows.next() {
+        writeln!(buf, "<thead>").map_err(Error::WriteInTable)?;
+        write_row(&mut buf, "th", header).map_err(Error::WriteInTable)?;
+        writeln!(buf, "</thead>").map_err(Error::WriteInTable)?;
+    }
+    writeln!(buf, "<tbody>").map_err(Error::WriteInTable)?;
+    for row in rows {
+        write_row(&mut buf, "td", row).map_err(Error::WriteInTable)?;
+    }
+    writeln!(buf, "</tbody></table></div>").map_err(Error::WriteInTable)?;
+    writeln!(buf, "{}", TABLE_SCRIPT).map_err(Error::WriteInTable)?;
+
+    let title = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    crate::render_html(HtmlCfg { title, body: buf })
+}
+
+fn write_row(buf: &mut String, cell: &str, row: &[String]) -> std::fmt::Result {
+    write!(buf, "<tr>")?;
+    for field in row {
+        write!(buf, "<{cell}>{}</{cell}>", html_escape(field), cell = cell)?;
+    }
+    writeln!(buf, "</tr>")
+}
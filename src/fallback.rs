@@ -0,0 +1,60 @@
+//! Responses for well-known files that a site doesn't have, which crawlers
+//! and browsers ask for anyway. A real file is always served instead.
+
+use crate::{Body, Config, Error, Result};
+use http::{header, Request, Response, StatusCode};
+
+/// The icon browsers get for sites without one, instead of a 404 for every
+/// page they open
+static FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+/// How long browsers may keep the default icon, in seconds
+const FAVICON_MAX_AGE: u32 = 7 * 24 * 60 * 60;
+
+/// What a generated `robots.txt` tells crawlers, with `--robots`
+#[derive(Clone, Copy, Debug)]
+pub enum Robots {
+    /// Crawl nothing, so a server exposed for a while isn't indexed
+    Deny,
+    Allow,
+}
+
+/// Respond to a request for a well-known file that isn't on disk.
+pub async fn respond<B>(config: &Config, req: &Request<B>) -> Result<Option<Response<Body>>> {
+    match (req.uri().path(), config.robots) {
+        ("/robots.txt", Some(robots)) if !exists(config, "robots.txt").await => {
+            let body = match robots {
+                Robots::Deny => "User-agent: *\nDisallow: /\n",
+                Robots::Allow => "User-agent: *\nDisallow:\n",
+            };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, body.len())
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.as_ref())
+                .body(Body::from(body))
+                .map(Some)
+                .map_err(Error::from)
+        }
+        (crate::feed::PATH, _) if config.feed.is_some() && !exists(config, "feed.xml").await => {
+            let dir = config.feed.as_deref().unwrap_or_default();
+            crate::feed::respond(config, req, dir).await.map(Some)
+        }
+        ("/favicon.ico", _) if !exists(config, "favicon.ico").await => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, FAVICON.len())
+            .header(header::CONTENT_TYPE, "image/x-icon")
+            .header(
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", FAVICON_MAX_AGE),
+            )
+            .body(Body::from(FAVICON))
+            .map(Some)
+            .map_err(Error::from),
+        _ => Ok(None),
+    }
+}
+
+async fn exists(config: &Config, name: &str) -> bool {
+    let path = config.root_dir.join(name);
+    config.vfs.metadata(&path).await.is_ok()
+}
@@ -0,0 +1,274 @@
+//! An Atom feed of a directory at `/feed.xml`, with `--feed`, listing its
+//! most recently changed files, so that a folder of markdown notes can be
+//! followed like a blog. Markdown files can have YAML front matter, like
+//!
+//! ```markdown
+//! ---
+//! title: First post
+//! date: 2024-05-01
+//! summary: What this is about
+//! ---
+//! ```
+//!
+//! for their entry's title, date and summary. Otherwise the title is the
+//! file's first heading, or its name, and the date is when it was modified.
+
+use crate::vfs::{self, Vfs};
+use crate::{Body, Config, Error, Result};
+use handlebars::html_escape;
+use http::{header, Request, Response, StatusCode};
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the feed is served
+pub const PATH: &str = "/feed.xml";
+
+/// How many files the feed lists
+const MAX_ENTRIES: usize = 20;
+
+struct Entry {
+    title: String,
+    url: String,
+    /// Seconds since the Unix epoch
+    updated: i64,
+    summary: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct FrontMatter {
+    title: Option<String>,
+    date: Option<String>,
+    summary: Option<String>,
+}
+
+pub async fn respond<B>(config: &Config, req: &Request<B>, dir: &str) -> Result<Response<Body>> {
+    let vfs = &*config.vfs;
+    let origin = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("http://{}", host))
+        .unwrap_or_default();
+    let dir_path = config.root_dir.join(dir);
+
+    let mut entries = Vec::new();
+    for path in vfs.read_dir(&dir_path).await? {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let metadata = vfs.metadata(&path).await?;
+        if hidden || metadata.is_dir {
+            continue;
+        }
+        let modified = metadata.modified.map_or(0, unix_secs);
+        let url = format!("{}{}", origin, url_path(&config.root_dir, &path));
+        entries.push(entry(vfs, &path, url, modified).await?);
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated));
+    entries.truncate(MAX_ENTRIES);
+
+    let title = Path::new(dir)
+        .file_name()
+        .map_or_else(|| "basic-http-server".into(), |name| name.to_string_lossy());
+    let updated = entries
+        .first()
+        .map_or_else(|| unix_secs(SystemTime::now()), |entry| entry.updated);
+    let mut xml = String::new();
+    write_feed(&mut xml, &title, &origin, dir, updated, &entries).map_err(Error::WriteInFeed)?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, xml.len())
+        .header(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")
+        .body(Body::from(xml))
+        .map_err(Error::from)
+}
+
+fn write_feed(
+    xml: &mut String,
+    title: &str,
+    origin: &str,
+    dir: &str,
+    updated: i64,
+    entries: &[Entry],
+) -> std::fmt::Result {
+    writeln!(xml, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    writeln!(xml, "<feed xmlns=\"http://www.w3.org/2005/Atom\">")?;
+    writeln!(xml, "  <title>{}</title>", html_escape(title))?;
+    writeln!(xml, "  <id>{}{}</id>", html_escape(origin), PATH)?;
+    writeln!(
+        xml,
+        "  <link rel=\"self\" href=\"{}{}\"/>",
+        html_escape(origin),
+        PATH
+    )?;
+    let dir_url = format!("{}/{}", origin, dir.trim_matches('/'));
+    writeln!(xml, "  <link href=\"{}\"/>", html_escape(&dir_url))?;
+    writeln!(xml, "  <updated>{}</updated>", rfc3339(updated))?;
+    writeln!(xml, "  <author><name>{}</name></author>", html_escape(title))?;
+    for entry in entries {
+        writeln!(xml, "  <entry>")?;
+        writeln!(xml, "    <title>{}</title>", html_escape(&entry.title))?;
+        writeln!(xml, "    <link href=\"{}\"/>", html_escape(&entry.url))?;
+        writeln!(xml, "    <id>{}</id>", html_escape(&entry.url))?;
+        writeln!(xml, "    <updated>{}</updated>", rfc3339(entry.updated))?;
+        if let Some(summary) = &entry.summary {
+            writeln!(xml, "    <summary>{}</summary>", html_escape(summary))?;
+        }
+        writeln!(xml, "  </entry>")?;
+    }
+    writeln!(xml, "</feed>")
+}
+
+/// The entry for a file, from its front matter and heading if it's markdown
+async fn entry(vfs: &dyn Vfs, path: &Path, url: String, modified: i64) -> Result<Entry> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut entry = Entry {
+        title: name,
+        url,
+        updated: modified,
+        summary: None,
+    };
+    if path.extension().and_then(|ext| ext.to_str()) != Some("md") {
+        return Ok(entry);
+    }
+
+    let text = String::from_utf8_lossy(&vfs::read(vfs, path).await?).into_owned();
+    let (front_matter, markdown) = split_front_matter(&text);
+    let heading = markdown
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|heading| heading.trim().to_owned());
+    if let Some(title) = front_matter.title.or(heading) {
+        entry.title = title;
+    }
+    if let Some(date) = front_matter.date.as_deref().and_then(parse_date) {
+        entry.updated = date;
+    }
+    entry.summary = front_matter.summary;
+    Ok(entry)
+}
+
+/// Split YAML front matter between `---` lines off the start of a markdown
+/// file. Front matter that can't be parsed is ignored.
+fn split_front_matter(text: &str) -> (FrontMatter, &str) {
+    let rest = match text.strip_prefix("---\n").or_else(|| text.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (FrontMatter::default(), text),
+    };
+    let end = match rest.find("\n---") {
+        Some(end) => end,
+        None => return (FrontMatter::default(), text),
+    };
+    let front_matter = serde_yaml::from_str(&rest[..end]).unwrap_or_else(|e| {
+        debug!("ignoring front matter that can't be parsed: {}", e);
+        FrontMatter::default()
+    });
+    let markdown = rest[end + 4..].trim_start_matches(['-', '\r', '\n']);
+    (front_matter, markdown)
+}
+
+/// The path of a file's URL
+fn url_path(root_dir: &Path, path: &Path) -> String {
+    let mut url = String::new();
+    let relative = path.strip_prefix(root_dir).unwrap_or(path);
+    for component in relative.components() {
+        if let Component::Normal(name) = component {
+            url.push('/');
+            url.push_str(&crate::percent_encode(&name.to_string_lossy()));
+        }
+    }
+    url
+}
+
+/// Where the feed is for, as a path within the root, if the directory is in
+/// the root
+pub fn dir(value: &str) -> Option<String> {
+    let mut dir = PathBuf::new();
+    for component in Path::new(value.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(name) => dir.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(dir.to_string_lossy().replace('\\', "/"))
+}
+
+pub fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Parse a date like `2024-05-01`, with an optional UTC time like
+/// `T10:30:00Z` or ` 10:30`, to seconds since the Unix epoch
+fn parse_date(date: &str) -> Option<i64> {
+    let date = date.trim();
+    let year = date.get(0..4)?.parse().ok()?;
+    let month = date.get(5..7)?.parse().ok()?;
+    let day = date.get(8..10)?.parse().ok()?;
+    if date.get(4..5)? != "-" || date.get(7..8)? != "-" || !(1..=12).contains(&month) {
+        return None;
+    }
+    let mut secs = days_from_civil(year, month, day) * 86400;
+    let time = date[10..].trim_start_matches(['T', ' ']).trim_end_matches('Z');
+    if time.is_empty() {
+        return Some(secs);
+    }
+    let mut fields = time.split(':').map(|field| field.parse::<i64>().ok());
+    for multiplier in &[3600, 60, 1] {
+        match fields.next() {
+            Some(Some(field)) => secs += field * multiplier,
+            Some(None) => return None,
+            None => break,
+        }
+    }
+    Some(secs)
+}
+
+/// A time as an RFC 3339 date and time in UTC, like `2024-05-01T10:30:00Z`
+pub fn rfc3339(secs: i64) -> String {
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Days since the Unix epoch of a date in the proleptic Gregorian calendar,
+/// from Howard Hinnant's date algorithms
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of a number of days since the Unix epoch
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
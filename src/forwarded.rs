@@ -0,0 +1,141 @@
+//! Finding the client behind reverse proxies, from the `Forwarded` or
+//! `X-Forwarded-For` headers they add. Anyone can send those headers, so they
+//! are only believed on requests from the proxies given with
+//! `--trusted-proxy`.
+
+use crate::{Error, Result};
+use http::{header, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A range of IP addresses, like `10.0.0.0/8`. A bare address is a range of
+/// one.
+#[derive(Clone, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Cidr> {
+        let err = || Error::TrustedProxyParse(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| err())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| err())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(err());
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The address of the client that a request from `peer` was made by. For a
+/// trusted proxy, that is the nearest untrusted address in the chain of
+/// forwarding headers, since earlier entries could have been made up by the
+/// client. Otherwise it is the peer itself.
+///
+/// Addresses forwarded without a port are given port 0.
+pub fn client(headers: &HeaderMap, peer: SocketAddr, trusted: &[Cidr]) -> SocketAddr {
+    if !is_trusted(peer, trusted) {
+        return peer;
+    }
+
+    let chain = match forwarded_for(headers) {
+        Some(chain) => chain,
+        None => x_forwarded_for(headers),
+    };
+    let mut client = peer;
+    for hop in chain.iter().rev() {
+        // An obfuscated or unknown hop ends the chain, since nothing before
+        // it can be checked
+        match hop {
+            Some(addr) => client = *addr,
+            None => break,
+        }
+        if !is_trusted(client, trusted) {
+            break;
+        }
+    }
+    if client != peer {
+        debug!("{} forwarded request from {}", peer, client);
+    }
+    client
+}
+
+/// Whether an address is one of the trusted proxies
+pub fn is_trusted(addr: SocketAddr, trusted: &[Cidr]) -> bool {
+    trusted.iter().any(|cidr| cidr.contains(addr.ip()))
+}
+
+/// The `for` addresses of the standard `Forwarded` header, if there is one,
+/// from the client to the nearest proxy
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<SocketAddr>>> {
+    let mut values = headers.get_all(header::FORWARDED).iter().peekable();
+    values.peek()?;
+    let chain = values
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                if name.eq_ignore_ascii_case("for") {
+                    Some(parse_node(value.trim_matches('"')))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect();
+    Some(chain)
+}
+
+/// The addresses of the older `X-Forwarded-For` header, from the client to
+/// the nearest proxy
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<SocketAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Parse an address with or without a port, and IPv6 addresses with or
+/// without brackets
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = node.parse() {
+        return Some(addr);
+    }
+    let ip = node.trim_start_matches('[').trim_end_matches(']');
+    ip.parse().ok().map(|ip| SocketAddr::new(ip, 0))
+}
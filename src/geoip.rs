@@ -0,0 +1,287 @@
+//! Looking up the country and network of clients in MaxMind databases, with
+//! `--geoip`, for the request lines, the request log and the admin stats of
+//! a server that's open to the internet. Databases are in MaxMind's MMDB
+//! format, like the free GeoLite2 Country and ASN databases, and are read
+//! into memory when the server starts.
+//!
+//! An MMDB file is a binary tree of the bits of IP addresses, whose leaves
+//! point into a data section of values much like JSON, followed by metadata
+//! in the same encoding. See <https://maxmind.github.io/MaxMind-DB/>.
+
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// What starts the metadata, at the end of the file
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// The zeros between the tree and the data section
+const DATA_SEPARATOR: usize = 16;
+
+/// How deep values may nest, counting pointers, so that a corrupt database
+/// with a pointer to itself can't overflow the stack
+const MAX_DEPTH: u32 = 32;
+
+/// The databases to look clients up in, in the order given
+pub struct GeoIp {
+    databases: Vec<Database>,
+}
+
+/// Where a client is, as far as the databases know
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Location {
+    /// The ISO 3166 code of the country, like "DE"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// The autonomous system number of the network
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u64>,
+    /// The organization the network belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+}
+
+impl GeoIp {
+    pub fn open<'a>(paths: impl IntoIterator<Item = &'a str>) -> Result<GeoIp> {
+        let databases = paths
+            .into_iter()
+            .map(|path| Database::open(Path::new(path)))
+            .collect::<Result<_>>()?;
+        Ok(GeoIp { databases })
+    }
+
+    pub fn len(&self) -> usize {
+        self.databases.len()
+    }
+
+    /// Look up an address in each database, taking each part of the
+    /// location from the first that has it.
+    pub fn lookup(&self, ip: IpAddr) -> Location {
+        let mut location = Location::default();
+        for record in self.databases.iter().filter_map(|db| db.lookup(ip)) {
+            let country = record["country"]["iso_code"]
+                .as_str()
+                .or_else(|| record["registered_country"]["iso_code"].as_str());
+            if location.country.is_none() {
+                location.country = country.map(str::to_string);
+            }
+            if location.asn.is_none() {
+                location.asn = record["autonomous_system_number"].as_u64();
+            }
+            if location.organization.is_none() {
+                let organization = record["autonomous_system_organization"].as_str();
+                location.organization = organization.map(str::to_string);
+            }
+        }
+        location
+    }
+}
+
+impl Location {
+    pub fn is_empty(&self) -> bool {
+        *self == Location::default()
+    }
+}
+
+/// The country and network, like "DE AS3320", for request lines
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(country) = &self.country {
+            parts.push(country.clone());
+        }
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+struct Database {
+    bytes: Vec<u8>,
+    node_count: usize,
+    /// The bits in each of a node's two records: 24, 28 or 32
+    record_size: usize,
+    ip_version: u64,
+    /// Where the data section starts and ends
+    data: (usize, usize),
+    /// The node that IPv4 addresses start from, which in an IPv6 database is
+    /// the one for `::/96`
+    ipv4_start: usize,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Database> {
+        let bytes = fs::read(path)?;
+        Database::parse(bytes).ok_or_else(|| Error::GeoIpDatabase(path.display().to_string()))
+    }
+
+    fn parse(bytes: Vec<u8>) -> Option<Database> {
+        let marker = bytes
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)?;
+        let (metadata, _) = decode(&bytes[marker + METADATA_MARKER.len()..], 0, 0)?;
+        let node_count = metadata["node_count"].as_u64()? as usize;
+        let record_size = metadata["record_size"].as_u64()? as usize;
+        let ip_version = metadata["ip_version"].as_u64()?;
+        if ![24, 28, 32].contains(&record_size) || ![4, 6].contains(&ip_version) {
+            return None;
+        }
+        let data_start = node_count.checked_mul(record_size / 4)? + DATA_SEPARATOR;
+        if data_start > marker {
+            return None;
+        }
+        let mut db = Database {
+            bytes,
+            node_count,
+            record_size,
+            ip_version,
+            data: (data_start, marker),
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            for _ in 0..96 {
+                if db.ipv4_start >= node_count {
+                    break;
+                }
+                db.ipv4_start = db.record(db.ipv4_start, 0)?;
+            }
+        }
+        Some(db)
+    }
+
+    /// One of the two records of a node, for a 0 or a 1 bit
+    fn record(&self, node: usize, bit: u8) -> Option<usize> {
+        let len = self.record_size / 4;
+        let b = self.bytes.get(node * len..(node + 1) * len)?;
+        let record = match (self.record_size, bit) {
+            (24, 0) => uint(&b[0..3]),
+            (24, _) => uint(&b[3..6]),
+            // The middle byte has the top four bits of each record
+            (28, 0) => ((b[3] as usize & 0xf0) << 20) | uint(&b[0..3]),
+            (28, _) => ((b[3] as usize & 0x0f) << 24) | uint(&b[4..7]),
+            (_, 0) => uint(&b[0..4]),
+            (_, _) => uint(&b[4..8]),
+        };
+        Some(record)
+    }
+
+    /// The record for an address, if the database has one
+    fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        let (bits, len, mut node) = match ip {
+            IpAddr::V4(v4) => (u128::from(u32::from(v4)), 32, self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return None,
+            IpAddr::V6(v6) => (u128::from(v6), 128, 0),
+        };
+        for i in (0..len).rev() {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, ((bits >> i) & 1) as u8)?;
+        }
+        // Less than the node count is a node, and equal is the record for no
+        // data, but only a corrupt database ends with a node
+        let offset = node.checked_sub(self.node_count + DATA_SEPARATOR)?;
+        let data = &self.bytes[self.data.0..self.data.1];
+        decode(data, offset, 0).map(|(value, _)| value)
+    }
+}
+
+/// Decode the value at an offset into a section, returning it with the
+/// offset after it. Pointers are offsets into the same section.
+fn decode(section: &[u8], offset: usize, depth: u32) -> Option<(Value, usize)> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let control = *section.get(offset)?;
+    let mut offset = offset + 1;
+    let mut kind = control >> 5;
+
+    if kind == 1 {
+        let len = ((control >> 3) & 0x3) as usize + 1;
+        let high = (control & 0x7) as usize;
+        let bytes = section.get(offset..offset + len)?;
+        let pointer = match len {
+            1 => (high << 8) | uint(bytes),
+            2 => ((high << 16) | uint(bytes)) + 2048,
+            3 => ((high << 24) | uint(bytes)) + 526_336,
+            _ => uint(bytes),
+        };
+        let (value, _) = decode(section, pointer, depth + 1)?;
+        return Some((value, offset + len));
+    }
+    if kind == 0 {
+        kind = 7 + *section.get(offset)?;
+        offset += 1;
+    }
+    let mut size = (control & 0x1f) as usize;
+    if size >= 29 {
+        let len = size - 28;
+        let bytes = section.get(offset..offset + len)?;
+        size = [29, 285, 65_821][len - 1] + uint(bytes);
+        offset += len;
+    }
+
+    let value = match kind {
+        // A boolean is all in its size
+        14 => return Some((Value::Bool(size != 0), offset)),
+        7 => {
+            let mut map = Map::new();
+            for _ in 0..size {
+                let (key, after) = decode(section, offset, depth + 1)?;
+                let (value, after) = decode(section, after, depth + 1)?;
+                map.insert(key.as_str()?.to_string(), value);
+                offset = after;
+            }
+            return Some((Value::Object(map), offset));
+        }
+        11 => {
+            let mut array = Vec::new();
+            for _ in 0..size {
+                let (value, after) = decode(section, offset, depth + 1)?;
+                array.push(value);
+                offset = after;
+            }
+            return Some((Value::Array(array), offset));
+        }
+        _ => {
+            let bytes = section.get(offset..offset + size)?;
+            match kind {
+                2 => Value::from(std::str::from_utf8(bytes).ok()?),
+                3 => Value::from(f64::from_be_bytes(bytes.try_into().ok()?)),
+                15 => Value::from(f32::from_be_bytes(bytes.try_into().ok()?)),
+                5 | 6 | 9 if size <= 8 => Value::from(uint(bytes) as u64),
+                8 if size <= 4 => {
+                    // Shorter than four bytes is positive
+                    let mut int = [0; 4];
+                    int[4 - size..].copy_from_slice(bytes);
+                    Value::from(i32::from_be_bytes(int))
+                }
+                10 if size <= 16 => {
+                    let int = bytes.iter().fold(0, |int, &b| (int << 8) | u128::from(b));
+                    match u64::try_from(int) {
+                        Ok(int) => Value::from(int),
+                        Err(_) => Value::from(int.to_string()),
+                    }
+                }
+                // Raw bytes aren't needed, so they're skipped
+                4 => Value::Null,
+                _ => return None,
+            }
+        }
+    };
+    Some((value, offset + size))
+}
+
+/// A big-endian unsigned integer of up to eight bytes
+fn uint(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |int, &b| (int << 8) | b as usize)
+}
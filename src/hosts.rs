@@ -0,0 +1,43 @@
+//! Checking the `Host` of requests against `--allowed-hosts`. A page on
+//! another site can point its own domain name at the server's address, and
+//! its scripts could then read from the server as that site, which is DNS
+//! rebinding. Those requests still name the other site as their host.
+
+use http::uri::Authority;
+use http::{header, Request};
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+/// Whether a request is for one of the allowed hosts. An entry starting with
+/// "." allows that domain and any subdomain of it. Requests for an IP address
+/// or `localhost` are always allowed, since they can't come from rebinding,
+/// as are requests without a host, which browsers always send.
+pub fn allowed<B>(req: &Request<B>, allowed_hosts: &[String]) -> bool {
+    let authority = match (req.uri().authority(), req.headers().get(header::HOST)) {
+        (Some(authority), _) => authority.clone(),
+        (None, Some(host)) => match Authority::try_from(host.as_bytes()) {
+            Ok(authority) => authority,
+            Err(_) => {
+                warn!("rejected request with invalid host {:?}", host);
+                return false;
+            }
+        },
+        (None, None) => return true,
+    };
+    let host = authority.host().trim_end_matches('.').to_ascii_lowercase();
+
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    if ip.parse::<IpAddr>().is_ok() || host == "localhost" {
+        return true;
+    }
+    let allowed = allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(allowed.as_str()),
+            None => host == *allowed,
+        });
+    if !allowed {
+        warn!("rejected request for host {}", host);
+    }
+    allowed
+}
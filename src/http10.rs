@@ -0,0 +1,36 @@
+//! Responses to HTTP/1.0 requests, from the old embedded clients that are
+//! still tested against servers like this one. hyper already answers them
+//! as HTTP/1.0 should be: connections close after each response unless the
+//! client asks for `Connection: keep-alive`, bodies are never chunked, and
+//! every response has a `Date`. What's left is what HTTP/1.0 clients don't
+//! understand in the responses themselves.
+
+use crate::Body;
+use http::{header, HeaderValue, Response, StatusCode};
+
+/// Make a response understandable to an HTTP/1.0 client.
+pub fn adapt(resp: &mut Response<Body>) {
+    // 307 and 308 are from HTTP/1.1, and HTTP/1.0 clients treat unknown
+    // redirects as a 300, which they don't follow
+    let status = match resp.status() {
+        StatusCode::TEMPORARY_REDIRECT => Some(StatusCode::FOUND),
+        StatusCode::PERMANENT_REDIRECT => Some(StatusCode::MOVED_PERMANENTLY),
+        _ => None,
+    };
+    if let Some(status) = status {
+        *resp.status_mut() = status;
+    }
+
+    // `Cache-Control` is from HTTP/1.1 too, and HTTP/1.0 caches only know
+    // `Pragma`
+    let headers = resp.headers_mut();
+    let uncached = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| matches!(directive.trim(), "no-cache" | "no-store"));
+    if uncached && !headers.contains_key(header::PRAGMA) {
+        headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    }
+}
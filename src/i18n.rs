@@ -0,0 +1,123 @@
+//! Translations of the pages the server makes itself, like error pages and
+//! directory listings, into the language that a request prefers by its
+//! `Accept-Language` header, or the one `--lang` sets for every request, so
+//! that the server can be used by people who don't read English.
+
+use crate::Config;
+use http::{header, HeaderValue, Request, StatusCode};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    Pt,
+}
+
+/// The languages by tag, with English first, as it's chosen among equals
+const LANGS: &[(&str, Lang)] = &[
+    ("en", Lang::En),
+    ("de", Lang::De),
+    ("es", Lang::Es),
+    ("fr", Lang::Fr),
+    ("pt", Lang::Pt),
+];
+
+/// Text in the pages, besides the titles of error pages
+#[derive(Clone, Copy)]
+pub enum Text {
+    ListView,
+    GalleryView,
+    Playlist,
+}
+
+impl Lang {
+    /// The language of a tag, like "de" or "pt-BR", if it's translated to
+    pub fn parse(tag: &str) -> Option<Lang> {
+        let language = tag.split('-').next().unwrap_or("");
+        LANGS
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(language))
+            .map(|&(_, lang)| lang)
+    }
+
+    /// The title of an error page, like "404 Not Found". Statuses that aren't
+    /// translated keep their English reason.
+    pub fn status(self, status: StatusCode) -> String {
+        let reason = match (self, status.as_u16()) {
+            (Lang::De, 400) => "Ungültige Anfrage",
+            (Lang::De, 403) => "Verboten",
+            (Lang::De, 404) => "Nicht gefunden",
+            (Lang::De, 405) => "Methode nicht erlaubt",
+            (Lang::De, 500) => "Interner Serverfehler",
+            (Lang::De, 503) => "Dienst nicht verfügbar",
+            (Lang::Es, 400) => "Solicitud incorrecta",
+            (Lang::Es, 403) => "Prohibido",
+            (Lang::Es, 404) => "No encontrado",
+            (Lang::Es, 405) => "Método no permitido",
+            (Lang::Es, 500) => "Error interno del servidor",
+            (Lang::Es, 503) => "Servicio no disponible",
+            (Lang::Fr, 400) => "Requête incorrecte",
+            (Lang::Fr, 403) => "Interdit",
+            (Lang::Fr, 404) => "Introuvable",
+            (Lang::Fr, 405) => "Méthode non autorisée",
+            (Lang::Fr, 500) => "Erreur interne du serveur",
+            (Lang::Fr, 503) => "Service indisponible",
+            (Lang::Pt, 400) => "Requisição inválida",
+            (Lang::Pt, 403) => "Proibido",
+            (Lang::Pt, 404) => "Não encontrado",
+            (Lang::Pt, 405) => "Método não permitido",
+            (Lang::Pt, 500) => "Erro interno do servidor",
+            (Lang::Pt, 503) => "Serviço indisponível",
+            _ => return format!("{}", status),
+        };
+        format!("{} {}", status.as_u16(), reason)
+    }
+
+    pub fn text(self, text: Text) -> &'static str {
+        match (self, text) {
+            (Lang::En, Text::ListView) => "list view",
+            (Lang::En, Text::GalleryView) => "gallery view",
+            (Lang::En, Text::Playlist) => "playlist",
+            (Lang::De, Text::ListView) => "Listenansicht",
+            (Lang::De, Text::GalleryView) => "Galerieansicht",
+            (Lang::De, Text::Playlist) => "Wiedergabeliste",
+            (Lang::Es, Text::ListView) => "vista de lista",
+            (Lang::Es, Text::GalleryView) => "vista de galería",
+            (Lang::Es, Text::Playlist) => "lista de reproducción",
+            (Lang::Fr, Text::ListView) => "vue en liste",
+            (Lang::Fr, Text::GalleryView) => "vue en galerie",
+            (Lang::Fr, Text::Playlist) => "liste de lecture",
+            (Lang::Pt, Text::ListView) => "visualização em lista",
+            (Lang::Pt, Text::GalleryView) => "visualização em galeria",
+            (Lang::Pt, Text::Playlist) => "lista de reprodução",
+        }
+    }
+}
+
+/// The language to make a page for a request in.
+pub fn for_request<B>(config: &Config, req: &Request<B>) -> Lang {
+    match config.lang {
+        Some(lang) => lang,
+        None => from_accept_language(crate::vary::header(req, header::ACCEPT_LANGUAGE)),
+    }
+}
+
+/// The language an `Accept-Language` header prefers, of those translated
+/// to, or English if it accepts none of them.
+pub fn from_accept_language(accept: Option<&HeaderValue>) -> Lang {
+    let accept = match accept.and_then(|accept| accept.to_str().ok()) {
+        Some(accept) => accept,
+        None => return Lang::En,
+    };
+    let mut best = (0.0, Lang::En);
+    for &(tag, lang) in LANGS {
+        let q = crate::language::quality(accept, tag);
+        if q > best.0 {
+            best = (q, lang);
+        }
+    }
+    best.1
+}
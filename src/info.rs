@@ -0,0 +1,51 @@
+//! What a server is, once it's listening, with `--print-info=json`, for
+//! scripts and test harnesses that start one and need to know how to reach
+//! it: the addresses it listens on, with the port it was given for port 0,
+//! its PID, and the arguments it was started with, from the command line and
+//! any config file, keyed as in a config file. It's printed on standard
+//! output as one line of JSON, which nothing else is printed on, or written
+//! to `--info-file`.
+
+use crate::listen::{self, ListenAddr};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Info {
+    /// The arguments, keyed as in a config file
+    args: serde_json::Map<String, serde_json::Value>,
+    /// The file to write to, instead of standard output
+    file: Option<PathBuf>,
+}
+
+impl Info {
+    pub fn new(args: serde_json::Map<String, serde_json::Value>, file: Option<PathBuf>) -> Info {
+        Info { args, file }
+    }
+
+    /// The file to write to, if not standard output
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// Print or write the information about a server serving the root, now
+    /// that it's listening on the addresses.
+    pub fn print(&self, root: &Path, addrs: &[ListenAddr]) -> io::Result<()> {
+        // The first TCP port, which is the port if there is only one
+        let port = addrs.iter().find_map(ListenAddr::port);
+        let json = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "pid": std::process::id(),
+            "addrs": listen::addrs_json(addrs),
+            "port": port,
+            "root": root,
+            "args": self.args,
+        });
+        match &self.file {
+            Some(path) => listen::write_json_file(path, &json),
+            None => {
+                println!("{}", json);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Injecting scripts and stylesheets into HTML pages, with `--inject-script`
+//! and `--inject-css`, for adding development tools or overrides to a site
+//! without editing it. Stylesheets go before `</head>` and scripts before
+//! `</body>`, inline, and the files are read for each page, so edits to them
+//! show on the next load. `--auto-refresh` injects a refresh of the page
+//! after a while, for reloading it without any live-reload machinery.
+//!
+//! Responses are built in memory, so rather than parsing the page, it's
+//! scanned once for the closing tags, and the tags are spliced in.
+
+use crate::Body;
+use http::{header, Response, StatusCode};
+use http_body_util::BodyExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default)]
+pub struct Injections {
+    pub scripts: Vec<PathBuf>,
+    pub styles: Vec<PathBuf>,
+    /// How often pages reload themselves
+    pub refresh: Option<Duration>,
+}
+
+impl Injections {
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty() && self.styles.is_empty() && self.refresh.is_none()
+    }
+
+    /// The files that are injected
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.styles.iter().chain(&self.scripts)
+    }
+
+    /// Inject the scripts and stylesheets into the response, if it's an HTML
+    /// page.
+    pub async fn apply(&self, resp: Response<Body>) -> Response<Body> {
+        if self.is_empty() || !is_html_page(&resp) {
+            return resp;
+        }
+        let mut head = Vec::new();
+        if let Some(refresh) = self.refresh {
+            head.extend_from_slice(refresh_tag(refresh).as_bytes());
+        }
+        for path in &self.styles {
+            if let Some(css) = read(path).await {
+                head.extend_from_slice(b"<style>\n");
+                head.extend_from_slice(&css);
+                head.extend_from_slice(b"\n</style>\n");
+            }
+        }
+        let mut body = Vec::new();
+        for path in &self.scripts {
+            if let Some(js) = read(path).await {
+                body.extend_from_slice(b"<script>\n");
+                body.extend_from_slice(&js);
+                body.extend_from_slice(b"\n</script>\n");
+            }
+        }
+        splice(resp, &head, &body).await
+    }
+}
+
+/// A meta refresh, which only counts whole seconds, or a script reloading
+/// the page for other intervals
+fn refresh_tag(refresh: Duration) -> String {
+    if refresh.subsec_nanos() == 0 {
+        format!("<meta http-equiv=\"refresh\" content=\"{}\">\n", refresh.as_secs())
+    } else {
+        format!(
+            "<script>setTimeout(function () {{ location.reload(); }}, {});</script>\n",
+            refresh.as_millis()
+        )
+    }
+}
+
+/// Whether the response is a whole HTML page
+pub fn is_html_page(resp: &Response<Body>) -> bool {
+    resp.status() == StatusCode::OK
+        && resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/html"))
+}
+
+/// Insert `head` before the page's `</head>`, or at its start if it has
+/// none, and `body` before its last `</body>`, or at its end.
+pub async fn splice(resp: Response<Body>, head: &[u8], body: &[u8]) -> Response<Body> {
+    let (mut parts, page) = resp.into_parts();
+    let page = page.collect().await.expect("infallible").to_bytes();
+    let head_at = find_ignore_case(&page, b"</head>", false).unwrap_or(0);
+    let body_at = find_ignore_case(&page, b"</body>", true)
+        .filter(|&at| at >= head_at)
+        .unwrap_or(page.len());
+
+    let mut spliced = Vec::with_capacity(page.len() + head.len() + body.len());
+    spliced.extend_from_slice(&page[..head_at]);
+    spliced.extend_from_slice(head);
+    spliced.extend_from_slice(&page[head_at..body_at]);
+    spliced.extend_from_slice(body);
+    spliced.extend_from_slice(&page[body_at..]);
+
+    // The page isn't the file any more, so the file's digests and tag are
+    // wrong
+    parts.headers.remove("repr-digest");
+    parts.headers.remove("content-md5");
+    parts.headers.remove(header::ETAG);
+    parts.headers.insert(header::CONTENT_LENGTH, spliced.len().into());
+    Response::from_parts(parts, Body::from(spliced))
+}
+
+async fn read(path: &Path) -> Option<Vec<u8>> {
+    match tokio::fs::read(path).await {
+        Ok(contents) => Some(contents),
+        Err(e) => {
+            warn!("failed to read {} to inject: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The position of the first, or last, ASCII case-insensitive match of the
+/// needle in the haystack
+fn find_ignore_case(haystack: &[u8], needle: &[u8], last: bool) -> Option<usize> {
+    let mut matches = haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| window.eq_ignore_ascii_case(needle))
+        .map(|(at, _)| at);
+    if last {
+        matches.next_back()
+    } else {
+        matches.next()
+    }
+}
@@ -0,0 +1,94 @@
+//! Serving language variants of pages, as Apache does, with
+//! `--default-language`. A request for `guide.html`, when there is no such
+//! file, is served from whichever of `guide.html.en`, `guide.html.de` and so
+//! on best suits its `Accept-Language` header, or else from the default
+//! language.
+
+use crate::vfs::Vfs;
+use http::{header, Request};
+use std::path::{Path, PathBuf};
+
+/// The variant of a missing file to serve, and its language
+pub async fn find_variant<B>(
+    vfs: &dyn Vfs,
+    req: &Request<B>,
+    path: &Path,
+    default: &str,
+) -> Option<(PathBuf, String)> {
+    let name = path.file_name()?.to_str()?;
+    let entries = vfs.read_dir(path.parent()?).await.ok()?;
+    let mut variants: Vec<(PathBuf, String)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let tag = entry.file_name()?.to_str()?.strip_prefix(name)?;
+            let tag = tag.strip_prefix('.').filter(|tag| is_language_tag(tag))?;
+            let tag = tag.to_string();
+            Some((entry, tag))
+        })
+        .collect();
+    if variants.is_empty() {
+        return None;
+    }
+    variants.sort();
+
+    let accept = crate::vary::header(req, header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let is_default = |tag: &str| tag.eq_ignore_ascii_case(default);
+    // The most preferred language, with the default language and then the
+    // first in order chosen among equals
+    let mut best: Option<(f32, bool, usize)> = None;
+    for (i, (_, tag)) in variants.iter().enumerate() {
+        let candidate = (quality(accept, tag), is_default(tag), i);
+        let better = match best {
+            None => true,
+            Some((q, default, _)) => {
+                candidate.0 > q || (candidate.0 == q && candidate.1 && !default)
+            }
+        };
+        if better {
+            best = Some(candidate);
+        }
+    }
+    let (_, _, i) = best?;
+    Some(variants.swap_remove(i))
+}
+
+/// Whether a file name suffix is a language tag, like "en" or "pt-BR"
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or("");
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// The quality an `Accept-Language` header gives a language, from the best
+/// range that matches it. A range matches its own language and the more
+/// specific languages under it, and for want of better, "de-CH" accepts
+/// "de".
+pub fn quality(accept: &str, tag: &str) -> f32 {
+    let tag = tag.to_ascii_lowercase();
+    let mut best = 0.0;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q: f32 = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        let q = if range == tag || range == "*" || tag.starts_with(&format!("{}-", range)) {
+            q
+        } else if range.starts_with(&format!("{}-", tag)) {
+            // Slightly less than an exact match at the same quality
+            q * 0.9
+        } else {
+            continue;
+        };
+        if q > best {
+            best = q;
+        }
+    }
+    best
+}
@@ -0,0 +1,396 @@
+//! The sockets that the server listens on: TCP sockets, or on unix, unix
+//! domain sockets for sitting behind a local reverse proxy. On unix the
+//! sockets can also be passed in by systemd socket activation.
+
+use crate::{Error, Result};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// An address to listen on, as given to `--addr`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// A unix domain socket, given as `unix:PATH`
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+const DEFAULT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 4000;
+
+impl ListenAddr {
+    /// The port, if it's a TCP address
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            ListenAddr::Tcp(addr) => Some(addr.port()),
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl Default for ListenAddr {
+    fn default() -> ListenAddr {
+        ListenAddr::Tcp(SocketAddr::new(DEFAULT_IP, DEFAULT_PORT))
+    }
+}
+
+/// Parse an address given to `--addr`. As well as `IP:PORT`, it may be just
+/// the IP or just the port, as `:PORT`, with the other taken from the
+/// default, or a hostname, which may resolve to several addresses.
+pub fn parse_addr(s: &str) -> Result<Vec<ListenAddr>> {
+    #[cfg(unix)]
+    {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(vec![ListenAddr::Unix(PathBuf::from(path))]);
+        }
+    }
+
+    let tcp = |addr| vec![ListenAddr::Tcp(addr)];
+    if let Ok(addr) = s.parse() {
+        return Ok(tcp(addr));
+    }
+    let ip = s.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = ip.parse() {
+        return Ok(tcp(SocketAddr::new(ip, DEFAULT_PORT)));
+    }
+    if let Some(port) = s.strip_prefix(':') {
+        let port = port.parse().map_err(Error::PortParse)?;
+        return Ok(tcp(SocketAddr::new(DEFAULT_IP, port)));
+    }
+
+    let (host, port) = match s.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(Error::PortParse)?),
+        None => (s, DEFAULT_PORT),
+    };
+    let mut addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(Error::AddrResolve)?
+        .collect();
+    // Resolvers may return each address once per socket type
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs.into_iter().map(ListenAddr::Tcp).collect())
+}
+
+/// The addresses that reach an address bound to all interfaces, one for each
+/// of the machine's interfaces, so there's a working link to give to others.
+/// Other addresses have just themselves, which are already displayed.
+pub fn interface_addrs(addr: &ListenAddr) -> Vec<SocketAddr> {
+    let addr = match addr {
+        ListenAddr::Tcp(addr) if addr.ip().is_unspecified() => addr,
+        _ => return Vec::new(),
+    };
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            debug!("failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+    interfaces
+        .into_iter()
+        .map(|interface| interface.ip())
+        // Binding IPv4's wildcard doesn't listen on IPv6. Link-local IPv6
+        // addresses need a scope ID in the URL, which browsers don't accept.
+        .filter(|ip| match ip {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(ip) => addr.is_ipv6() && ip.segments()[0] & 0xffc0 != 0xfe80,
+        })
+        .map(|ip| SocketAddr::new(ip, addr.port()))
+        .collect()
+}
+
+/// A URL that other machines on the network can reach the server at,
+/// preferring IPv4, if it listens on anything but loopback.
+pub fn lan_url(addrs: &[ListenAddr]) -> Option<String> {
+    let mut lan: Vec<SocketAddr> = addrs
+        .iter()
+        .flat_map(|addr| match addr {
+            ListenAddr::Tcp(tcp) if tcp.ip().is_unspecified() => interface_addrs(addr),
+            ListenAddr::Tcp(tcp) => vec![*tcp],
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => Vec::new(),
+        })
+        .filter(|addr| !addr.ip().is_loopback())
+        .collect();
+    lan.sort_by_key(SocketAddr::is_ipv6);
+    lan.first().map(|addr| format!("http://{}", addr))
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "http://{}", addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Options for the listening sockets and their connections
+#[derive(Clone)]
+pub struct SocketOptions {
+    /// Bind with `SO_REUSEPORT`, so that other processes can share the port
+    pub reuseport: bool,
+    /// Set `TCP_NODELAY`, sending small writes without waiting to batch them
+    pub nodelay: bool,
+    /// The most connections waiting to be accepted
+    pub backlog: u32,
+    pub send_buffer: Option<u32>,
+    pub recv_buffer: Option<u32>,
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// A connection accepted by a `Listener`
+pub enum Connection {
+    /// A TCP connection, and the address of its peer
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// A listening socket that was created before the tokio runtime, and so
+/// isn't yet registered with it. Only unix has any.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub enum StdListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    pub fn from_std(listener: StdListener) -> io::Result<Listener> {
+        match listener {
+            StdListener::Tcp(listener) => {
+                debug!("listening on inherited socket {}", listener.local_addr()?);
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            StdListener::Unix(listener) => {
+                debug!("listening on inherited unix socket");
+                Ok(Listener::Unix(UnixListener::from_std(listener)?))
+            }
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => Ok(ListenAddr::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let addr = listener.local_addr()?;
+                let path = addr.as_pathname().unwrap_or_else(|| Path::new(""));
+                Ok(ListenAddr::Unix(path.to_owned()))
+            }
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok(Connection::Tcp(stream, peer))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Connection::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Create a listening socket. The socket options only apply to TCP sockets.
+pub fn bind(addr: &ListenAddr, sockopts: &SocketOptions) -> Result<Listener> {
+    match addr {
+        ListenAddr::Tcp(addr) => bind_tcp(*addr, sockopts).map(Listener::Tcp),
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            remove_stale_socket(path)?;
+            Ok(Listener::Unix(UnixListener::bind(path)?))
+        }
+    }
+}
+
+/// Create a listening socket, and if the port is in use, try up to
+/// `fallback` ports after it in turn, for when an earlier server is still
+/// running.
+pub fn bind_with_fallback(
+    addr: &ListenAddr,
+    sockopts: &SocketOptions,
+    fallback: u16,
+) -> Result<Listener> {
+    let mut addr = addr.clone();
+    for _ in 0..fallback {
+        match (bind(&addr, sockopts), &mut addr) {
+            (Err(Error::Io(ref e)), ListenAddr::Tcp(tcp))
+                if e.kind() == io::ErrorKind::AddrInUse && tcp.port() < u16::MAX =>
+            {
+                warn!("port {} is in use, trying {}", tcp.port(), tcp.port() + 1);
+                tcp.set_port(tcp.port() + 1);
+            }
+            (result, _) => return result,
+        }
+    }
+    bind(&addr, sockopts)
+}
+
+/// Create a listening TCP socket. `TcpListener::bind` would do, but doesn't
+/// allow setting socket options first.
+fn bind_tcp(addr: SocketAddr, sockopts: &SocketOptions) -> Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // As `TcpListener::bind` does, so that restarting the server doesn't
+    // have to wait for old connections to time out.
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    if sockopts.reuseport {
+        #[cfg(unix)]
+        socket.set_reuseport(true)?;
+        #[cfg(not(unix))]
+        warn!("SO_REUSEPORT is not supported on this platform");
+    }
+    // Accepted connections inherit the listening socket's buffer sizes
+    if let Some(size) = sockopts.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = sockopts.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    socket.bind(addr)?;
+    Ok(socket.listen(sockopts.backlog)?)
+}
+
+/// Write the addresses listened on to a file as JSON, for tools that start
+/// the server on port 0 and need to know which port it was given.
+pub fn write_addr_file(path: &Path, addrs: &[ListenAddr]) -> io::Result<()> {
+    write_json_file(path, &serde_json::json!({ "addrs": addrs_json(addrs) }))
+}
+
+/// The addresses listened on, as JSON, with their URLs and their parts
+pub fn addrs_json(addrs: &[ListenAddr]) -> serde_json::Value {
+    addrs
+        .iter()
+        .map(|addr| match addr {
+            ListenAddr::Tcp(tcp) => serde_json::json!({
+                "url": addr.to_string(),
+                "ip": tcp.ip().to_string(),
+                "port": tcp.port(),
+            }),
+            #[cfg(unix)]
+            ListenAddr::Unix(unix) => serde_json::json!({
+                "url": addr.to_string(),
+                "path": unix,
+            }),
+        })
+        .collect()
+}
+
+/// Write JSON to a file in full before moving it into place, so that
+/// nothing watching for the file reads half of it
+pub fn write_json_file(path: &Path, json: &serde_json::Value) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, json.to_string())?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Take the listening sockets passed by systemd socket activation, if the
+/// server was started that way. systemd binds the sockets, so the server can
+/// be started on the first connection, and listen on privileged ports
+/// without running as root.
+///
+/// This must be called before any threads are started, since it removes the
+/// activation variables from the environment, so that they aren't passed on.
+#[cfg(unix)]
+pub fn systemd_listeners() -> Result<Vec<StdListener>> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+
+    /// The first descriptor passed, after stdin, stdout and stderr
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let pid = std::env::var("LISTEN_PID");
+    let fds = std::env::var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Ok(pid), Ok(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+    // The sockets are meant for the process systemd started, not one it
+    // started in turn
+    if pid.parse() != Ok(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let fds: RawFd = fds.parse().map_err(Error::ListenFdsParse)?;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        .map(|fd| {
+            // Safety: systemd passes these descriptors for this process to own
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            // Only TCP sockets have an address that std understands
+            if listener.local_addr().is_ok() {
+                Ok(StdListener::Tcp(listener))
+            } else {
+                let fd = listener.into_raw_fd();
+                // Safety: as above, and the descriptor was just released
+                let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+                Ok(StdListener::Unix(listener))
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn systemd_listeners() -> Result<Vec<StdListener>> {
+    Ok(Vec::new())
+}
+
+/// Remove the socket file left by a server that wasn't shut down cleanly,
+/// which would stop the socket being bound again. A socket that something is
+/// still listening on is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_err() {
+                debug!("removing stale socket {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Clean up after listening on `addr`, once the server has stopped.
+pub fn cleanup(addr: &ListenAddr) {
+    #[cfg(unix)]
+    {
+        if let ListenAddr::Unix(path) = addr {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!("failed to remove socket {}: {}", path.display(), e);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = addr;
+}
@@ -0,0 +1,298 @@
+//! Request lines in a format of one's own, with `--log-format`, for tools
+//! that expect an access log with particular fields. Formats are written
+//! like nginx's `log_format`, with variables such as `$remote_addr`,
+//! `$status` and `$request_time`, or `${status}` where the name would run
+//! into the text after it, and `common` and `combined` name the usual ones.
+//! A format is parsed once, when the server starts.
+//!
+//! Values that a request doesn't have, like a header it wasn't sent with,
+//! are written as `-`.
+
+use crate::geoip::Location;
+use crate::request_id::RequestId;
+use crate::{ClientAddr, Error, Result};
+use http::header::{self, HeaderName};
+use http::{HeaderMap, Method, Request, Response, Uri, Version};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+/// The Common Log Format of the first web servers
+const COMMON: &str =
+    "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent";
+
+/// The Common Log Format with the referer and user agent, as nginx logs by
+/// default
+const COMBINED: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent \"$http_referer\" \"$http_user_agent\"";
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+pub struct LogFormat {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Text(String),
+    Var(Var),
+}
+
+enum Var {
+    RemoteAddr,
+    RemotePort,
+    /// Always `-`, as there's no authentication
+    RemoteUser,
+    /// The request line, like `GET /index.html HTTP/1.1`
+    Request,
+    RequestMethod,
+    /// The path and query
+    RequestUri,
+    Uri,
+    Args,
+    ServerProtocol,
+    Status,
+    BodyBytesSent,
+    /// Seconds, to the millisecond
+    RequestTime,
+    /// Seconds since the Unix epoch, to the millisecond
+    Msec,
+    /// Like `10/Oct/2026:13:55:36 +0000`
+    TimeLocal,
+    TimeIso8601,
+    GeoipCountryCode,
+    GeoipAsn,
+    RequestId,
+    /// A request header, from `$http_user_agent` etc.
+    RequestHeader(HeaderName),
+    /// A response header, from `$sent_http_content_type` etc.
+    ResponseHeader(HeaderName),
+}
+
+/// What a format needs of a request, taken before it's answered
+pub struct Captured {
+    client: Option<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    id: Option<RequestId>,
+    headers: HeaderMap,
+}
+
+impl LogFormat {
+    /// Parse a format, or the name of one
+    pub fn parse(format: &str) -> Result<LogFormat> {
+        let format = match format {
+            "common" => COMMON,
+            "combined" => COMBINED,
+            format => format,
+        };
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = format;
+        while let Some(dollar) = rest.find('$') {
+            text.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+            let name = if let Some(braced) = rest.strip_prefix('{') {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| Error::LogFormatParse(format!("${}", rest)))?;
+                rest = &braced[end + 1..];
+                &braced[..end]
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..end];
+                rest = &rest[end..];
+                name
+            };
+            let var =
+                Var::parse(name).ok_or_else(|| Error::LogFormatParse(format!("${}", name)))?;
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Var(var));
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(LogFormat { parts })
+    }
+
+    /// Take what the format needs of a request, before it's answered
+    pub fn capture<B>(&self, req: &Request<B>) -> Captured {
+        let mut headers = HeaderMap::new();
+        for part in &self.parts {
+            if let Part::Var(Var::RequestHeader(name)) = part {
+                if let Some(value) = req.headers().get(name) {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        Captured {
+            client: req.extensions().get::<ClientAddr>().map(|client| client.0),
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            id: req.extensions().get::<RequestId>().cloned(),
+            headers,
+        }
+    }
+
+    /// The line for a request and its response
+    pub fn format<B: hyper::body::Body>(
+        &self,
+        req: &Captured,
+        resp: &Response<B>,
+        elapsed: Duration,
+        location: Option<&Location>,
+    ) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            let var = match part {
+                Part::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Part::Var(var) => var,
+            };
+            let len = line.len();
+            // Writing to a string can't fail
+            let _ = match var {
+                Var::RemoteAddr => match req.client {
+                    Some(client) => write!(line, "{}", client.ip()),
+                    None => Ok(()),
+                },
+                Var::RemotePort => match req.client {
+                    Some(client) => write!(line, "{}", client.port()),
+                    None => Ok(()),
+                },
+                Var::RemoteUser => Ok(()),
+                Var::Request => write!(
+                    line,
+                    "{} {} {:?}",
+                    req.method,
+                    request_uri(&req.uri),
+                    req.version
+                ),
+                Var::RequestMethod => write!(line, "{}", req.method),
+                Var::RequestUri => write!(line, "{}", request_uri(&req.uri)),
+                Var::Uri => write!(line, "{}", req.uri.path()),
+                Var::Args => write!(line, "{}", req.uri.query().unwrap_or("")),
+                Var::ServerProtocol => write!(line, "{:?}", req.version),
+                Var::Status => write!(line, "{}", resp.status().as_u16()),
+                Var::BodyBytesSent => {
+                    let bytes = resp.body().size_hint().exact().unwrap_or(0);
+                    write!(line, "{}", bytes)
+                }
+                Var::RequestTime => write!(line, "{:.3}", elapsed.as_secs_f64()),
+                Var::Msec => {
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    write!(line, "{:.3}", now.as_secs_f64())
+                }
+                Var::TimeLocal => write!(line, "{}", time_local(SystemTime::now())),
+                Var::TimeIso8601 => {
+                    let secs = crate::feed::unix_secs(SystemTime::now());
+                    write!(line, "{}", crate::feed::rfc3339(secs))
+                }
+                Var::GeoipCountryCode => match location.and_then(|l| l.country.as_ref()) {
+                    Some(country) => write!(line, "{}", country),
+                    None => Ok(()),
+                },
+                Var::GeoipAsn => match location.and_then(|l| l.asn) {
+                    Some(asn) => write!(line, "{}", asn),
+                    None => Ok(()),
+                },
+                Var::RequestId => match &req.id {
+                    Some(id) => write!(line, "{}", id),
+                    None => Ok(()),
+                },
+                Var::RequestHeader(name) => write_header(&mut line, req.headers.get(name)),
+                Var::ResponseHeader(name) => write_header(&mut line, resp.headers().get(name)),
+            };
+            if line.len() == len {
+                line.push('-');
+            }
+        }
+        line
+    }
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Var> {
+        let var = match name {
+            "remote_addr" => Var::RemoteAddr,
+            "remote_port" => Var::RemotePort,
+            "remote_user" => Var::RemoteUser,
+            "request" => Var::Request,
+            "request_method" => Var::RequestMethod,
+            "request_uri" => Var::RequestUri,
+            "uri" => Var::Uri,
+            "args" | "query_string" => Var::Args,
+            "server_protocol" => Var::ServerProtocol,
+            "status" => Var::Status,
+            "body_bytes_sent" => Var::BodyBytesSent,
+            "request_time" => Var::RequestTime,
+            "msec" => Var::Msec,
+            "time_local" => Var::TimeLocal,
+            "time_iso8601" => Var::TimeIso8601,
+            "geoip_country_code" => Var::GeoipCountryCode,
+            "geoip_asn" => Var::GeoipAsn,
+            "request_id" => Var::RequestId,
+            _ => {
+                let (header, response) = match name.strip_prefix("sent_http_") {
+                    Some(header) => (header, true),
+                    None => (name.strip_prefix("http_")?, false),
+                };
+                let header = HeaderName::from_bytes(header.replace('_', "-").as_bytes()).ok()?;
+                if response {
+                    Var::ResponseHeader(header)
+                } else {
+                    Var::RequestHeader(header)
+                }
+            }
+        };
+        Some(var)
+    }
+}
+
+/// The path and query of a URI, as they were in the request line
+fn request_uri(uri: &Uri) -> &str {
+    uri.path_and_query().map_or("/", |pq| pq.as_str())
+}
+
+/// Write a header's value, if it's there, with quotes and anything that
+/// isn't printable escaped, so that a header can't forge log lines
+fn write_header(line: &mut String, value: Option<&header::HeaderValue>) -> std::fmt::Result {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    for &b in value.as_bytes() {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' => line.push(b as char),
+            _ => write!(line, "\\x{:02X}", b)?,
+        }
+    }
+    Ok(())
+}
+
+/// A time like `10/Oct/2026:13:55:36 +0000`, always in UTC
+fn time_local(time: SystemTime) -> String {
+    let secs = crate::feed::unix_secs(time);
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = crate::feed::civil_from_days(days);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
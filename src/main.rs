@@ -7,24 +7,112 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
-use clap::App;
+use bytes::Bytes;
+use clap::{App, ArgMatches, SubCommand};
 use env_logger::{Builder, Env};
-use futures::{future, future::Either, Future};
-use handlebars::Handlebars;
 use http::status::StatusCode;
 use http::Uri;
-use hyper::{header, service::service_fn, Body, Request, Response, Server};
+use http::{header, HeaderValue, Method, Request, Response, Version};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
 use std::{
+    backtrace::Backtrace,
+    convert::TryFrom,
     error::Error as StdError,
+    ffi::OsString,
     io,
     net::SocketAddr,
-    path::{Path, PathBuf},
+    num::{NonZeroUsize, ParseIntError},
+    panic,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+mod admin;
+mod agents;
+mod archive;
+mod args;
+mod bench;
+mod browser;
+mod bundle;
+mod cache;
+mod case;
+mod check;
+mod client;
+mod compress;
+mod config_file;
+mod console;
+#[cfg(unix)]
+mod daemon;
+mod digest;
+mod doc;
+mod etag;
+mod exit;
+mod fallback;
+mod feed;
+mod forwarded;
+mod geoip;
+mod hosts;
+mod http10;
+mod i18n;
+mod info;
+mod inject;
+mod language;
+mod listen;
+mod log_format;
+mod maintenance;
+mod mdns;
+mod media;
+mod mirror;
+mod mmap;
+mod mock;
+mod negotiate;
+mod preload;
+#[cfg(unix)]
+mod privs;
+mod proxy_protocol;
+mod qr;
+mod range;
+mod record;
+mod request_id;
+mod request_log;
+mod routes;
+#[cfg(target_os = "linux")]
+mod sandbox;
+#[cfg(windows)]
+mod service;
+mod stats;
+mod stdin;
+mod template;
+mod tui;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod vary;
+mod vfs;
+mod webhook;
+
+use listen::{Connection, ListenAddr, Listener, SocketOptions, StdListener};
+use negotiate::Format;
+use vfs::{LocalFs, Vfs, VfsFile};
 
 // Developer extensions
 mod ext;
 
+/// The body of every response. Responses are built in memory before being
+/// sent, so the body is always a single buffer.
+type Body = http_body_util::Full<Bytes>;
+
+/// The address of the client that sent a request, kept in the request's
+/// extensions. Behind a proxy speaking the PROXY protocol, or a trusted proxy
+/// adding forwarding headers, this is the address the proxy passed on. There
+/// is none for unix socket connections.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub SocketAddr);
+
 fn main() {
     // Set up our error handling immediately. The situations in which `run` can
     // actually return errors are few though - any errors propagated up to the
@@ -32,6 +120,7 @@ fn main() {
     // HTTP service additionally converts any errors to HTTP error responses.
     if let Err(e) = run() {
         log_error_chain(&e);
+        std::process::exit(1);
     }
 }
 
@@ -46,136 +135,1580 @@ fn log_error_chain(mut e: &dyn StdError) {
 }
 
 fn run() -> Result<()> {
-    // Initialize logging, and log the "info" level for this crate only, unless
-    // the environment contains `RUST_LOG`.
-    let env = Env::new().default_filter_or("basic_http_server=info");
+    // Create the configuration from the command line arguments. It
+    // includes the IP address and port to listen on and the path to use
+    // as the HTTP server's root directory. Other subcommands don't run the
+    // server: `check` checks its configuration, `bundle` builds a standalone
+    // executable, and on Windows, others manage the server's service.
+    let command = parse_command_from_cmdline();
+
+    // Initialize logging, and log the "info" level for this crate only, or
+    // the level set by `-q` or `-v`, unless the environment contains
+    // `RUST_LOG`. Errors parsing the arguments are logged once it's ready.
+    let level = match &command {
+        Ok(Command::Serve(config)) | Ok(Command::Check(config)) | Ok(Command::Bench(config, _)) => {
+            config.verbosity.log_level()
+        }
+        _ => "info",
+    };
+    let env = Env::new().default_filter_or(format!("basic_http_server={}", level));
     Builder::from_env(env)
         .default_format_module_path(false)
         .default_format_timestamp(false)
         .init();
+    // Panics are logged, with a backtrace, rather than only printed, so that
+    // they're in the log file, and the server's responses to them are clear
+    panic::set_hook(Box::new(|info| {
+        error!("{}\n{}", info, Backtrace::force_capture());
+    }));
 
-    // Create the configuration from the command line arguments. It
-    // includes the IP address and port to listen on and the path to use
-    // as the HTTP server's root directory.
-    let config = parse_config_from_cmdline()?;
+    let config = match command? {
+        Command::Serve(config) => *config,
+        Command::Bundle { dir, output } => return bundle::bundle(&dir, &output),
+        Command::Check(config) => return check::check(&config),
+        Command::Bench(config, options) => return bench::run(*config, options),
+        Command::Replay { file, target } => return record::replay(&file, &target),
+        Command::GenConfig(config) => {
+            print!("{}", config);
+            return Ok(());
+        }
+        Command::Completions(shell) => {
+            app().gen_completions_to("basic-http-server", shell, &mut io::stdout());
+            return Ok(());
+        }
+        #[cfg(windows)]
+        Command::InstallService(args) => return service::install(args),
+        #[cfg(windows)]
+        Command::UninstallService => return service::uninstall(),
+    };
+
+    prepare(&config)?;
+
+    // Sockets passed by systemd replace the configured address. They're
+    // taken before the runtime starts any threads.
+    let inherited = listen::systemd_listeners()?;
 
-    // Display the configuration to be helpful
+    // Likewise, forking to run in the background is only safe with one thread
+    #[cfg(unix)]
+    let daemon = config.daemon.clone();
+    #[cfg(unix)]
+    daemon::start(&daemon)?;
+
+    // Display the configuration to be helpful. The addresses are displayed
+    // once they're bound.
     info!("basic-http-server {}", env!("CARGO_PKG_VERSION"));
-    info!("addr: http://{}", config.addr);
-    info!("root dir: {}", config.root_dir.display());
-    info!("extensions: {}", config.use_extensions);
-
-    let server = Server::bind(&config.addr)
-        .serve(move || {
-            let config = config.clone();
-            service_fn(move |req| {
-                serve(&config, req).map_err(|e| {
-                    // Log any errors that result from handling a single HTTP
-                    // request. This _should_ be impossible - we expect our
-                    // service function to map all errors to HTTP error
-                    // responses.
-                    error!("request handler error: {}", e);
-                    e
-                })
-            })
-        })
-        .map_err(|e| {
-            // Log any errors that result from hyper's `Server` future failing.
-            // The tokio runtime expects to run a future that doesn't error so
-            // not sure how to square that with hyper's `Server` carrying an
-            // error type, but here hyper's error type is mapped to nil.
-            error!("server error: {}", e);
-            ()
-        });
+    if let Some(stdin) = &config.stdin {
+        info!("standard input: {}", stats::format_bytes(stdin.size() as f64));
+    } else if config.single_file {
+        info!("file: {}", config.root_dir.display());
+    } else {
+        info!("root dir: {}", config.root_dir.display());
+    }
+    info!("extensions: {}", config.extensions);
+    if let Some(dir) = &config.mock {
+        info!("mock API: {}", dir.display());
+    }
+
+    // The sandbox only covers threads started after it's entered, so has to
+    // come before the runtime's
+    #[cfg(target_os = "linux")]
+    {
+        if config.sandbox {
+            sandbox::enter(&config)?;
+        } else if config.read_only {
+            sandbox::forbid_writes(&config)?;
+        }
+    }
 
-    tokio::run(server);
+    #[cfg(windows)]
+    {
+        if config.service {
+            return service::run(config);
+        }
+    }
 
+    let result = start_runtime(config, inherited);
+    #[cfg(unix)]
+    daemon::stop(&daemon);
+    result
+}
+
+/// Get ready to serve: open the files and create the directories that the
+/// server writes to, and read standard input. This isn't done while parsing
+/// the arguments, so that `check` has no effects.
+fn prepare(config: &Config) -> Result<()> {
+    if let Some(record) = &config.record {
+        record.open()?;
+    }
+    if let Some(compression) = &config.compression {
+        compression.create_cache_dir()?;
+    }
+    if let Some(stdin) = &config.stdin {
+        stdin.read()?;
+    }
     Ok(())
 }
 
+/// Create the tokio runtime, which runs the server's futures on a pool of
+/// threads, or on this thread alone if asked for one thread, and run the
+/// server on it until the process is asked to stop.
+fn start_runtime(config: Config, inherited: Vec<StdListener>) -> Result<()> {
+    let mut builder = match config.threads {
+        Some(threads) if threads.get() == 1 => tokio::runtime::Builder::new_current_thread(),
+        Some(threads) => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            builder.worker_threads(threads.get());
+            builder
+        }
+        None => tokio::runtime::Builder::new_multi_thread(),
+    };
+    if let Some(blocking_threads) = config.blocking_threads {
+        builder.max_blocking_threads(blocking_threads.get());
+    }
+    let runtime = builder.enable_all().build()?;
+    runtime.block_on(run_server(config, inherited))
+}
+
+/// Bind the listening sockets, unless given sockets already listening, and
+/// accept connections on them until the process is asked to stop.
+async fn run_server(config: Config, inherited: Vec<StdListener>) -> Result<()> {
+    // Only sockets that the server bound itself are cleaned up
+    let (listeners, bound) = if inherited.is_empty() {
+        bind_all(&config)?
+    } else {
+        let listeners = inherited
+            .into_iter()
+            .map(Listener::from_std)
+            .collect::<io::Result<_>>()?;
+        (listeners, Vec::new())
+    };
+
+    let result = async {
+        // Nothing needs root once the sockets are bound
+        #[cfg(unix)]
+        {
+            if let Some(credentials) = &config.credentials {
+                privs::drop_to(credentials)?;
+            }
+        }
+        serve_listeners(listeners, config).await
+    }
+    .await;
+    bound.iter().for_each(listen::cleanup);
+    result
+}
+
+/// Bind each address to listen on, returning the listeners and the addresses
+/// bound. If any address can't be bound, those already bound are cleaned up.
+fn bind_all(config: &Config) -> Result<(Vec<Listener>, Vec<ListenAddr>)> {
+    let mut listeners = Vec::new();
+    let mut bound = Vec::new();
+    for addr in &config.addrs {
+        // With more than one acceptor, each gets its own socket bound to the
+        // same address, and the kernel spreads new connections between them.
+        // Unix sockets can't share an address, so always have one.
+        let acceptors = match addr {
+            ListenAddr::Tcp(_) => config.acceptors.map_or(1, NonZeroUsize::get),
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => 1,
+        };
+        let mut sockopts = config.sockopts.clone();
+        sockopts.reuseport |= acceptors > 1;
+        let mut addr = addr.clone();
+        for i in 0..acceptors {
+            let fallback = if i == 0 { config.port_fallback } else { 0 };
+            let listener = match listen::bind_with_fallback(&addr, &sockopts, fallback) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    bound.iter().for_each(listen::cleanup);
+                    return Err(e);
+                }
+            };
+            // Port 0, or falling back to another port, binds whichever port
+            // is free, which the other acceptors then have to share
+            if i == 0 {
+                if let Ok(local) = listener.local_addr() {
+                    addr = local;
+                }
+            }
+            listeners.push(listener);
+        }
+        bound.push(addr);
+    }
+    Ok((listeners, bound))
+}
+
+/// Accept connections on each listener until the process is asked to stop.
+async fn serve_listeners(mut listeners: Vec<Listener>, config: Config) -> Result<()> {
+    // Listeners' own addresses include the actual port where port 0 was
+    // asked for
+    let mut addrs = Vec::new();
+    for listener in &listeners {
+        let addr = listener.local_addr()?;
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if !config.preload.is_empty() {
+        preload::preload(&config).await;
+    }
+    for addr in &addrs {
+        info!("addr: {}", addr);
+        for addr in listen::interface_addrs(addr) {
+            info!("  reachable at http://{}", addr);
+        }
+    }
+    if config.qr {
+        match listen::lan_url(&addrs) {
+            Some(url) => qr::print(&url),
+            None => warn!("not listening on an address reachable from the network"),
+        }
+    }
+    if let Some(path) = &config.addr_file {
+        listen::write_addr_file(path, &addrs)?;
+    }
+    if let Some(info) = &config.info {
+        info.print(&config.root_dir, &addrs)?;
+    }
+    if let Some(path) = &config.open {
+        match browser::url(&addrs, path) {
+            Some(url) => browser::open(&url),
+            None => warn!("can't open a browser without a TCP address"),
+        }
+    }
+
+    let announcement = match &config.mdns {
+        Some(name) => mdns::announce(name, &addrs),
+        None => None,
+    };
+
+    if config.maintenance {
+        maintenance::set_enabled(true);
+    }
+    if let Some(port) = config.admin_port {
+        let listener = admin::bind(port).await?;
+        let token = match &config.admin_token {
+            Some(token) => token.clone(),
+            None => admin::random_token(),
+        };
+        tokio::spawn(admin::serve(listener, config.clone(), token));
+    }
+    stats::start();
+    if !config.webhooks.is_empty() {
+        let addrs: Vec<_> = addrs.iter().map(|addr| addr.to_string()).collect();
+        config.webhooks.fire(
+            webhook::Event::Started,
+            serde_json::json!({ "addrs": addrs }),
+        );
+    }
+    let dashboard = if config.tui && tui::available() {
+        Some(tokio::spawn(tui::run(addrs.clone())))
+    } else {
+        None
+    };
+    #[cfg(unix)]
+    tokio::spawn(maintenance::toggle_on_signal());
+
+    let last = listeners.pop().expect("at least one listener");
+    for listener in listeners {
+        tokio::spawn(accept_loop(listener, config.clone()));
+    }
+    tokio::select! {
+        _ = accept_loop(last, config.clone()) => {}
+        _ = shutdown_signal() => info!("shutting down"),
+        _ = admin::SHUTDOWN.notified() => info!("shutting down"),
+        _ = config.exit.wait() => {}
+    }
+    if let Some(dashboard) = dashboard {
+        dashboard.abort();
+        let _ = dashboard.await;
+        tui::leave();
+    }
+    if config.summary {
+        stats::print_summary();
+    }
+    if let Some(path) = &config.summary_json {
+        if let Err(e) = stats::write_summary(path) {
+            error!("failed to write summary to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(announcement) = announcement {
+        announcement.withdraw();
+    }
+    if !config.webhooks.is_empty() {
+        config
+            .webhooks
+            .fire_and_wait(webhook::Event::Stopped, serde_json::json!({}))
+            .await;
+    }
+    Ok(())
+}
+
+/// Wait for the process to be asked to stop, with ctrl-c or, on unix, with
+/// `SIGTERM`, or on Windows, by the service control manager.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("failed to listen for SIGTERM: {}", e),
+        }
+    }
+    #[cfg(windows)]
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = service::STOP.notified() => {}
+    }
+    #[cfg(not(windows))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Accept connections forever, serving each one on its own task.
+async fn accept_loop(listener: Listener, config: Config) {
+    loop {
+        let conn = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Errors accepting one connection, like running out of file
+                // descriptors, shouldn't stop the server.
+                error!("accept error: {}", e);
+                continue;
+            }
+        };
+        stats::connection();
+        let config = config.clone();
+        match conn {
+            Connection::Tcp(stream, peer) => {
+                if config.sockopts.nodelay {
+                    if let Err(e) = stream.set_nodelay(true) {
+                        debug!("failed to set TCP_NODELAY: {}", e);
+                    }
+                }
+                tokio::spawn(serve_connection(stream, Some(peer), config));
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                tokio::spawn(serve_connection(stream, None, config));
+            }
+        }
+    }
+}
+
+/// Serve the requests on one connection, until the client closes it. `peer`
+/// is the address the connection came from, if it's a TCP connection.
+async fn serve_connection<S>(mut stream: S, peer: Option<SocketAddr>, config: Config)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let client = if config.proxy_protocol {
+        match proxy_protocol::read_header(&mut stream).await {
+            Ok(Some(client)) => Some(client),
+            Ok(None) => peer,
+            Err(e) => {
+                debug!("bad PROXY protocol header: {}", e);
+                return;
+            }
+        }
+    } else {
+        peer
+    };
+    if let Some(client) = client {
+        debug!("connection from {}", client);
+    }
+
+    // hyper calls the service function for each request on the connection.
+    // Each call gets its own copy of the configuration, which is cheap, since
+    // the large parts are shared.
+    let service = service_fn(move |mut req: Request<Incoming>| {
+        if config.request_ids {
+            let id = request_id::RequestId::new(req.headers(), client, &config.trusted_proxies);
+            req.extensions_mut().insert(id);
+        }
+        let client =
+            client.map(|peer| forwarded::client(req.headers(), peer, &config.trusted_proxies));
+        if let Some(client) = client {
+            req.extensions_mut().insert(ClientAddr(client));
+        }
+        let config = config.clone();
+        async move {
+            serve(config, req).await.map_err(|e| {
+                // Log any errors that result from handling a single HTTP
+                // request. This _should_ be impossible - we expect our
+                // service function to map all errors to HTTP error
+                // responses.
+                error!("request handler error: {}", e);
+                e
+            })
+        }
+    });
+    // hyper works with its own I/O traits, so tokio's streams are adapted to
+    // them with `TokioIo`.
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+    if let Err(e) = conn.await {
+        debug!("connection error: {}", e);
+    }
+    if stdin::finished() {
+        info!("standard input was downloaded");
+        admin::SHUTDOWN.notify_one();
+    }
+    if exit::requests_served() {
+        info!("exiting, as the requests have been answered");
+        admin::SHUTDOWN.notify_one();
+    }
+}
+
 /// The configuration object, parsed from command line options
 #[derive(Clone)]
 pub struct Config {
-    addr: SocketAddr,
+    /// The addresses to listen on, which are all served the same
+    addrs: Vec<ListenAddr>,
+    /// A file to write the addresses listened on to, once bound
+    addr_file: Option<PathBuf>,
+    /// What to print about the server, once bound
+    info: Option<Arc<info::Info>>,
+    /// How many ports after a port in use to try instead
+    port_fallback: u16,
+    /// The path to open in the browser once listening
+    open: Option<String>,
+    /// Print a QR code of a URL for the network
+    qr: bool,
+    /// The instance name to announce the server as with mDNS
+    mdns: Option<String>,
+    /// The user and group to switch to after binding
+    #[cfg(unix)]
+    credentials: Option<privs::Credentials>,
+    #[cfg(unix)]
+    daemon: daemon::Options,
+    /// Run as a Windows service, started by the service control manager
+    #[cfg(windows)]
+    service: bool,
+    /// Confine filesystem access to the root with Landlock
+    #[cfg(target_os = "linux")]
+    sandbox: bool,
+    /// Write no files
+    read_only: bool,
     root_dir: PathBuf,
-    use_extensions: bool,
+    /// The root is a file, which is served for every request
+    single_file: bool,
+    /// The filesystem that `root_dir` is in, which is the local disk unless
+    /// the root is an archive
+    vfs: Arc<dyn Vfs>,
+    /// The developer extensions that are enabled
+    extensions: ext::Extensions,
+    /// Settings for parts of the site, overriding these
+    routes: routes::Routes,
+    /// The databases to look up where clients are in
+    geoip: Option<Arc<geoip::GeoIp>>,
+    /// The URL paths under which directories aren't listed, or every one if
+    /// there are none
+    no_listings: Option<Vec<String>>,
+    /// The pages the extensions have rendered, for viewing them again
+    renders: Arc<ext::RenderCache>,
+    /// The archives opened to serve files from inside them
+    archives: Arc<ext::ArchiveCache>,
+    gallery: bool,
+    /// Serve language variants of missing pages, in this language when none
+    /// suits the request better
+    default_language: Option<String>,
+    /// The language of error pages and listings, instead of the one each
+    /// request prefers
+    lang: Option<i18n::Lang>,
+    /// Send digests of files, remembered in this cache
+    digests: Option<Arc<digest::DigestCache>>,
+    /// How files' entity tags are made, if they have them
+    etags: etag::ETags,
+    /// Files of at least this many bytes are served with `mmap`
+    mmap_threshold: Option<u64>,
+    /// How responses are compressed, if they are
+    compression: Option<Arc<compress::Compression>>,
+    /// Globs of the files to read into the caches before serving
+    preload: Vec<String>,
+    /// The number of threads running the server, default one per core
+    threads: Option<NonZeroUsize>,
+    /// The most threads for blocking work, like reading files
+    blocking_threads: Option<NonZeroUsize>,
+    /// The number of sockets to accept connections on
+    acceptors: Option<NonZeroUsize>,
+    sockopts: SocketOptions,
+    /// Expect a PROXY protocol header on each connection
+    proxy_protocol: bool,
+    /// The proxies whose forwarding headers are believed
+    trusted_proxies: Vec<forwarded::Cidr>,
+    /// Give each request an ID
+    request_ids: bool,
+    /// The host names requests may be for, in lower case. Any are allowed if
+    /// there are none.
+    allowed_hosts: Vec<String>,
+    /// The user agents requests are allowed from
+    user_agents: agents::Rules,
+    /// Generate a `robots.txt` if the site has none
+    robots: Option<fallback::Robots>,
+    /// The directory, within the root, to serve a feed of at `/feed.xml`
+    feed: Option<String>,
+    /// The loopback port to serve the admin API on
+    admin_port: Option<u16>,
+    /// The token the admin API's `POST`s need, or a random one if not given
+    admin_token: Option<String>,
+    /// Show a dashboard of live traffic in the terminal
+    tui: bool,
+    /// How much is written to the terminal
+    verbosity: console::Verbosity,
+    /// The directory of fixtures for the mock API
+    mock: Option<PathBuf>,
+    /// Where to record requests
+    record: Option<Arc<record::Recorder>>,
+    /// The last few requests, served at `/__log`
+    request_log: Option<Arc<request_log::RequestLog>>,
+    /// The format of request lines, instead of the usual one
+    log_format: Option<Arc<log_format::LogFormat>>,
+    /// Where to send copies of requests
+    mirror: Option<mirror::Mirror>,
+    /// Where to post events
+    webhooks: webhook::Webhooks,
+    /// Standard input, to serve instead of the root
+    stdin: Option<Arc<stdin::Stdin>>,
+    /// The scripts and stylesheets to inject into HTML pages
+    injections: Arc<inject::Injections>,
+    /// Serve the root `index.html` for pages browsers ask for that don't exist
+    spa: bool,
+    /// Redirect paths that aren't found to files named in another case
+    case_insensitive: bool,
+    /// Have browsers check for changes each time they use a response
+    no_cache: bool,
+    /// Send the headers that isolate pages from other origins
+    cross_origin_isolated: bool,
+    /// Serve the number of downloads of each path at `/__stats`
+    hit_counters: bool,
+    /// Print a summary of the requests served on exit
+    summary: bool,
+    /// Write the summary to this file as JSON on exit
+    summary_json: Option<PathBuf>,
+    /// When to stop without being asked
+    exit: exit::Conditions,
+    /// Start in maintenance mode
+    maintenance: bool,
+    /// The page to serve in maintenance mode
+    maintenance_page: Option<PathBuf>,
+    /// The directory of templates that generated pages are rendered with
+    template_dir: Option<PathBuf>,
 }
 
-fn parse_config_from_cmdline() -> Result<Config> {
-    let matches = App::new("basic-http-server")
-        .version(env!("CARGO_PKG_VERSION"))
-        .about("A basic HTTP file server")
-        .args_from_usage(
-            "[ROOT] 'Sets the root dir (default \".\")'
-             [ADDR] -a --addr=[ADDR] 'Sets the IP:PORT combination (default \"127.0.0.1:4000\")',
-             [EXT] -x 'Enable developer extensions'",
+/// What to do, as parsed from the command line
+enum Command {
+    Serve(Box<Config>),
+    /// Check the configuration to serve with
+    Check(Box<Config>),
+    /// Measure a server, or this one serving with the configuration
+    Bench(Box<Config>, bench::Options),
+    Bundle {
+        dir: PathBuf,
+        output: PathBuf,
+    },
+    /// Send recorded requests to a server
+    Replay {
+        file: PathBuf,
+        target: String,
+    },
+    /// Print a shell completion script
+    Completions(clap::Shell),
+    /// Print a config file
+    GenConfig(String),
+    /// Install the Windows service, to serve with these arguments
+    #[cfg(windows)]
+    InstallService(Vec<std::ffi::OsString>),
+    #[cfg(windows)]
+    UninstallService,
+}
+
+/// The command line interface. Serving is the default, and its arguments can
+/// be given with or without the `serve` subcommand.
+fn app() -> App<'static, 'static> {
+    let app = serve_args(
+        App::new("basic-http-server")
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("A basic HTTP file server"),
+    )
+    .subcommand(serve_args(
+        SubCommand::with_name("serve").about("Serves the root dir, as without a subcommand"),
+    ))
+    .subcommand(serve_args(
+        SubCommand::with_name("check")
+            .about("Checks the arguments to serve with, and the root dir, without serving"),
+    ))
+    .subcommand(serve_args(
+        SubCommand::with_name("gen-config")
+            .about("Prints a config file with every argument to serve with, set to those given"),
+    ))
+    .subcommand(serve_args(
+        SubCommand::with_name("bench")
+            .about("Measures how fast a server answers requests, or this one serving the root dir")
+            .args_from_usage(
+                "-u --url=[URL] 'The URL to request, or a path on this server (default /)'
+                 -c --concurrency=[N] 'Sends requests on N connections at once (default 10)'
+                 -n --requests=[N] 'Sends N requests in all (default 1000)'
+                 --duration=[DURATION] 'Sends requests for this long instead, like 10s'",
+            ),
+    ))
+    .subcommand(
+        SubCommand::with_name("bundle")
+            .about("Bundles a directory into an executable that serves it")
+            .args_from_usage(
+                "<DIR> 'The directory to bundle'
+                 <OUTPUT> -o --output=<OUTPUT> 'The executable to create'",
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("replay")
+            .about("Sends the requests recorded with --record to a server")
+            .args_from_usage(
+                "<FILE> 'The file of recorded requests'
+                 [TARGET] -t --target=[URL] 'The server to send them to (default \"http://127.0.0.1:4000\")'",
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("completions")
+            .about("Prints a script for completing the arguments in a shell")
+            .arg(
+                clap::Arg::from_usage("<SHELL> 'The shell to complete in'")
+                    .possible_values(&["bash", "elvish", "fish", "powershell", "zsh"]),
+            ),
+    );
+    #[cfg(windows)]
+    let app = app
+        .subcommand(
+            SubCommand::with_name("install-service")
+                .about("Installs a Windows service that serves with the given arguments")
+                .setting(clap::AppSettings::TrailingVarArg)
+                .setting(clap::AppSettings::AllowLeadingHyphen)
+                .arg(clap::Arg::from_usage(
+                    "[ARGS]... 'The arguments to serve with, like the root dir'",
+                )),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("uninstall-service")
+                .about("Stops and removes the Windows service"),
+        );
+    app
+}
 
-    let addr = matches.value_of("ADDR").unwrap_or("127.0.0.1:4000");
-    let root_dir = matches.value_of("ROOT").unwrap_or(".");
-    let ext = matches.is_present("EXT");
+/// Add the arguments for serving, to the app and the subcommands that take
+/// them.
+fn serve_args(app: App<'static, 'static>) -> App<'static, 'static> {
+    let app = args::SERVE_ARGS
+        .iter()
+        .fold(app, |app, arg| app.arg(arg.to_clap()));
+    // The service control manager runs the server with `--service`, which
+    // isn't for people to use
+    #[cfg(windows)]
+    let app = app.arg(clap::Arg::with_name("service").long("service").hidden(true));
+    app
+}
 
-    Ok(Config {
-        addr: addr.parse().map_err(Error::AddrParse)?,
-        root_dir: PathBuf::from(root_dir),
-        use_extensions: ext,
-    })
+/// The matches for the arguments for serving, which are given with or
+/// without `serve`, and to `check` and `gen-config`
+fn serve_matches<'a>(matches: &'a ArgMatches<'static>) -> &'a ArgMatches<'static> {
+    match matches.subcommand() {
+        ("serve", Some(matches))
+        | ("check", Some(matches))
+        | ("gen-config", Some(matches))
+        | ("bench", Some(matches)) => matches,
+        _ => matches,
+    }
 }
 
-/// The function that returns a future of an HTTP response for each hyper
-/// Request that is received. Errors are turned into an Error response (404 or
-/// 500), and never propagated upward for hyper to deal with.
-fn serve(config: &Config, req: Request<Body>) -> impl Future<Item = Response<Body>, Error = Error> {
-    let config = config.clone();
-    serve_file(&req, &config.root_dir)
-        .then(
-            // Give developer extensions an opportunity to post-process the request/response pair
-            move |resp| ext::serve(config, req, resp).map_err(Error::from),
+fn parse_command_from_cmdline() -> Result<Command> {
+    let mut cmdline: Vec<OsString> = std::env::args_os().collect();
+    let mut matches = app().get_matches_from(&cmdline);
+
+    if let Some(matches) = matches.subcommand_matches("bundle") {
+        return Ok(Command::Bundle {
+            dir: PathBuf::from(matches.value_of("DIR").expect("required arg")),
+            output: PathBuf::from(matches.value_of("OUTPUT").expect("required arg")),
+        });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        return Ok(Command::Replay {
+            file: PathBuf::from(matches.value_of("FILE").expect("required arg")),
+            target: matches
+                .value_of("TARGET")
+                .unwrap_or("http://127.0.0.1:4000")
+                .to_owned(),
+        });
+    }
+
+    #[cfg(windows)]
+    {
+        if let Some(matches) = matches.subcommand_matches("install-service") {
+            let args = matches.values_of_os("ARGS").into_iter().flatten();
+            return Ok(Command::InstallService(args.map(Into::into).collect()));
+        }
+        if matches.subcommand_matches("uninstall-service").is_some() {
+            return Ok(Command::UninstallService);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let shell = matches.value_of("SHELL").expect("required arg");
+        return Ok(Command::Completions(
+            shell.parse().expect("one of the possible values"),
+        ));
+    }
+
+    // Arguments from a config file go before those on the command line,
+    // which override them
+    if let Some(path) = serve_matches(&matches).value_of("config") {
+        let app = app();
+        config_file::add_args(Path::new(path), serve_matches(&matches), &mut cmdline)?;
+        matches = app.get_matches_from(&cmdline);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("gen-config") {
+        return Ok(Command::GenConfig(config_file::generate(matches)?));
+    }
+
+    let check = matches.subcommand_name() == Some("check");
+    let bench = matches
+        .subcommand_matches("bench")
+        .map(bench::Options::parse)
+        .transpose()?;
+    let matches = serve_matches(&matches);
+    let check = check || matches.is_present("check");
+
+    let addrs = match matches.values_of("ADDR") {
+        Some(addrs) => {
+            let mut all = Vec::new();
+            for addr in addrs {
+                all.extend(listen::parse_addr(addr)?);
+            }
+            all
+        }
+        None => vec![ListenAddr::default()],
+    };
+    let trusted_proxies = matches
+        .values_of("trusted-proxy")
+        .into_iter()
+        .flatten()
+        .map(str::parse)
+        .collect::<Result<_>>()?;
+    let allowed_hosts = matches
+        .value_of("allowed-hosts")
+        .into_iter()
+        .flat_map(|hosts| hosts.split(','))
+        .map(|host| host.trim().to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect();
+    let user_agents = agents::Rules::new(
+        matches.values_of("allow-user-agent").into_iter().flatten(),
+        matches.values_of("deny-user-agent").into_iter().flatten(),
+    )?;
+    let robots = match matches.value_of("robots") {
+        _ if !matches.is_present("robots") => None,
+        Some("allow") => Some(fallback::Robots::Allow),
+        _ => Some(fallback::Robots::Deny),
+    };
+    let feed = match matches.value_of("feed") {
+        _ if !matches.is_present("feed") => None,
+        dir => {
+            let dir = dir.unwrap_or("");
+            Some(feed::dir(dir).ok_or_else(|| Error::FeedDir(dir.to_owned()))?)
+        }
+    };
+    let lang = matches
+        .value_of("lang")
+        .map(|lang| i18n::Lang::parse(lang).ok_or_else(|| Error::UnknownLanguage(lang.to_owned())))
+        .transpose()?;
+    let no_listings = match matches.values_of("no-listings") {
+        _ if !matches.is_present("no-listings") => None,
+        prefixes => Some(prefixes.into_iter().flatten().map(str::to_string).collect()),
+    };
+    let template_dir = matches.value_of("template-dir").map(PathBuf::from);
+    if let Some(dir) = &template_dir {
+        template::use_dir(dir.clone())?;
+    }
+    let admin_port = matches
+        .value_of("admin-port")
+        .map(|port| port.parse().map_err(Error::PortParse))
+        .transpose()?;
+    let addr_file = matches.value_of("addr-file").map(PathBuf::from);
+    let exit = exit::Conditions {
+        after: matches
+            .value_of("exit-after")
+            .map(|after| {
+                parse_duration(after).ok_or_else(|| Error::DurationParse(after.to_owned()))
+            })
+            .transpose()?,
+        requests: matches
+            .value_of("exit-after-requests")
+            .map(|n| n.parse().map_err(Error::RequestsParse))
+            .transpose()?,
+        #[cfg(unix)]
+        with_parent: Some(std::os::unix::process::parent_id())
+            .filter(|_| matches.is_present("exit-with-parent")),
+    };
+    let info = if matches.is_present("print-info") || matches.is_present("info-file") {
+        Some(Arc::new(info::Info::new(
+            config_file::to_json(matches),
+            matches.value_of("info-file").map(PathBuf::from),
+        )))
+    } else {
+        None
+    };
+    let request_log = match matches.value_of("request-log") {
+        _ if !matches.is_present("request-log") => None,
+        len => {
+            let len = match len {
+                Some(len) => len
+                    .parse::<NonZeroUsize>()
+                    .map_err(Error::RequestLogParse)?
+                    .get(),
+                None => 200,
+            };
+            Some(Arc::new(request_log::RequestLog::new(len)))
+        }
+    };
+    let record = matches.value_of("record").map(|path| {
+        let responses = matches.is_present("record-responses");
+        Arc::new(record::Recorder::new(PathBuf::from(path), responses))
+    });
+    let mirror = matches
+        .value_of("mirror")
+        .map(mirror::Mirror::new)
+        .transpose()?;
+    let webhooks = webhook::Webhooks::new(matches.values_of("webhook").into_iter().flatten())?;
+    let routes = routes::Routes::new(matches.values_of("route").into_iter().flatten())?;
+    let geoip = match matches.values_of("geoip") {
+        Some(paths) => Some(Arc::new(geoip::GeoIp::open(paths)?)),
+        None => None,
+    };
+    let log_format = matches
+        .value_of("log-format")
+        .map(log_format::LogFormat::parse)
+        .transpose()?
+        .map(Arc::new);
+    let stdin = if matches.is_present("stdin") {
+        let content_type = matches.value_of("content-type");
+        let once = matches.is_present("once");
+        Some(Arc::new(stdin::Stdin::new(content_type, once)?))
+    } else {
+        None
+    };
+    let mock = matches.value_of("mock").map(PathBuf::from);
+    let wasm_dev = matches.is_present("wasm-dev");
+    let auto_refresh = matches
+        .value_of("auto-refresh")
+        .map(|refresh| parse_duration(refresh).ok_or_else(|| Error::DurationParse(refresh.to_owned())))
+        .transpose()?;
+    let paths_of = |name| {
+        let paths = matches.values_of(name).into_iter().flatten();
+        paths.map(PathBuf::from).collect()
+    };
+    if let Some(dir) = &mock {
+        mock::check(dir)?;
+    }
+    let port_fallback = matches
+        .value_of("port-fallback")
+        .unwrap_or("0")
+        .parse()
+        .map_err(Error::PortParse)?;
+    let docs = if matches.is_present("doc") {
+        Some(doc::find()?)
+    } else {
+        None
+    };
+    let open = if matches.is_present("open") {
+        Some(matches.value_of("open").unwrap_or("/").to_string())
+    } else {
+        docs.as_ref().map(|docs| docs.index.clone())
+    };
+    let mdns = if matches.is_present("mdns") {
+        Some(
+            matches
+                .value_of("mdns")
+                .unwrap_or("basic-http-server")
+                .to_string(),
         )
-        .then(|maybe_resp| {
-            // Turn any errors into an HTTP error response.
-            //
-            // This `Either` future is a simple way to create a concrete future
-            // (i.e. a non-boxed future) of one of two different `Future` types.
-            // We'll use it a lot.
-            //
-            // Here type `A` is a `FutureResult`, and type `B` is some `impl Future`
-            // returned by `make_error_response`.
-            match maybe_resp {
-                Ok(r) => Either::A(future::ok(r)),
-                Err(e) => Either::B(make_error_response(e)),
+    } else {
+        None
+    };
+    #[cfg(unix)]
+    let credentials = match (matches.value_of("user"), matches.value_of("group")) {
+        (None, None) => None,
+        (user, group) => Some(privs::resolve(user, group)?),
+    };
+    #[cfg(unix)]
+    let daemon = daemon::Options {
+        detach: matches.is_present("daemon"),
+        pid_file: matches.value_of("pid-file").map(PathBuf::from),
+        log_file: matches.value_of("log-file").map(PathBuf::from),
+    };
+    let root_dir = matches.value_of("ROOT");
+    let extensions = if matches.is_present("EXT") {
+        ext::Extensions::all()
+    } else {
+        let list = matches.value_of("ext").unwrap_or("");
+        ext::Extensions::parse(list).map_err(Error::UnknownExtension)?
+    };
+    let gallery = matches.is_present("GALLERY");
+    let metadata_ttl = matches
+        .value_of("metadata-ttl")
+        .map(|secs| secs.parse().map_err(Error::MetadataTtlParse))
+        .transpose()?;
+    let cache_size: Option<usize> = matches
+        .value_of("cache-size")
+        .map(|mb| parse_size(mb, 1 << 20, Error::CacheSizeParse))
+        .transpose()?;
+    let cache_file_size: u64 = parse_size(
+        matches.value_of("cache-file-size").unwrap_or("64"),
+        1 << 10,
+        Error::CacheSizeParse,
+    )?;
+    let compression = if [
+        "compress",
+        "compress-level",
+        "compress-min-size",
+        "compress-types",
+        "no-compress-types",
+        "compress-cache",
+        "compress-cache-dir",
+    ]
+    .iter()
+    .any(|&name| matches.is_present(name))
+    {
+        let min_size = matches
+            .value_of("compress-min-size")
+            .map(|bytes| bytes.parse().map_err(Error::CompressMinSizeParse))
+            .transpose()?
+            .unwrap_or(compress::DEFAULT_MIN_SIZE);
+        let mut compression = compress::Compression::new(
+            matches.values_of("compress-level").into_iter().flatten(),
+            min_size,
+            matches.value_of("compress-types"),
+            matches.value_of("no-compress-types"),
+        )?;
+        if let Some(mb) = matches.value_of("compress-cache") {
+            let size = parse_size(mb, 1 << 20, Error::CacheSizeParse)?;
+            compression.cache_in_memory(size);
+        }
+        if let Some(dir) = matches.value_of("compress-cache-dir") {
+            compression.cache_in_dir(PathBuf::from(dir));
+        }
+        Some(Arc::new(compression))
+    } else {
+        None
+    };
+    let digests: Option<Arc<digest::DigestCache>> = if matches.is_present("digest") {
+        Some(Arc::default())
+    } else {
+        None
+    };
+    // Strong tags share the hashes of `--digest`, if it's on
+    let etags = match matches.value_of("etag") {
+        Some("weak") => etag::ETags::Weak,
+        Some("strong") => etag::ETags::Strong(digests.clone().unwrap_or_default()),
+        _ => etag::ETags::Off,
+    };
+    let mmap_threshold: Option<u64> = matches
+        .value_of("mmap-threshold")
+        .map(|kb| parse_size(kb, 1 << 10, Error::MmapThresholdParse))
+        .transpose()?;
+    let threads = matches
+        .value_of("threads")
+        .map(|n| n.parse().map_err(Error::ThreadsParse))
+        .transpose()?;
+    let blocking_threads = matches
+        .value_of("blocking-threads")
+        .map(|n| n.parse().map_err(Error::ThreadsParse))
+        .transpose()?;
+    let acceptors = matches
+        .value_of("acceptors")
+        .map(|n| n.parse().map_err(Error::AcceptorsParse))
+        .transpose()?;
+    let buffer_size = |name| -> Result<Option<u32>> {
+        matches
+            .value_of(name)
+            .map(|kb| parse_size(kb, 1 << 10, Error::SocketOptionParse))
+            .transpose()
+    };
+    let sockopts = SocketOptions {
+        reuseport: matches.is_present("reuseport"),
+        nodelay: matches.is_present("nodelay"),
+        backlog: matches
+            .value_of("backlog")
+            .unwrap_or("128")
+            .parse()
+            .map_err(Error::SocketOptionParse)?,
+        send_buffer: buffer_size("send-buffer")?,
+        recv_buffer: buffer_size("recv-buffer")?,
+    };
+
+    // A bundle serves the site embedded in it, unless given another root
+    let (root_dir, vfs): (PathBuf, Arc<dyn Vfs>) = match (docs, root_dir) {
+        (Some(docs), _) => (docs.dir, Arc::new(LocalFs)),
+        (None, Some(root_dir)) => {
+            let root_dir = PathBuf::from(root_dir);
+            if archive::is_archive(&root_dir) && root_dir.is_file() {
+                let vfs = archive::open(&root_dir)?;
+                (root_dir, vfs)
+            } else {
+                (root_dir, Arc::new(LocalFs))
+            }
+        }
+        (None, None) => {
+            let exe = std::env::current_exe()?;
+            match archive::open_bundle(&exe)? {
+                Some(vfs) => (exe, vfs),
+                None => (PathBuf::from("."), Arc::new(LocalFs)),
             }
+        }
+    };
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    let vfs: Arc<dyn Vfs> = if matches.is_present("io-uring") && vfs.is_local() {
+        Arc::new(uring::UringFs)
+    } else {
+        vfs
+    };
+
+    let vfs: Arc<dyn Vfs> = match metadata_ttl {
+        Some(secs) => Arc::new(cache::MetadataCache::new(vfs, Duration::from_secs(secs))),
+        None => vfs,
+    };
+    let vfs: Arc<dyn Vfs> = match cache_size {
+        Some(size) => Arc::new(cache::ContentCache::new(vfs, size, cache_file_size)),
+        None => vfs,
+    };
+
+    let config = Box::new(Config {
+        addrs,
+        addr_file,
+        info,
+        port_fallback,
+        open,
+        qr: matches.is_present("qr"),
+        mdns,
+        #[cfg(unix)]
+        credentials,
+        #[cfg(unix)]
+        daemon,
+        #[cfg(windows)]
+        service: matches.is_present("service"),
+        #[cfg(target_os = "linux")]
+        sandbox: matches.is_present("sandbox"),
+        read_only: matches.is_present("read-only"),
+        single_file: vfs.is_local() && root_dir.is_file(),
+        root_dir,
+        vfs,
+        extensions,
+        routes,
+        geoip,
+        no_listings,
+        renders: Arc::default(),
+        archives: Arc::default(),
+        gallery,
+        default_language: matches.value_of("default-language").map(str::to_string),
+        lang,
+        digests,
+        etags,
+        mmap_threshold,
+        compression,
+        preload: matches
+            .values_of("preload")
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect(),
+        threads,
+        blocking_threads,
+        acceptors,
+        sockopts,
+        proxy_protocol: matches.is_present("proxy-protocol"),
+        trusted_proxies,
+        request_ids: matches.is_present("request-ids"),
+        allowed_hosts,
+        user_agents,
+        robots,
+        feed,
+        admin_port,
+        admin_token: matches.value_of("admin-token").map(str::to_string),
+        tui: matches.is_present("tui"),
+        verbosity: console::Verbosity::new(
+            matches.is_present("quiet"),
+            matches
+                .occurrences_of("verbose")
+                .max(log_format.is_some() as u64),
+        ),
+        mock,
+        record,
+        request_log,
+        log_format,
+        mirror,
+        webhooks,
+        stdin,
+        injections: Arc::new(inject::Injections {
+            scripts: paths_of("inject-script"),
+            styles: paths_of("inject-css"),
+            refresh: auto_refresh,
+        }),
+        spa: wasm_dev,
+        case_insensitive: matches.is_present("case-insensitive"),
+        no_cache: wasm_dev,
+        cross_origin_isolated: wasm_dev,
+        hit_counters: matches.is_present("hit-counters"),
+        summary: matches.is_present("summary"),
+        summary_json: matches.value_of("summary-json").map(PathBuf::from),
+        exit,
+        maintenance: matches.is_present("maintenance"),
+        maintenance_page: matches.value_of("maintenance-page").map(PathBuf::from),
+        template_dir,
+    });
+    Ok(match bench {
+        Some(options) => Command::Bench(config, options),
+        None if check => Command::Check(config),
+        None => Command::Serve(config),
+    })
+}
+
+/// The function that returns an HTTP response for each hyper Request that is
+/// received. Errors are turned into an Error response (404 or 500), and never
+/// propagated upward for hyper to deal with.
+async fn serve(mut config: Config, mut req: Request<Incoming>) -> Result<Response<Body>> {
+    if let Some(mirror) = &config.mirror {
+        mirror.mirror(&req);
+    }
+    let vary = vary::Vary::default();
+    req.extensions_mut().insert(vary.clone());
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let version = req.version();
+    if !config.routes.is_empty() {
+        let routes = config.routes.clone();
+        routes.apply(&mut config, &path);
+    }
+    let recorder = config.record.clone();
+    let recorded = recorder.as_ref().map(|_| record::request(&req));
+    // The log's own requests would crowd out the rest
+    let request_log = config
+        .request_log
+        .clone()
+        .filter(|_| path != request_log::PATH);
+    let logged = request_log.as_ref().map(|_| record::request(&req));
+    let log_format = config.log_format.clone();
+    let captured = log_format.as_ref().map(|format| format.capture(&req));
+    let location = config.geoip.as_ref().and_then(|geoip| {
+        let client = req.extensions().get::<ClientAddr>()?;
+        Some(geoip.lookup(client.0.ip()))
+    });
+    let request_id = req.extensions().get::<request_id::RequestId>().cloned();
+    let started = Instant::now();
+    let print_request = config.verbosity >= console::Verbosity::Requests && !config.tui;
+    let no_cache = config.no_cache;
+    let cross_origin_isolated = config.cross_origin_isolated;
+    let webhooks = config.webhooks.clone();
+    let exit = config.exit.clone();
+    let compression = config.compression.clone();
+    let accept_encoding = compression
+        .as_ref()
+        .and_then(|_| vary::header(&req, header::ACCEPT_ENCODING).cloned());
+    // Tags are checked once the response is final, since compression
+    // changes them
+    let if_none_match = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .filter(|_| !config.etags.is_off() && matches!(method, Method::GET | Method::HEAD))
+        .cloned();
+    // For the error page if responding fails or panics
+    let accept = req.headers().get(header::ACCEPT).cloned();
+    let accept_language = req.headers().get(header::ACCEPT_LANGUAGE).cloned();
+    let lang = config.lang;
+    // Responding runs as its own task, so that a panic, like one in an
+    // extension rendering a page, only fails this request with a 500,
+    // instead of ending the connection
+    let responding = {
+        let vary = vary.clone();
+        tokio::spawn(async move { respond(config, req, &vary).await })
+    };
+    let error_format = || {
+        vary.add(header::ACCEPT);
+        let lang = lang.unwrap_or_else(|| {
+            vary.add(header::ACCEPT_LANGUAGE);
+            i18n::from_accept_language(accept_language.as_ref())
+        });
+        (negotiate::from_accept(accept.as_ref()), lang)
+    };
+    // An error is answered with an error page too, as hyper would drop the
+    // connection without a response
+    let mut resp = match responding.await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(e)) => {
+            let (format, lang) = error_format();
+            make_error_response(e, format, lang)?
+        }
+        Err(e) => {
+            // The panic hook has logged the panic itself
+            error!("failed to respond to {} {}: {}", method, path, e);
+            let (format, lang) = error_format();
+            make_error_response_from_code(StatusCode::INTERNAL_SERVER_ERROR, format, lang)?
+        }
+    };
+    if let Some(compression) = compression {
+        resp = compression.apply(&path, accept_encoding.as_ref(), resp).await;
+    }
+    if let Some(if_none_match) = if_none_match {
+        if etag::none_match(&if_none_match, &resp) {
+            resp = etag::not_modified(&resp);
+        }
+    }
+    vary.apply(&mut resp);
+    let headers = resp.headers_mut();
+    if no_cache {
+        headers
+            .entry(header::CACHE_CONTROL)
+            .or_insert(HeaderValue::from_static("no-cache"));
+    }
+    // Needed for `SharedArrayBuffer`, which threads in WebAssembly use
+    if cross_origin_isolated {
+        headers.insert(
+            "cross-origin-opener-policy",
+            HeaderValue::from_static("same-origin"),
+        );
+        headers.insert(
+            "cross-origin-embedder-policy",
+            HeaderValue::from_static("require-corp"),
+        );
+    }
+    if let Some(request_id) = &request_id {
+        headers.insert(request_id::HEADER, request_id.header_value());
+    }
+    // The server stops once the last request's connection closes, so it
+    // mustn't be kept open
+    if exit.count_request() {
+        headers.insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    if version == Version::HTTP_10 {
+        http10::adapt(&mut resp);
+    }
+    let elapsed = started.elapsed();
+    if print_request {
+        match (log_format, captured) {
+            (Some(format), Some(captured)) => {
+                let line = format.format(&captured, &resp, elapsed, location.as_ref());
+                eprintln!("{}", line);
+            }
+            _ => console::request(
+                &method,
+                &path,
+                &resp,
+                elapsed,
+                location.as_ref(),
+                request_id.as_ref(),
+            ),
+        }
+    }
+    if resp.status() == StatusCode::NOT_FOUND && !webhooks.is_empty() {
+        webhooks.fire(
+            webhook::Event::NotFound,
+            serde_json::json!({ "method": method.as_str(), "path": path }),
+        );
+    }
+    if let (Some(request_log), Some(mut logged)) = (request_log, logged) {
+        if let Some(location) = &location {
+            logged["location"] = serde_json::json!(location);
+        }
+        if let Some(request_id) = &request_id {
+            logged["request_id"] = request_id.as_str().into();
+        }
+        request_log.add(logged, &resp, elapsed);
+    }
+    stats::response(method, path, &resp, elapsed);
+    if let Some(location) = &location {
+        stats::location(location);
+    }
+    if let (Some(recorder), Some(recorded)) = (recorder, recorded) {
+        recorder.record(recorded, &resp).await;
+    }
+    Ok(resp)
+}
+
+/// Respond to a request, recording in `vary` the request headers that the
+/// response depends on.
+async fn respond(
+    config: Config,
+    req: Request<Incoming>,
+    vary: &vary::Vary,
+) -> Result<Response<Body>> {
+    if !config.allowed_hosts.is_empty() && !hosts::allowed(&req, &config.allowed_hosts) {
+        let lang = i18n::for_request(&config, &req);
+        return make_error_response_from_code(StatusCode::FORBIDDEN, negotiate::format(&req), lang);
+    }
+    if !config.user_agents.is_empty() && !config.user_agents.allows(&req) {
+        let lang = i18n::for_request(&config, &req);
+        return make_error_response_from_code(StatusCode::FORBIDDEN, negotiate::format(&req), lang);
+    }
+    if maintenance::is_enabled() {
+        let lang = i18n::for_request(&config, &req);
+        return maintenance::respond(&config, negotiate::format(&req), lang).await;
+    }
+
+    if let Some(dir) = &config.mock {
+        if let Some(resp) = mock::respond(dir, &req).await? {
+            return Ok(resp);
+        }
+    }
+
+    // Nothing can be uploaded, so requests with a body are refused before
+    // it's read. A client waiting to send one, with `Expect: 100-continue`,
+    // gets this answer instead of a go-ahead.
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let format = negotiate::format(&req);
+        let lang = i18n::for_request(&config, &req);
+        let mut resp = make_error_response_from_code(StatusCode::METHOD_NOT_ALLOWED, format, lang)?;
+        resp.headers_mut()
+            .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return Ok(resp);
+    }
+
+    // Ranges are of the response as finally served, after extensions
+    let range = match *req.method() {
+        Method::GET => req.headers().get(header::RANGE).cloned(),
+        _ => None,
+    };
+    let if_range = req.headers().get(header::IF_RANGE).cloned();
+    // Error pages are HTML or JSON, and in a language, as asked for
+    let accept = req.headers().get(header::ACCEPT).cloned();
+    let accept_language = req.headers().get(header::ACCEPT_LANGUAGE).cloned();
+    let lang = config.lang;
+
+    if let Some(stdin) = &config.stdin {
+        if req.uri().path() != "/" {
+            let lang = i18n::for_request(&config, &req);
+            return make_error_response_from_code(
+                StatusCode::NOT_FOUND,
+                negotiate::format(&req),
+                lang,
+            );
+        }
+        let whole = req.method() == Method::GET && range.is_none();
+        return range::respond(range, if_range, stdin.response(whole)?).await;
+    }
+
+    if config.hit_counters && req.uri().path() == stats::HITS_PATH {
+        let mut resp = json_to_response(&stats::downloads(), StatusCode::OK)?;
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        return Ok(resp);
+    }
+    if let Some(request_log) = &config.request_log {
+        if req.uri().path() == request_log::PATH {
+            return request_log.respond(&req).await;
+        }
+    }
+    if let Some(resp) = fallback::respond(&config, &req).await? {
+        return Ok(resp);
+    }
+
+    let mut resp = serve_file(&req, &config).await;
+    if config.case_insensitive && !config.single_file && is_not_found(&resp) {
+        if let Some(redirect) = case::redirect(&config, req.uri()).await? {
+            resp = Ok(redirect);
+        }
+    }
+
+    let injections = config.injections.clone();
+    let resp = if config.spa && is_not_found(&resp) && ext::wants_html(&req) {
+        // Single page apps have their own routes, so pages that aren't files
+        // are the app's
+        serve_path(&config, &req, config.root_dir.join("index.html")).await
+    } else {
+        // Give developer extensions an opportunity to post-process the request/response pair
+        ext::serve(config, req, resp).await
+    };
+
+    // Turn any errors into an HTTP error response.
+    match resp {
+        Ok(r) => range::respond(range, if_range, injections.apply(r).await).await,
+        Err(e) => {
+            vary.add(header::ACCEPT);
+            let lang = lang.unwrap_or_else(|| {
+                vary.add(header::ACCEPT_LANGUAGE);
+                i18n::from_accept_language(accept_language.as_ref())
+            });
+            make_error_response(e, negotiate::from_accept(accept.as_ref()), lang)
+        }
+    }
+}
+
+fn is_not_found(resp: &Result<Response<Body>>) -> bool {
+    matches!(resp, Err(e) if e.status() == StatusCode::NOT_FOUND)
+}
+
+/// Serve static files from a root directory, or the root file for every
+/// request if it's a file
+async fn serve_file(req: &Request<Incoming>, config: &Config) -> Result<Response<Body>> {
+    match path_for_request(config, req.uri()) {
+        Some(path) if config.single_file => {
+            let mut resp = serve_path(config, req, path).await?;
+            if let Some(disposition) = content_disposition(&config.root_dir) {
+                resp.headers_mut()
+                    .insert(header::CONTENT_DISPOSITION, disposition);
+            }
+            Ok(resp)
+        }
+        Some(path) => serve_path(config, req, path).await,
+        None => Err(Error::UrlToPath),
+    }
+}
+
+/// The path of the file to serve for a URI
+fn path_for_request(config: &Config, uri: &Uri) -> Option<PathBuf> {
+    if config.single_file {
+        Some(config.root_dir.clone())
+    } else {
+        local_path_for_request(uri, &config.root_dir)
+    }
+}
+
+/// A `Content-Disposition` header with the file's name, so that it's saved
+/// with that name, whatever the URL it was served at
+fn content_disposition(path: &Path) -> Option<HeaderValue> {
+    let name = path.file_name()?.to_str()?;
+    HeaderValue::from_str(&format!("inline; filename*=UTF-8''{}", percent_encode(name))).ok()
+}
+
+/// Decode a percent-encoded part of a URL, if it's UTF-8
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        let decoded = match b {
+            b'%' => rest
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &rest[2..];
+            }
+            None => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Percent-encode everything but letters, digits, `.`, `-` and `_`
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
         })
+        .collect()
 }
 
-/// Serve static files from a root directory
-fn serve_file(
-    req: &Request<Body>,
-    root_dir: &PathBuf,
-) -> impl Future<Item = Response<Body>, Error = Error> {
-    let uri = req.uri().clone();
-    let root_dir = root_dir.clone();
+/// Serve the static file that a request maps to
+async fn serve_path(
+    config: &Config,
+    req: &Request<Incoming>,
+    path: PathBuf,
+) -> Result<Response<Body>> {
+    let vfs = &*config.vfs;
+    let uri = req.uri();
+
+    if let Some(digests) = &config.digests {
+        if let Some(resp) = digests.serve_sidecar(vfs, &path).await? {
+            return Ok(resp);
+        }
+    }
 
     // First, try to do a redirect per `try_dir_redirect`. If that doesn't
     // happen, then find the path to the static file we want to serve - which
     // may be `index.html` for directories - and send a response containing that
     // file.
-    try_dir_redirect(req, &root_dir).and_then(move |maybe_redir_resp| {
-        if let Some(redir_resp) = maybe_redir_resp {
-            return Either::A(future::ok(redir_resp));
+    if let Some(redir_resp) = try_dir_redirect(vfs, uri, &path).await? {
+        return Ok(redir_resp);
+    }
+
+    let path = local_path_with_maybe_index(vfs, path).await;
+    // Errors here will also happen when opening the file, where they are
+    // reported
+    let metadata = vfs.metadata(&path).await;
+
+    // A missing page may have language variants, which are served as the
+    // page, with its content type
+    let mut language = None;
+    let requested = path.clone();
+    let (path, metadata) = match (&config.default_language, metadata) {
+        (Some(default), Err(e)) => match language::find_variant(vfs, req, &path, default).await {
+            Some((variant, tag)) => {
+                language = Some(tag);
+                let metadata = vfs.metadata(&variant).await;
+                (variant, metadata)
+            }
+            None => (path, Err(e)),
+        },
+        (_, metadata) => (path, metadata),
+    };
+    let metadata = metadata.ok();
+    let modified = metadata.as_ref().and_then(|m| m.modified);
+
+    // Players seeking in a film shouldn't have to wait for all of it to be
+    // read
+    if let Some(metadata) = metadata.filter(|m| !m.is_dir && vfs.is_local()) {
+        if let Some(resp) = media::respond(req, &path, metadata.len, modified).await? {
+            return Ok(resp);
         }
+    }
 
-        if let Some(path) = local_path_with_maybe_index(&uri, &root_dir) {
-            Either::B(
-                File::open(path.clone())
-                    .map_err(Error::from)
-                    .and_then(move |file| respond_with_file(file, path)),
-            )
-        } else {
-            Either::A(future::err(Error::UrlToPath))
+    let mut resp = match config.mmap_threshold {
+        Some(threshold) if vfs.is_local() => {
+            mmap::respond_with_file(vfs, path.clone(), threshold).await?
         }
-    })
+        _ => {
+            let file = vfs.open(&path).await?;
+            respond_with_file(file, &path).await?
+        }
+    };
+
+    if let Some(language) = language {
+        let headers = resp.headers_mut();
+        let mime_type = file_path_mime(&requested);
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(mime_type.as_ref()).expect("valid header"),
+        );
+        headers.insert(
+            header::CONTENT_LANGUAGE,
+            HeaderValue::from_str(&language).expect("valid header"),
+        );
+    }
+
+    // Resumed downloads check the file hasn't changed since, with `If-Range`
+    if let Some(modified) = modified {
+        let date = httpdate::fmt_http_date(modified);
+        resp.headers_mut().insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&date).expect("valid header"),
+        );
+    }
+    if let Some(digests) = &config.digests {
+        resp = digests.add_headers(&path, modified, resp).await?;
+    }
+    if !config.etags.is_off() {
+        resp = config.etags.add_header(&path, modified, resp).await?;
+    }
+    Ok(resp)
 }
 
 /// If we get a URL without trailing "/" that can be mapped to a directory, then
@@ -191,65 +1724,104 @@ fn serve_file(
 /// the case for URL `docs/`.
 ///
 /// This seems to match the behavior of other static web servers.
-fn try_dir_redirect(
-    req: &Request<Body>,
-    root_dir: &PathBuf,
-) -> impl Future<Item = Option<Response<Body>>, Error = Error> {
-    if !req.uri().path().ends_with("/") {
-        debug!("path does not end with /");
-        if let Some(path) = local_path_for_request(req.uri(), root_dir) {
-            if path.is_dir() {
-                let mut new_loc = req.uri().path().to_string();
-                new_loc.push_str("/");
-                if let Some(query) = req.uri().query() {
-                    new_loc.push_str("?");
-                    new_loc.push_str(query);
-                }
-                info!("redirecting {} to {}", req.uri(), new_loc);
-                future::result(
-                    Response::builder()
-                        .status(StatusCode::FOUND)
-                        .header(header::LOCATION, new_loc)
-                        .body(Body::empty())
-                        .map(Some)
-                        .map_err(Error::from),
-                )
-            } else {
-                future::ok(None)
+async fn try_dir_redirect(vfs: &dyn Vfs, uri: &Uri, path: &Path) -> Result<Option<Response<Body>>> {
+    if uri.path().ends_with('/') {
+        return Ok(None);
+    }
+
+    debug!("path does not end with /");
+    match vfs.metadata(path).await {
+        Ok(m) if m.is_dir => {
+            let mut new_loc = uri.path().to_string();
+            new_loc.push('/');
+            if let Some(query) = uri.query() {
+                new_loc.push('?');
+                new_loc.push_str(query);
             }
-        } else {
-            future::err(Error::UrlToPath)
+            info!("redirecting {} to {}", uri, new_loc);
+            Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, new_loc)
+                .body(Body::default())
+                .map(Some)
+                .map_err(Error::from)
         }
-    } else {
-        future::ok(None)
+        // Errors here will also happen when opening the file, where they
+        // are reported
+        _ => Ok(None),
     }
 }
 
 /// Read the file completely and construct a 200 response with that file as the
-/// body of the response. If the I/O here fails then an error future will be
-/// returned, and `serve` will convert it into the appropriate HTTP error
-/// response.
-fn respond_with_file(
-    file: tokio::fs::File,
-    path: PathBuf,
-) -> impl Future<Item = Response<Body>, Error = Error> {
-    read_file(file).and_then(move |buf| {
-        let mime_type = file_path_mime(&path);
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_LENGTH, buf.len() as u64)
-            .header(header::CONTENT_TYPE, mime_type.as_ref())
-            .body(Body::from(buf))
-            .map_err(Error::from)
-    })
+/// body of the response. If the I/O here fails then an error will be returned,
+/// and `serve` will convert it into the appropriate HTTP error response.
+async fn respond_with_file(file: VfsFile, path: &Path) -> Result<Response<Body>> {
+    let buf = read_file(file).await?;
+    file_response(buf, path)
 }
 
-/// Read a file and return a future of the buffer
-fn read_file(file: tokio::fs::File) -> impl Future<Item = Vec<u8>, Error = Error> {
-    let buf: Vec<u8> = Vec::new();
-    tokio::io::read_to_end(file, buf)
-        .map_err(Error::Io)
-        .and_then(|(_read_handle, buf)| future::ok(buf))
+/// Construct a 200 response with the file's contents as the body.
+fn file_response(buf: Vec<u8>, path: &Path) -> Result<Response<Body>> {
+    let mime_type = file_path_mime(path);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, buf.len() as u64)
+        .header(header::CONTENT_TYPE, mime_type.as_ref())
+        .body(Body::from(buf))
+        .map_err(Error::from)
+}
+
+/// Read a file into a buffer
+async fn read_file(mut file: impl AsyncRead + Unpin) -> Result<Vec<u8>> {
+    let mut buf: Vec<u8> = Vec::new();
+    file.read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Run blocking work, like decoding an image, on tokio's blocking thread pool
+/// without stalling other requests.
+async fn blocking<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Io(io::Error::other(e)))?
+}
+
+/// Parse a duration like "500ms", "2s", "1.5s" or "5m", or a number of
+/// seconds, of more than zero
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(at) => s.split_at(at),
+        None => (s, "s"),
+    };
+    let number: f64 = number.parse().ok()?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+/// Parse a size given in units of `unit` bytes, like kilobytes, as a number
+/// of bytes, failing with `invalid` if it isn't a number.
+fn parse_size<T: TryFrom<u64>>(
+    size: &str,
+    unit: u64,
+    invalid: fn(ParseIntError) -> Error,
+) -> Result<T> {
+    let units: u64 = size.parse().map_err(invalid)?;
+    units
+        .checked_mul(unit)
+        .and_then(|bytes| T::try_from(bytes).ok())
+        .ok_or_else(|| Error::SizeTooLarge(size.to_owned()))
 }
 
 /// Get a MIME type based on the file etension
@@ -257,31 +1829,44 @@ fn file_path_mime(file_path: &Path) -> mime::Mime {
     let mime_type = match file_path.extension().and_then(std::ffi::OsStr::to_str) {
         Some("html") => mime::TEXT_HTML,
         Some("css") => mime::TEXT_CSS,
-        Some("js") => mime::TEXT_JAVASCRIPT,
-        Some("jpg") => mime::IMAGE_JPEG,
+        Some("flac") => "audio/flac".parse::<mime::Mime>().unwrap(),
+        Some("js") | Some("mjs") => mime::TEXT_JAVASCRIPT,
+        Some("gif") => mime::IMAGE_GIF,
+        Some("gz") => "application/gzip".parse::<mime::Mime>().unwrap(),
+        Some("jpg") | Some("jpeg") => mime::IMAGE_JPEG,
+        Some("json") => mime::APPLICATION_JSON,
+        Some("m4a") => "audio/mp4".parse::<mime::Mime>().unwrap(),
         Some("md") => "text/markdown; charset=UTF-8"
             .parse::<mime::Mime>()
             .unwrap(),
+        Some("mkv") => "video/x-matroska".parse::<mime::Mime>().unwrap(),
+        Some("mov") => "video/quicktime".parse::<mime::Mime>().unwrap(),
+        Some("mp3") => "audio/mpeg".parse::<mime::Mime>().unwrap(),
+        Some("mp4") | Some("m4v") => "video/mp4".parse::<mime::Mime>().unwrap(),
+        Some("oga") | Some("ogg") | Some("opus") => "audio/ogg".parse::<mime::Mime>().unwrap(),
+        Some("ogv") => "video/ogg".parse::<mime::Mime>().unwrap(),
+        Some("pdf") => mime::APPLICATION_PDF,
         Some("png") => mime::IMAGE_PNG,
         Some("svg") => mime::IMAGE_SVG,
+        Some("tar") => "application/x-tar".parse::<mime::Mime>().unwrap(),
         Some("wasm") => "application/wasm".parse::<mime::Mime>().unwrap(),
+        Some("wav") => "audio/wav".parse::<mime::Mime>().unwrap(),
+        Some("webm") => "video/webm".parse::<mime::Mime>().unwrap(),
+        Some("zip") => "application/zip".parse::<mime::Mime>().unwrap(),
         _ => mime::TEXT_PLAIN,
     };
     mime_type
 }
 
-/// Find the local path for a request URI, converting directories to the
-/// `index.html` file.
-fn local_path_with_maybe_index(uri: &Uri, root_dir: &Path) -> Option<PathBuf> {
-    local_path_for_request(uri, root_dir).map(|mut p: PathBuf| {
-        if p.is_dir() {
-            p.push("index.html");
-            debug!("trying {} for directory URL", p.display());
-        } else {
-            trace!("trying path as from URL");
-        }
-        p
-    })
+/// Convert a local path for a directory to its `index.html` file.
+async fn local_path_with_maybe_index(vfs: &dyn Vfs, mut path: PathBuf) -> PathBuf {
+    if vfs.metadata(&path).await.map(|m| m.is_dir).unwrap_or(false) {
+        path.push("index.html");
+        debug!("trying {} for directory URL", path.display());
+    } else {
+        trace!("trying path as from URL");
+    }
+    path
 }
 
 /// Map the request's URI to a local path
@@ -291,7 +1876,7 @@ fn local_path_for_request(uri: &Uri, root_dir: &Path) -> Option<PathBuf> {
     debug!("raw URI to path: {}", request_path);
 
     // This is equivalent to checking for hyper::RequestUri::AbsoluteUri
-    if !request_path.starts_with("/") {
+    if !request_path.starts_with('/') {
         debug!("found non-absolute path");
         return None;
     }
@@ -300,13 +1885,31 @@ fn local_path_for_request(uri: &Uri, root_dir: &Path) -> Option<PathBuf> {
     let end = request_path.find('?').unwrap_or(request_path.len());
     let request_path = &request_path[0..end];
 
-    // Append the requested path to the root directory
+    // Append the requested path to the root directory, a decoded segment at
+    // a time. Segments that aren't a plain name, like `..`, or `%2F` for a
+    // separator, could reach outside the root, so aren't allowed.
     let mut path = root_dir.to_owned();
-    if request_path.starts_with('/') {
-        path.push(&request_path[1..]);
-    } else {
-        debug!("found non-absolute path");
-        return None;
+    for segment in request_path[1..].split('/') {
+        let segment = match percent_decode(segment) {
+            Some(segment) => segment,
+            None => {
+                debug!("found path that isn't UTF-8");
+                return None;
+            }
+        };
+        let mut components = Path::new(&segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) if cfg!(windows) && is_windows_special(&segment) => {
+                debug!("found path segment Windows treats specially: {}", segment);
+                return None;
+            }
+            (Some(Component::Normal(name)), None) => path.push(name),
+            (Some(Component::CurDir), None) | (None, _) => {}
+            _ => {
+                debug!("found path segment outside the root: {}", segment);
+                return None;
+            }
+        }
     }
 
     debug!("URL · path : {} · {}", uri, path.display());
@@ -314,40 +1917,66 @@ fn local_path_for_request(uri: &Uri, root_dir: &Path) -> Option<PathBuf> {
     Some(path)
 }
 
-/// Convert an error to an HTTP error response future, with correct response code.
-fn make_error_response(e: Error) -> impl Future<Item = Response<Body>, Error = Error> {
-    match e {
-        Error::Io(e) => Either::A(make_io_error_response(e)),
-        e => Either::B(make_internal_server_error_response(e)),
+/// Whether Windows would open something other than the file a name seems
+/// to be: a device, like `CON` or `nul.txt`, an alternate data stream, like
+/// `file.txt::$DATA`, or, as trailing dots and spaces are dropped, another
+/// file, like `page.md.` for `page.md`, which would be served raw.
+fn is_windows_special(name: &str) -> bool {
+    if name.contains(':') || name.ends_with(['.', ' ']) {
+        return true;
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    let stem = stem.to_ascii_uppercase();
+    // Serial and parallel ports are numbered with a digit or a superscript
+    let port = match stem.get(..3) {
+        Some("COM") | Some("LPT") => &stem[3..],
+        _ => "",
+    };
+    let mut number = port.chars();
+    let is_port = match (number.next(), number.next()) {
+        (Some(n), None) => n.is_ascii_digit() || "¹²³".contains(n),
+        _ => false,
+    };
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => is_port,
     }
 }
 
-/// Convert an error into a 500 internal server error, and log it.
-fn make_internal_server_error_response(
-    err: Error,
-) -> impl Future<Item = Response<Body>, Error = Error> {
-    log_error_chain(&err);
-    make_error_response_from_code(StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-/// Handle the one special io error (file not found) by returning a 404, otherwise
-/// return a 500.
-fn make_io_error_response(error: io::Error) -> impl Future<Item = Response<Body>, Error = Error> {
-    match error.kind() {
-        io::ErrorKind::NotFound => {
-            debug!("{}", error);
-            Either::A(make_error_response_from_code(StatusCode::NOT_FOUND))
-        }
-        _ => Either::B(make_internal_server_error_response(Error::Io(error))),
+/// Convert an error to an HTTP error response, with correct response code.
+fn make_error_response(e: Error, format: Format, lang: i18n::Lang) -> Result<Response<Body>> {
+    let status = e.status();
+    if status.is_server_error() {
+        log_error_chain(&e);
+    } else {
+        let chain: Vec<_> = std::iter::successors(Some(&e as &dyn StdError), |&e| e.source())
+            .map(|e| e.to_string())
+            .collect();
+        debug!("{}: {}", status, chain.join(": "));
     }
+    make_error_response_from_code(status, format, lang)
 }
 
-/// Make an error response given an HTTP status code.
+/// Make an error response given an HTTP status code, as a page in the
+/// language or as JSON.
 fn make_error_response_from_code(
     status: StatusCode,
-) -> impl Future<Item = Response<Body>, Error = Error> {
-    future::result({ render_error_html(status) })
-        .and_then(move |body| html_str_to_response(body, status))
+    format: Format,
+    lang: i18n::Lang,
+) -> Result<Response<Body>> {
+    match format {
+        Format::Html => {
+            let body = render_error_html(status, lang)?;
+            html_str_to_response(body, status)
+        }
+        Format::Json => {
+            let error = serde_json::json!({
+                "status": status.as_u16(),
+                "error": status.canonical_reason(),
+            });
+            json_to_response(&error, status)
+        }
+    }
 }
 
 /// Make an HTTP response from a HTML string.
@@ -360,8 +1989,16 @@ fn html_str_to_response(body: String, status: StatusCode) -> Result<Response<Bod
         .map_err(Error::from)
 }
 
-/// A handlebars HTML template
-static HTML_TEMPLATE: &str = include_str!("template.html");
+/// Make an HTTP response from JSON.
+fn json_to_response(value: &serde_json::Value, status: StatusCode) -> Result<Response<Body>> {
+    let body = value.to_string();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_LENGTH, body.len())
+        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .body(Body::from(body))
+        .map_err(Error::from)
+}
 
 /// The data for the handlebars HTML template. Handlebars will use serde to get
 /// the data out of the struct and mapped onto the template.
@@ -373,17 +2010,13 @@ struct HtmlCfg {
 
 /// Render an HTML page with handlebars, the template and the configuration data.
 fn render_html(cfg: HtmlCfg) -> Result<String> {
-    let reg = Handlebars::new();
-    let rendered = reg
-        .render_template(HTML_TEMPLATE, &cfg)
-        .map_err(Error::TemplateRender)?;
-    Ok(rendered)
+    template::render(template::PAGE, &cfg)
 }
 
 /// Render an HTML page from an HTTP status code
-fn render_error_html(status: StatusCode) -> Result<String> {
+fn render_error_html(status: StatusCode, lang: i18n::Lang) -> Result<String> {
     render_html(HtmlCfg {
-        title: format!("{}", status),
+        title: lang.status(status),
         body: String::new(),
     })
 }
@@ -419,23 +2052,208 @@ pub enum Error {
     Io(io::Error),
 
     // custom "semantic" error types
-    #[display(fmt = "failed to parse IP address")]
-    AddrParse(std::net::AddrParseError),
+    #[display(fmt = "failed to parse number of acceptors")]
+    AcceptorsParse(std::num::ParseIntError),
+
+    #[display(fmt = "failed to resolve address")]
+    AddrResolve(io::Error),
+
+    #[display(fmt = "invalid bench URL {}, expected an http:// URL or a path", _0)]
+    BenchTarget(String),
+
+    #[display(fmt = "failed to parse cache size")]
+    CacheSizeParse(std::num::ParseIntError),
+
+    #[display(fmt = "failed to find the cargo workspace: {}", _0)]
+    CargoMetadata(String),
+
+    #[display(fmt = "the configuration has {} problems", _0)]
+    CheckFailed(usize),
+
+    #[display(fmt = "failed to send request")]
+    Client(hyper::Error),
+
+    #[display(
+        fmt = "invalid compression level {}, expected 0 to 9, or e.g. gzip=9",
+        _0
+    )]
+    CompressLevelParse(String),
+
+    #[display(fmt = "failed to parse compression minimum size")]
+    CompressMinSizeParse(std::num::ParseIntError),
+
+    #[display(fmt = "failed to parse concurrency")]
+    ConcurrencyParse(std::num::ParseIntError),
+
+    #[display(fmt = "unknown config key {}", _0)]
+    ConfigKey(String),
+
+    #[display(fmt = "failed to parse config file")]
+    ConfigParse(toml::de::Error),
+
+    #[display(fmt = "invalid value for config key {}", _0)]
+    ConfigValue(String),
+
+    #[display(fmt = "failed to parse content type")]
+    ContentTypeParse(mime::FromStrError),
+
+    #[display(fmt = "no docs in {}, run cargo doc first", _0)]
+    DocsMissing(String),
+
+    #[display(fmt = "invalid duration {}, expected e.g. 2s, 500ms or 5m", _0)]
+    DurationParse(String),
+
+    #[display(fmt = "feed directory {} isn't in the root", _0)]
+    FeedDir(String),
+
+    #[display(fmt = "invalid GeoIP database {}, expected a MaxMind database", _0)]
+    GeoIpDatabase(String),
+
+    #[display(fmt = "failed to highlight source file")]
+    Highlight(syntect::Error),
+
+    #[display(fmt = "failed to encode image")]
+    ImageEncode(image::ImageError),
+
+    #[display(fmt = "failed to parse LISTEN_FDS")]
+    ListenFdsParse(std::num::ParseIntError),
+
+    #[display(
+        fmt = "invalid log format variable {}, expected e.g. $remote_addr or $status",
+        _0
+    )]
+    LogFormatParse(String),
 
     #[display(fmt = "markdown is not UTF-8")]
     MarkdownUtf8,
 
+    #[display(fmt = "failed to parse metadata TTL")]
+    MetadataTtlParse(std::num::ParseIntError),
+
+    #[display(fmt = "invalid mirror URL {}, expected an http:// URL", _0)]
+    MirrorTarget(String),
+
+    #[display(fmt = "failed to parse mmap threshold")]
+    MmapThresholdParse(std::num::ParseIntError),
+
+    #[display(fmt = "invalid mock fixture {}", _0)]
+    MockFixture(String),
+
+    #[display(fmt = "failed to parse port")]
+    PortParse(std::num::ParseIntError),
+
+    #[display(fmt = "failed to parse recorded request")]
+    RecordParse(serde_json::Error),
+
+    #[display(fmt = "invalid replay target {}, expected an http:// URL", _0)]
+    ReplayTarget(String),
+
+    #[display(fmt = "failed to parse request log length")]
+    RequestLogParse(std::num::ParseIntError),
+
+    #[display(fmt = "failed to parse number of requests")]
+    RequestsParse(std::num::ParseIntError),
+
+    #[display(
+        fmt = "invalid route {}, expected a path and settings, as in \"/docs ext=markdown no-cache\"",
+        _0
+    )]
+    RouteParse(String),
+
+    #[cfg(target_os = "linux")]
+    #[display(fmt = "failed to enter sandbox")]
+    Sandbox(landlock::RulesetError),
+
+    #[display(fmt = "sandboxing is not supported by this kernel")]
+    SandboxUnsupported,
+
+    #[display(fmt = "size {} is too large", _0)]
+    SizeTooLarge(String),
+
+    #[display(fmt = "failed to parse socket option")]
+    SocketOptionParse(std::num::ParseIntError),
+
     #[display(fmt = "failed to strip prefix in directory listing")]
     StripPrefixInDirList(std::path::StripPrefixError),
 
+    #[display(fmt = "failed to parse template")]
+    TemplateParse(Box<handlebars::TemplateError>),
+
     #[display(fmt = "failed to render template")]
-    TemplateRender(handlebars::TemplateRenderError),
+    TemplateRender(Box<handlebars::TemplateRenderError>),
+
+    #[display(fmt = "failed to parse number of threads")]
+    ThreadsParse(std::num::ParseIntError),
+
+    #[display(fmt = "invalid trusted proxy {}", _0)]
+    TrustedProxyParse(String),
+
+    #[display(fmt = "unknown archive type")]
+    UnknownArchive,
+
+    #[display(
+        fmt = "unknown extension {}, expected markdown, listing, gallery, playlist, highlight, json, table, resize, hexdump or archive",
+        _0
+    )]
+    UnknownExtension(String),
+
+    #[display(fmt = "unknown group {}", _0)]
+    UnknownGroup(String),
+
+    #[display(fmt = "unknown language {}, expected en, de, es, fr or pt", _0)]
+    UnknownLanguage(String),
+
+    #[display(fmt = "unknown user {}", _0)]
+    UnknownUser(String),
 
     #[display(fmt = "failed to convert URL to local file path")]
     UrlToPath,
 
+    #[display(fmt = "invalid user agent pattern")]
+    UserAgentRegex(regex::Error),
+
+    #[display(
+        fmt = "invalid webhook {}, expected an http:// URL, or events like started,not-found=URL",
+        _0
+    )]
+    WebhookParse(String),
+
+    #[cfg(windows)]
+    #[display(fmt = "Windows service error")]
+    WindowsService(windows_service::Error),
+
+    #[display(fmt = "formatting error while creating checksum")]
+    WriteInChecksum(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating config file")]
+    WriteInConfig(std::fmt::Error),
+
     #[display(fmt = "formatting error while creating directory listing")]
     WriteInDirList(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating feed")]
+    WriteInFeed(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating gallery")]
+    WriteInGallery(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating hex dump")]
+    WriteInHexdump(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating JSON view")]
+    WriteInJson(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating request log")]
+    WriteInRequestLog(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating source listing")]
+    WriteInSource(std::fmt::Error),
+
+    #[display(fmt = "formatting error while creating table view")]
+    WriteInTable(std::fmt::Error),
+
+    #[display(fmt = "failed to read zip archive")]
+    Zip(zip::result::ZipError),
 }
 
 impl StdError for Error {
@@ -445,12 +2263,92 @@ impl StdError for Error {
         match self {
             Http(e) => Some(e),
             Io(e) => Some(e),
-            AddrParse(e) => Some(e),
+            AcceptorsParse(e) => Some(e),
+            AddrResolve(e) => Some(e),
+            BenchTarget(_) => None,
+            CacheSizeParse(e) => Some(e),
+            CargoMetadata(_) => None,
+            CheckFailed(_) => None,
+            Client(e) => Some(e),
+            CompressLevelParse(_) => None,
+            CompressMinSizeParse(e) => Some(e),
+            ConcurrencyParse(e) => Some(e),
+            ConfigKey(_) => None,
+            ConfigParse(e) => Some(e),
+            ConfigValue(_) => None,
+            ContentTypeParse(e) => Some(e),
+            DocsMissing(_) => None,
+            DurationParse(_) => None,
+            FeedDir(_) => None,
+            GeoIpDatabase(_) => None,
+            Highlight(e) => Some(e),
+            ImageEncode(e) => Some(e),
+            ListenFdsParse(e) => Some(e),
+            LogFormatParse(_) => None,
             MarkdownUtf8 => None,
+            MetadataTtlParse(e) => Some(e),
+            MirrorTarget(_) => None,
+            MmapThresholdParse(e) => Some(e),
+            MockFixture(_) => None,
+            PortParse(e) => Some(e),
+            RecordParse(e) => Some(e),
+            ReplayTarget(_) => None,
+            RequestLogParse(e) => Some(e),
+            RequestsParse(e) => Some(e),
+            RouteParse(_) => None,
+            #[cfg(target_os = "linux")]
+            Sandbox(e) => Some(e),
+            SandboxUnsupported => None,
+            SizeTooLarge(_) => None,
+            SocketOptionParse(e) => Some(e),
             StripPrefixInDirList(e) => Some(e),
-            TemplateRender(e) => Some(e),
+            TemplateParse(e) => Some(&**e),
+            TemplateRender(e) => Some(&**e),
+            ThreadsParse(e) => Some(e),
+            TrustedProxyParse(_) => None,
+            UnknownArchive => None,
+            UnknownExtension(_) => None,
+            UnknownGroup(_) => None,
+            UnknownLanguage(_) => None,
+            UnknownUser(_) => None,
             UrlToPath => None,
+            UserAgentRegex(e) => Some(e),
+            WebhookParse(_) => None,
+            #[cfg(windows)]
+            WindowsService(e) => Some(e),
+            WriteInChecksum(e) => Some(e),
+            WriteInConfig(e) => Some(e),
             WriteInDirList(e) => Some(e),
+            WriteInFeed(e) => Some(e),
+            WriteInGallery(e) => Some(e),
+            WriteInHexdump(e) => Some(e),
+            WriteInJson(e) => Some(e),
+            WriteInRequestLog(e) => Some(e),
+            WriteInSource(e) => Some(e),
+            WriteInTable(e) => Some(e),
+            Zip(e) => Some(e),
+        }
+    }
+}
+
+impl Error {
+    /// The status of the response to a request that failed with this error.
+    /// Clients only see the status, in an error page or JSON, while the
+    /// error itself, and its sources, are logged for server errors.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Io(e) => match e.kind() {
+                // Like `/file.txt/x`, which is a path through a file
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
+                io::ErrorKind::InvalidFilename => StatusCode::NOT_FOUND,
+                io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                // Like a path with a NUL byte in it
+                io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            // Requests for paths that can't be in the root, like `/../x`
+            Error::UrlToPath => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
@@ -0,0 +1,69 @@
+//! Maintenance mode, in which every request is answered with a 503 and a
+//! maintenance page, so that the site's files can be swapped out without
+//! anyone seeing them half-copied. It's started with `--maintenance`, and on
+//! unix toggled with `SIGUSR1`.
+
+use crate::i18n::Lang;
+use crate::negotiate::Format;
+use crate::{Body, Config, Error, Result};
+use http::{header, HeaderValue, Response, StatusCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How long clients are told to wait before trying again, in seconds
+const RETRY_AFTER: u32 = 60;
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    info!("maintenance mode {}", if enabled { "on" } else { "off" });
+}
+
+/// Turn maintenance mode on or off each time the process gets `SIGUSR1`.
+#[cfg(unix)]
+pub async fn toggle_on_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr1 = match signal(SignalKind::user_defined1()) {
+        Ok(usr1) => usr1,
+        Err(e) => {
+            warn!("failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+    while usr1.recv().await.is_some() {
+        set_enabled(!is_enabled());
+    }
+}
+
+/// The response to every request while in maintenance mode: the configured
+/// maintenance page, which is read afresh each time, or else an error page.
+pub async fn respond(config: &Config, format: Format, lang: Lang) -> Result<Response<Body>> {
+    let status = StatusCode::SERVICE_UNAVAILABLE;
+    let page = match &config.maintenance_page {
+        Some(path) => match tokio::fs::read(path).await {
+            Ok(page) => Some(page),
+            Err(e) => {
+                warn!("failed to read maintenance page {}: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut resp = match page {
+        Some(page) => Response::builder()
+            .status(status)
+            .header(header::CONTENT_LENGTH, page.len())
+            .header(header::CONTENT_TYPE, mime::TEXT_HTML.as_ref())
+            .body(Body::from(page))
+            .map_err(Error::from)?,
+        None => crate::make_error_response_from_code(status, format, lang)?,
+    };
+    resp.headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER));
+    Ok(resp)
+}
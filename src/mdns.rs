@@ -0,0 +1,82 @@
+//! Announcing the server on the local network with mDNS, as an `_http._tcp`
+//! service, so that it shows up in the network browsers of other machines,
+//! with `--mdns`.
+
+use crate::listen::ListenAddr;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::time::Duration;
+
+const SERVICE_TYPE: &str = "_http._tcp.local.";
+
+/// A service being announced, until withdrawn
+pub struct Announcement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+/// Announce the server under the instance name `name`, on the first TCP
+/// address listened on that isn't loopback. Failing to announce isn't fatal
+/// to the server, so is only logged.
+pub fn announce(name: &str, addrs: &[ListenAddr]) -> Option<Announcement> {
+    let addr = addrs.iter().find_map(|addr| match addr {
+        ListenAddr::Tcp(addr) if !addr.ip().is_loopback() => Some(*addr),
+        _ => None,
+    });
+    let addr = match addr {
+        Some(addr) => addr,
+        None => {
+            warn!("not announcing with mDNS without an address on the network");
+            return None;
+        }
+    };
+
+    // The host name only needs to be unique on the network, so is made from
+    // the instance name
+    let host: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let host = format!("{}.local.", host);
+    let properties = [("path", "/")];
+    let service = if addr.ip().is_unspecified() {
+        ServiceInfo::new(SERVICE_TYPE, name, &host, (), addr.port(), &properties[..])
+            .map(ServiceInfo::enable_addr_auto)
+    } else {
+        ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &host,
+            addr.ip(),
+            addr.port(),
+            &properties[..],
+        )
+    };
+
+    let result = service.and_then(|service| {
+        let fullname = service.get_fullname().to_string();
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(service)?;
+        Ok(Announcement { daemon, fullname })
+    });
+    match result {
+        Ok(announcement) => {
+            info!("announcing with mDNS as {}", announcement.fullname);
+            Some(announcement)
+        }
+        Err(e) => {
+            warn!("failed to announce with mDNS: {}", e);
+            None
+        }
+    }
+}
+
+impl Announcement {
+    /// Tell the network that the service is going away, so that it doesn't
+    /// linger in network browsers.
+    pub fn withdraw(self) {
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            let _ = status.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
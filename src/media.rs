@@ -0,0 +1,71 @@
+//! A fast path for seeking in large audio and video files. Responses are
+//! otherwise built from the whole file, which is slow for a player that asks
+//! for a new range each time it seeks in a film of a few gigabytes. For a
+//! single range of a media file on the local disk, only that range is read.
+//! An open-ended range gets the rest of the file, however long, as a client
+//! resuming a download takes a shorter response to mean the file ends there.
+
+use crate::{file_path_mime, range, Body, Result};
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Whether the file is audio or video, by its extension
+pub fn is_media(path: &Path) -> bool {
+    let mime = file_path_mime(path);
+    mime.type_() == mime::VIDEO || mime.type_() == mime::AUDIO
+}
+
+/// Respond to a request for a range of a media file by reading just that
+/// range, if that's what it is. Anything else, including a range that can't
+/// be satisfied, or an `If-Range` that doesn't match, is left to the usual
+/// path, which reads the whole file.
+pub async fn respond<B>(
+    req: &Request<B>,
+    path: &Path,
+    len: u64,
+    modified: Option<SystemTime>,
+) -> Result<Option<Response<Body>>> {
+    if req.method() != Method::GET || !is_media(path) {
+        return Ok(None);
+    }
+    let requested = match req.headers().get(header::RANGE) {
+        Some(requested) => requested,
+        None => return Ok(None),
+    };
+    let range = match range::single(requested, len) {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+
+    let mut headers = HeaderMap::new();
+    if let Some(modified) = modified {
+        let date = httpdate::fmt_http_date(modified);
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&date).expect("valid header"),
+        );
+    }
+    if let Some(if_range) = req.headers().get(header::IF_RANGE) {
+        if !range::unchanged(if_range, &headers) {
+            return Ok(None);
+        }
+    }
+
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let mut buf = vec![0; (range.end - range.start) as usize];
+    file.read_exact(&mut buf).await?;
+
+    let mut resp = Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_TYPE, file_path_mime(path).as_ref())
+        .header(header::CONTENT_LENGTH, buf.len())
+        .header(header::CONTENT_RANGE, range::content_range(&range, len))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .body(Body::from(buf))?;
+    resp.headers_mut().extend(headers);
+    Ok(Some(resp))
+}
@@ -0,0 +1,98 @@
+//! Mirroring requests to another server with `--mirror`, to try out a new
+//! backend with real traffic. Copies of requests are sent in the background,
+//! and their responses are thrown away, so the mirror can't slow down or
+//! change what clients get. Copies are dropped while too many are waiting on
+//! a slow mirror. They're sent without a body, and without the headers about
+//! the client's connection or the body's length.
+
+use crate::client::Target;
+use crate::{Body, Error, Result};
+use bytes::Bytes;
+use http::{header, HeaderMap, Request};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// How many mirrored requests may be waiting at once
+const MAX_IN_FLIGHT: usize = 64;
+
+/// How long a mirrored request may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers that are about a connection, rather than the request, along with
+/// any the `Connection` header names, and those about the body, which the
+/// mirror doesn't get
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "content-length",
+    "expect",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Clone)]
+pub struct Mirror {
+    target: Target,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Mirror {
+    pub fn new(url: &str) -> Result<Mirror> {
+        let target = Target::parse(url).ok_or_else(|| Error::MirrorTarget(url.to_owned()))?;
+        Ok(Mirror {
+            target,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        })
+    }
+
+    /// Send a copy of the request, without its body, to the mirror.
+    pub fn mirror<B>(&self, req: &Request<B>) {
+        let permit = match self.in_flight.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                debug!("mirror is behind, not mirroring {}", req.uri());
+                return;
+            }
+        };
+        let mut copy = Request::new(Body::new(Bytes::new()));
+        *copy.method_mut() = req.method().clone();
+        *copy.uri_mut() = req.uri().clone();
+        *copy.headers_mut() = req.headers().clone();
+        strip_hop_by_hop(copy.headers_mut());
+
+        let target = self.target.clone();
+        tokio::spawn(async move {
+            let uri = copy.uri().clone();
+            match tokio::time::timeout(TIMEOUT, target.send(copy)).await {
+                Ok(Ok(status)) => trace!("mirrored {}: {}", uri, status),
+                Ok(Err(e)) => debug!("failed to mirror {}: {}", uri, e),
+                Err(_) => debug!("timed out mirroring {}", uri),
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Remove the headers that mustn't be passed on from one connection to
+/// another.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+    for name in &named {
+        headers.remove(name.as_str());
+    }
+}
@@ -0,0 +1,65 @@
+//! Serving large files by mapping them into memory, so that their contents
+//! come straight from the kernel's page cache instead of first being read
+//! into a buffer the size of the file.
+//!
+//! This is as close to zero-copy as the server gets. `sendfile` and `splice`
+//! would move the file to the socket without it passing through the server
+//! at all, but hyper writes response bodies to the connection itself and
+//! never hands over the socket for a normal response, so they can't be used.
+//!
+//! A mapped file that's truncated while it's being sent can't be read past
+//! its new end, and the kernel kills the server with `SIGBUS` when that's
+//! tried. Files are mostly truncated while they're being rewritten, as by a
+//! build, so files modified in the last minute are read as usual instead, but
+//! that's only less likely to crash, which is why mapping has to be asked for.
+
+use crate::vfs::Vfs;
+use crate::{Body, Error, Result};
+use bytes::Bytes;
+use http::{header, Response, StatusCode};
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Files modified more recently than this may still be being written, and
+/// aren't mapped
+const SETTLE_TIME: Duration = Duration::from_secs(60);
+
+/// Respond with the file mapped into memory if it is at least `threshold`
+/// bytes long and hasn't been modified lately, otherwise read it as usual.
+/// The filesystem must be the local disk.
+pub async fn respond_with_file(
+    vfs: &dyn Vfs,
+    path: PathBuf,
+    threshold: u64,
+) -> Result<Response<Body>> {
+    let metadata = vfs.metadata(&path).await?;
+    let settled = metadata
+        .modified
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= SETTLE_TIME);
+    if !metadata.is_dir && metadata.len >= threshold && settled {
+        crate::blocking(move || respond_with_mmap(&path)).await
+    } else {
+        let file = vfs.open(&path).await?;
+        crate::respond_with_file(file, &path).await
+    }
+}
+
+fn respond_with_mmap(path: &Path) -> Result<Response<Body>> {
+    debug!("mapping {} into memory", path.display());
+    let file = File::open(path)?;
+    // Safety: the map is only read from. If the file is truncated while being
+    // served the server crashes, which those asking for mapping accept.
+    let mmap = unsafe { Mmap::map(&file)? };
+    let len = mmap.len();
+
+    // The map itself becomes the body, without being copied
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, len as u64)
+        .header(header::CONTENT_TYPE, crate::file_path_mime(path).as_ref())
+        .body(Body::from(Bytes::from_owner(mmap)))
+        .map_err(Error::from)
+}
@@ -0,0 +1,257 @@
+//! A mock API, with `--mock`, for stubbing out a backend while working on its
+//! frontend. Each `.json`, `.yaml` or `.yml` file in the mock directory holds
+//! a route, or a list of them, like
+//!
+//! ```yaml
+//! method: GET
+//! path: /api/users/:id
+//! status: 200
+//! headers:
+//!   X-Request-Path: "{{path}}"
+//! body:
+//!   id: "{{params.id}}"
+//!   name: User {{params.id}}
+//! delay_ms: 250
+//! ```
+//!
+//! Requests are matched against the routes in order of file name, then of
+//! their place in the file, and those matching none are served from the root
+//! directory as usual. Routes without a method match any. `:name` in a path
+//! matches one segment, and a final `*` matches the rest. Strings in the
+//! headers and body are handlebars templates, given the request's `method`,
+//! `path`, `params`, `query` and `headers`. A string body is sent as text,
+//! and any other as JSON.
+//!
+//! The fixtures are read afresh for each request, so they can be edited
+//! without restarting the server.
+
+use crate::{Body, Error, Result};
+use handlebars::Handlebars;
+use http::{header, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Route {
+    method: Option<String>,
+    path: String,
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Nothing is sent if there's no body
+    #[serde(default)]
+    body: Value,
+    #[serde(default)]
+    delay_ms: u64,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl Route {
+    /// The parameters from the request's path, if the route matches it
+    fn matches(&self, method: &Method, path: &str) -> Option<Map<String, Value>> {
+        if let Some(expected) = &self.method {
+            let head_for_get = method == Method::HEAD && expected.eq_ignore_ascii_case("GET");
+            if !expected.eq_ignore_ascii_case(method.as_str()) && !head_for_get {
+                return None;
+            }
+        }
+        let mut params = Map::new();
+        let mut segments = path.trim_start_matches('/').split('/');
+        for pattern in self.path.trim_start_matches('/').split('/') {
+            if pattern == "*" {
+                let rest: Vec<&str> = segments.by_ref().collect();
+                params.insert("*".to_owned(), decode(&rest.join("/")).into());
+                return Some(params);
+            }
+            let segment = segments.next()?;
+            if let Some(name) = pattern.strip_prefix(':') {
+                params.insert(name.to_owned(), decode(segment).into());
+            } else if pattern != segment {
+                return None;
+            }
+        }
+        match segments.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+/// Check that the fixtures in the directory are valid, so that mistakes are
+/// found on startup, returning the number of routes.
+pub fn check(dir: &Path) -> Result<usize> {
+    load(dir).map(|routes| routes.len())
+}
+
+/// Respond to the request from the first route that matches it, if any does.
+pub async fn respond<B>(dir: &Path, req: &Request<B>) -> Result<Option<Response<Body>>> {
+    let dir = dir.to_owned();
+    let routes = crate::blocking(move || load(&dir)).await?;
+    let path = req.uri().path();
+    let found = routes.iter().find_map(|route| {
+        route
+            .matches(req.method(), path)
+            .map(|params| (route, params))
+    });
+    let (route, params) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    debug!("mock route {} matched {}", route.path, path);
+
+    let data = serde_json::json!({
+        "method": req.method().as_str(),
+        "path": path,
+        "params": params,
+        "query": query(req),
+        "headers": headers(req),
+    });
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    let render = |template: &str| {
+        handlebars
+            .render_template(template, &data)
+            .map_err(|e| Error::TemplateRender(Box::new(e)))
+    };
+
+    let mut builder = Response::builder().status(route.status);
+    let body = match &route.body {
+        Value::Null => String::new(),
+        Value::String(template) => {
+            builder = builder.header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.as_ref());
+            render(template)?
+        }
+        value => {
+            builder = builder.header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+            render_json(value, &render)?.to_string()
+        }
+    };
+    let mut resp = builder
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body))
+        .map_err(Error::from)?;
+    for (name, value) in &route.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(http::Error::from)?;
+        let value = HeaderValue::from_str(&render(value)?).map_err(http::Error::from)?;
+        resp.headers_mut().insert(name, value);
+    }
+
+    if route.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(route.delay_ms)).await;
+    }
+    Ok(Some(resp))
+}
+
+/// Read the routes from each fixture in the directory, in order of name.
+fn load(dir: &Path) -> Result<Vec<Route>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    paths.sort();
+
+    let mut routes = Vec::new();
+    for path in paths {
+        let invalid = |e: &dyn Display| Error::MockFixture(format!("{}: {}", path.display(), e));
+        let value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => {
+                serde_json::from_str(&std::fs::read_to_string(&path)?).map_err(|e| invalid(&e))?
+            }
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| invalid(&e))?
+            }
+            _ => continue,
+        };
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let route: Route = serde_json::from_value(value).map_err(|e| invalid(&e))?;
+            if let Some(method) = &route.method {
+                Method::from_bytes(method.as_bytes()).map_err(|e| invalid(&e))?;
+            }
+            StatusCode::from_u16(route.status).map_err(|e| invalid(&e))?;
+            for name in route.headers.keys() {
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+            }
+            routes.push(route);
+        }
+    }
+    Ok(routes)
+}
+
+/// Render each string in a JSON value as a template.
+fn render_json(value: &Value, render: &dyn Fn(&str) -> Result<String>) -> Result<Value> {
+    Ok(match value {
+        Value::String(template) => Value::String(render(template)?),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render_json(value, render))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| Ok((key.clone(), render_json(value, render)?)))
+                .collect::<Result<_>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+/// The request's query parameters, for templates
+fn query<B>(req: &Request<B>) -> Map<String, Value> {
+    req.uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let name = decode(kv.next().unwrap_or(""));
+            let value = decode(kv.next().unwrap_or(""));
+            (name, value.into())
+        })
+        .collect()
+}
+
+/// The request's headers, by lowercase name, for templates
+fn headers<B>(req: &Request<B>) -> Map<String, Value> {
+    req.headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+        .collect()
+}
+
+/// Decode a percent-encoded part of a URL, with `+` as a space.
+fn decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(b),
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
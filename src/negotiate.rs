@@ -0,0 +1,55 @@
+//! Choosing between HTML and JSON for the responses the server makes itself,
+//! like directory listings and error pages, by the `Accept` header. Browsers
+//! get pages, and scripts that ask for JSON get JSON from the same URLs.
+
+use http::{header, HeaderValue, Request};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+/// The format a request prefers, which the response then varies by.
+pub fn format<B>(req: &Request<B>) -> Format {
+    from_accept(crate::vary::header(req, header::ACCEPT))
+}
+
+/// The format an `Accept` header prefers. JSON is only chosen when it's
+/// preferred to HTML, so a request without the header gets HTML.
+pub fn from_accept(accept: Option<&HeaderValue>) -> Format {
+    let accept = match accept.and_then(|accept| accept.to_str().ok()) {
+        Some(accept) => accept,
+        None => return Format::Html,
+    };
+    if quality(accept, "application", "json") > quality(accept, "text", "html") {
+        Format::Json
+    } else {
+        Format::Html
+    }
+}
+
+/// The quality an `Accept` header gives a media type, from its most specific
+/// matching range, with 0 for none.
+fn quality(accept: &str, type_: &str, subtype: &str) -> f32 {
+    let mut best = (0, 0.0);
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_range = params.next().unwrap_or("").trim();
+        let (t, s) = media_range.split_once('/').unwrap_or((media_range, ""));
+        let specificity = match (t, s) {
+            _ if t.eq_ignore_ascii_case(type_) && s.eq_ignore_ascii_case(subtype) => 3,
+            _ if t.eq_ignore_ascii_case(type_) && s == "*" => 2,
+            ("*", "*") => 1,
+            _ => continue,
+        };
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, q);
+        }
+    }
+    best.1
+}
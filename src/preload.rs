@@ -0,0 +1,161 @@
+//! Warming the caches before serving, with `--preload`, so that the first
+//! requests for a site's files, as in a demo or a benchmark, are as quick as
+//! the ones after. Each file under the root dir matching one of the globs is
+//! read as serving it would, filling the content cache, the digests that
+//! strong entity tags are made from, and the compression cache, for each
+//! algorithm. Without those caches, reading the files still fills the OS's.
+//!
+//! Globs are matched against paths relative to the root dir, with `/`
+//! between names. `*` matches any part of a name, `?` any one character and
+//! `**` any number of directories, so `*.html` matches the pages in the root
+//! dir and `**/*.html` every page.
+
+use crate::vfs::Metadata;
+use crate::{Config, Result};
+use http::{header, HeaderValue};
+use std::path::Path;
+use std::time::Instant;
+
+/// Read the files matching the globs, logging how many there were.
+pub async fn preload(config: &Config) {
+    let started = Instant::now();
+    let (mut files, mut bytes) = (0, 0);
+    let mut dirs = vec![config.root_dir.clone()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match config.vfs.read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("failed to preload {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for path in entries {
+            let metadata = match config.vfs.metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!("failed to preload {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if metadata.is_dir {
+                dirs.push(path);
+                continue;
+            }
+            let name = relative_name(&config.root_dir, &path);
+            if !config.preload.iter().any(|glob| matches(glob, &name)) {
+                continue;
+            }
+            match load(config, &path, &metadata, &name).await {
+                Ok(()) => {
+                    files += 1;
+                    bytes += metadata.len;
+                }
+                Err(e) => warn!("failed to preload {}: {}", path.display(), e),
+            }
+        }
+    }
+    info!(
+        "preloaded {} files, {}, in {:.2} s",
+        files,
+        crate::stats::format_bytes(bytes as f64),
+        started.elapsed().as_secs_f64()
+    );
+}
+
+/// Read a file, and make what's made from it for serving it, with the
+/// caches in front of it
+async fn load(config: &Config, path: &Path, metadata: &Metadata, name: &str) -> Result<()> {
+    let modified = metadata.modified;
+    let file = config.vfs.open(path).await?;
+    let mut resp = crate::respond_with_file(file, path).await?;
+    // Only files with a modification time have their compressed forms cached
+    if let Some(modified) = modified {
+        let date = httpdate::fmt_http_date(modified);
+        resp.headers_mut().insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&date).expect("valid header"),
+        );
+    }
+    if let Some(digests) = &config.digests {
+        resp = digests.add_headers(path, modified, resp).await?;
+    }
+    if !config.etags.is_off() {
+        resp = config.etags.add_header(path, modified, resp).await?;
+    }
+    if let Some(compression) = &config.compression {
+        for url in urls(name) {
+            compression.preload(&url, &resp).await;
+        }
+    }
+    Ok(())
+}
+
+/// A path relative to the root dir, with `/` between names
+fn relative_name(root: &Path, path: &Path) -> String {
+    let names: Vec<_> = path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .iter()
+        .map(|name| name.to_string_lossy())
+        .collect();
+    names.join("/")
+}
+
+/// The URL paths a file is served at: its own, and its directory's if it's
+/// an index
+fn urls(name: &str) -> Vec<String> {
+    let encoded: Vec<_> = name.split('/').map(crate::percent_encode).collect();
+    let url = format!("/{}", encoded.join("/"));
+    match url.strip_suffix("index.html") {
+        Some(dir) if dir.ends_with('/') => vec![dir.to_owned(), url.clone()],
+        _ => vec![url],
+    }
+}
+
+/// Whether a path relative to the root dir matches a glob
+fn matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<_> = glob.trim_start_matches('/').split('/').collect();
+    let name: Vec<_> = name.split('/').collect();
+    matches_names(&glob, &name)
+}
+
+fn matches_names(glob: &[&str], names: &[&str]) -> bool {
+    match glob.split_first() {
+        Some((&"**", rest)) => (0..=names.len()).any(|skip| matches_names(rest, &names[skip..])),
+        Some((pattern, rest)) => match names.split_first() {
+            Some((name, names)) => matches_name(pattern, name) && matches_names(rest, names),
+            None => false,
+        },
+        None => names.is_empty(),
+    }
+}
+
+/// Whether a name matches a pattern with `*` and `?` in it
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to go back to if what follows the last `*` doesn't match
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
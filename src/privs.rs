@@ -0,0 +1,113 @@
+//! Dropping root privileges once the listening sockets are bound, with
+//! `--user` and `--group`, so that the server can listen on port 80 but
+//! serve every request as an unprivileged user.
+
+use crate::{Error, Result};
+use libc::{c_char, c_int, gid_t, uid_t};
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+/// The user and group to switch to, looked up before switching, while the
+/// user database can still be read.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    uid: Option<uid_t>,
+    gid: Option<gid_t>,
+}
+
+/// Look up the user and group, which may be names or numeric IDs. The group
+/// is the user's primary group unless given.
+pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Credentials> {
+    let (uid, primary_gid) = match user {
+        Some(user) => {
+            let (uid, gid) = lookup_user(user)?;
+            (Some(uid), gid)
+        }
+        None => (None, None),
+    };
+    let gid = match group {
+        Some(group) => Some(lookup_group(group)?),
+        None => primary_gid,
+    };
+    // A numeric user without an entry in the user database has no primary
+    // group, and keeping root's group would defeat the point
+    if let (Some(user), None) = (user, gid) {
+        return Err(Error::UnknownUser(user.to_string()));
+    }
+    Ok(Credentials { uid, gid })
+}
+
+/// Switch to the user and group, giving up any supplementary groups, for
+/// every thread of the process.
+pub fn drop_to(credentials: &Credentials) -> Result<()> {
+    if let Some(gid) = credentials.gid {
+        check(unsafe { libc::setgroups(1, &gid) })?;
+        check(unsafe { libc::setgid(gid) })?;
+    }
+    if let Some(uid) = credentials.uid {
+        check(unsafe { libc::setuid(uid) })?;
+    }
+    info!(
+        "dropped privileges to uid {}, gid {}",
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    Ok(())
+}
+
+fn check(ret: c_int) -> io::Result<()> {
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn lookup_user(user: &str) -> Result<(uid_t, Option<gid_t>)> {
+    let unknown = || Error::UnknownUser(user.to_string());
+    let name = CString::new(user).map_err(|_| unknown())?;
+    let id = user.parse::<uid_t>().ok();
+    let entry = lookup(|pwd, buf, result| unsafe {
+        match id {
+            Some(uid) => libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result),
+            None => libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result),
+        }
+    })?;
+    match (entry, id) {
+        (Some(pwd), _) => Ok((pwd.pw_uid, Some(pwd.pw_gid))),
+        (None, Some(uid)) => Ok((uid, None)),
+        (None, None) => Err(unknown()),
+    }
+}
+
+fn lookup_group(group: &str) -> Result<gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let unknown = || Error::UnknownGroup(group.to_string());
+    let name = CString::new(group).map_err(|_| unknown())?;
+    let entry = lookup(|grp, buf, result| unsafe {
+        libc::getgrnam_r(name.as_ptr(), grp, buf.as_mut_ptr(), buf.len(), result)
+    })?;
+    entry.map(|grp: libc::group| grp.gr_gid).ok_or_else(unknown)
+}
+
+/// Call one of the reentrant user database functions, like `getpwnam_r`,
+/// growing the buffer for the entry's strings until it fits.
+fn lookup<T>(f: impl Fn(*mut T, &mut [c_char], *mut *mut T) -> c_int) -> io::Result<Option<T>> {
+    let mut len = 1024;
+    loop {
+        let mut entry = MaybeUninit::<T>::uninit();
+        let mut buf = vec![0; len];
+        let mut result = ptr::null_mut();
+        match f(entry.as_mut_ptr(), &mut buf, &mut result) {
+            libc::ERANGE if len < 1 << 20 => len *= 2,
+            0 if result.is_null() => return Ok(None),
+            // Only the IDs are used, so the entry's strings in `buf` can go
+            0 => return Ok(Some(unsafe { entry.assume_init() })),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
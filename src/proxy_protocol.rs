@@ -0,0 +1,124 @@
+//! The PROXY protocol, which load balancers like haproxy use to pass on the
+//! address of the client whose connection they're forwarding, enabled with
+//! `--proxy-protocol`. The proxy sends a header before any of the client's
+//! data, in either the text format of version 1 or the binary format of
+//! version 2.
+//!
+//! See <https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest version 1 header, including the line ending
+const V1_MAX_LEN: usize = 107;
+
+/// How long to wait for the header, which the proxy sends straight away
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read the PROXY protocol header from the start of a connection, returning
+/// the client's address. There is none if the proxy sent the connection on
+/// its own behalf, like for a health check, or doesn't know the address.
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    tokio::time::timeout(TIMEOUT, read(stream))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+async fn read<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // The shortest version 1 header is longer than the version 2 signature
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+    if &start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<S>(stream: &mut S, start: &[u8]) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // The header is read a byte at a time, so that none of the request after
+    // it is taken
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY protocol header is not text"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "TCP4", src, _dst, sport, _dport]
+        | ["PROXY", "TCP6", src, _dst, sport, _dport] => {
+            let ip: IpAddr = src
+                .parse()
+                .map_err(|_| invalid("bad PROXY source address"))?;
+            let port = sport
+                .parse()
+                .map_err(|_| invalid("bad PROXY source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        _ => Err(invalid("bad PROXY protocol header")),
+    }
+}
+
+async fn read_v2<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [version_command, family, len_hi, len_lo] = header;
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let mut addresses = vec![0; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut addresses).await?;
+
+    // The LOCAL command is for the proxy's own connections
+    if version_command & 0xf == 0 {
+        return Ok(None);
+    }
+    let a = &addresses[..];
+    let addr = match family >> 4 {
+        1 if a.len() >= 12 => {
+            let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([a[8], a[9]]))
+        }
+        2 if a.len() >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&a[..16]);
+            SocketAddr::new(
+                Ipv6Addr::from(ip).into(),
+                u16::from_be_bytes([a[32], a[33]]),
+            )
+        }
+        // Unix sockets and unspecified families have no IP address. Extra
+        // TLVs after the addresses are ignored.
+        1 | 2 => return Err(invalid("PROXY protocol addresses too short")),
+        _ => return Ok(None),
+    };
+    Ok(Some(addr))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,23 @@
+//! Printing a QR code of the server's URL with `--qr`, so that phones and
+//! tablets on the same network can open the site without typing it.
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+/// Print the URL and its QR code to the terminal.
+pub fn print(url: &str) {
+    let code = match QrCode::new(url) {
+        Ok(code) => code,
+        Err(e) => {
+            warn!("failed to make QR code: {}", e);
+            return;
+        }
+    };
+    // Drawn light on dark, which scans on the usual dark terminal
+    let image = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{}\n{}", url, image);
+}
@@ -0,0 +1,201 @@
+//! Serving parts of a response for `Range` requests, which resume downloads
+//! and let media players and PDF viewers seek. Several ranges at once are
+//! sent as a `multipart/byteranges` response.
+
+use crate::{Body, Result};
+use bytes::{Bytes, BytesMut};
+use http::{header, HeaderMap, HeaderValue, Response, StatusCode};
+use http_body_util::BodyExt;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+
+/// More ranges than this are answered with the whole response, so that a
+/// request for many tiny ranges can't make a response much larger than the
+/// file.
+const MAX_RANGES: usize = 64;
+
+/// Answer a request for a range of a successful response with just that
+/// range. Other responses, and requests without a valid range, are answered
+/// with the whole response, which says that ranges can be requested. So is a
+/// request whose `If-Range` doesn't match the response, since the part it
+/// has of an earlier version won't fit with a part of this one.
+pub async fn respond(
+    range: Option<HeaderValue>,
+    if_range: Option<HeaderValue>,
+    resp: Response<Body>,
+) -> Result<Response<Body>> {
+    if resp.status() != StatusCode::OK {
+        return Ok(resp);
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let range = match if_range {
+        Some(if_range) if !unchanged(&if_range, &parts.headers) => None,
+        _ => range,
+    };
+    let specs = match range.as_ref().and_then(parse) {
+        Some(specs) => specs,
+        None => return Ok(Response::from_parts(parts, body)),
+    };
+    let body = body.collect().await.expect("infallible").to_bytes();
+    let len = body.len() as u64;
+    // Unlike `Repr-Digest`, this is of the body sent, which is now a part
+    parts.headers.remove("content-md5");
+    let ranges: Vec<Range<u64>> = specs.iter().filter_map(|spec| spec.resolve(len)).collect();
+
+    let body = match &ranges[..] {
+        [] => {
+            parts.status = StatusCode::RANGE_NOT_SATISFIABLE;
+            parts.headers.insert(
+                header::CONTENT_RANGE,
+                header_value(format!("bytes */{}", len)),
+            );
+            Bytes::new()
+        }
+        [range] => {
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            parts
+                .headers
+                .insert(header::CONTENT_RANGE, header_value(describe(range, len)));
+            body.slice(range.start as usize..range.end as usize)
+        }
+        ranges => {
+            parts.status = StatusCode::PARTIAL_CONTENT;
+            multipart(&mut parts.headers, &body, ranges)
+        }
+    };
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// The range of a file of `len` bytes that a `Range` header asks for, if it
+/// asks for exactly one that can be satisfied, so that the file can be read
+/// from without reading all of it.
+pub fn single(range: &HeaderValue, len: u64) -> Option<Range<u64>> {
+    match &parse(range)?[..] {
+        [spec] => spec.resolve(len),
+        _ => None,
+    }
+}
+
+/// The `Content-Range` header for a part of a file of `len` bytes
+pub fn content_range(range: &Range<u64>, len: u64) -> HeaderValue {
+    header_value(describe(range, len))
+}
+
+/// Whether an `If-Range` header's validator, an entity tag or a date,
+/// matches the response. Only strong validators match: an exact entity tag,
+/// or the response's modification time.
+pub fn unchanged(if_range: &HeaderValue, headers: &HeaderMap) -> bool {
+    let if_range = match if_range.to_str() {
+        Ok(if_range) => if_range,
+        Err(_) => return false,
+    };
+    if if_range.starts_with('"') {
+        return headers.get(header::ETAG).map(HeaderValue::as_bytes) == Some(if_range.as_bytes());
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    let last_modified = headers
+        .get(header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    match (httpdate::parse_http_date(if_range), last_modified) {
+        (Ok(date), Some(last_modified)) => date == last_modified,
+        _ => false,
+    }
+}
+
+/// One range of a `Range` header, before the length is known
+enum Spec {
+    /// From the first byte to the last, inclusive, or to the end
+    From(u64, Option<u64>),
+    /// The last bytes
+    Suffix(u64),
+}
+
+impl Spec {
+    /// The bytes of a response of `len` bytes that the range covers, if any
+    fn resolve(&self, len: u64) -> Option<Range<u64>> {
+        match *self {
+            Spec::From(first, _) if first >= len => None,
+            Spec::From(first, last) => {
+                let end = last.map_or(len, |last| last.saturating_add(1).min(len));
+                Some(first..end)
+            }
+            Spec::Suffix(n) if n == 0 || len == 0 => None,
+            Spec::Suffix(n) => Some(len.saturating_sub(n)..len),
+        }
+    }
+}
+
+/// Parse a `Range` header of byte ranges. Anything malformed makes the whole
+/// header be ignored, as is any other unit than bytes.
+fn parse(value: &HeaderValue) -> Option<Vec<Spec>> {
+    let ranges = value.to_str().ok()?.trim().strip_prefix("bytes=")?;
+    let specs = ranges
+        .split(',')
+        .map(|spec| {
+            let (first, last) = spec.trim().split_once('-')?;
+            if first.is_empty() {
+                return last.parse().ok().map(Spec::Suffix);
+            }
+            let first = first.parse().ok()?;
+            let last = match last {
+                "" => None,
+                last => Some(last.parse().ok().filter(|&last| last >= first)?),
+            };
+            Some(Spec::From(first, last))
+        })
+        .collect::<Option<Vec<Spec>>>()?;
+    if specs.len() > MAX_RANGES {
+        return None;
+    }
+    Some(specs)
+}
+
+fn describe(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::from_str(&value).expect("valid header")
+}
+
+/// Build a `multipart/byteranges` body of the ranges, each part with the
+/// response's content type, and make that the response's content type.
+fn multipart(headers: &mut HeaderMap, body: &Bytes, ranges: &[Range<u64>]) -> Bytes {
+    // The hasher's random keys make a boundary that no file could have been
+    // written to contain
+    let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+    let content_type = headers.remove(header::CONTENT_TYPE);
+    let len = body.len() as u64;
+
+    let mut multipart = BytesMut::new();
+    for range in ranges {
+        multipart.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        if let Some(content_type) = &content_type {
+            multipart.extend_from_slice(b"Content-Type: ");
+            multipart.extend_from_slice(content_type.as_bytes());
+            multipart.extend_from_slice(b"\r\n");
+        }
+        let content_range = format!("Content-Range: {}\r\n\r\n", describe(range, len));
+        multipart.extend_from_slice(content_range.as_bytes());
+        multipart.extend_from_slice(&body[range.start as usize..range.end as usize]);
+        multipart.extend_from_slice(b"\r\n");
+    }
+    multipart.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        header_value(format!("multipart/byteranges; boundary={}", boundary)),
+    );
+    multipart.freeze()
+}
@@ -0,0 +1,165 @@
+//! Recording requests with `--record`, and sending them again with the
+//! `replay` subcommand, to reproduce a problem seen by some client against
+//! another server, or against a build with more logging.
+//!
+//! Each request is appended to the record file as a line of JSON, with its
+//! method, URI, version and headers, and with `--record-responses` the status,
+//! headers and base64 body of the response. The server never reads request
+//! bodies, so they aren't recorded.
+
+use crate::client::Target;
+use crate::{Body, ClientAddr, Error, Result};
+use base64::Engine;
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response, Uri};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// Where requests are recorded
+pub struct Recorder {
+    path: PathBuf,
+    /// The file, once it's been opened for serving
+    file: OnceLock<Mutex<File>>,
+    responses: bool,
+}
+
+impl Recorder {
+    /// Record to the file, once it's opened.
+    pub fn new(path: PathBuf, responses: bool) -> Recorder {
+        Recorder {
+            path,
+            file: OnceLock::new(),
+            responses,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the file, to record after anything already recorded there.
+    pub fn open(&self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let _ = self.file.set(Mutex::new(file));
+        Ok(())
+    }
+
+    /// Record a request, which was taken with `request` before it was
+    /// responded to.
+    pub async fn record(&self, mut entry: Value, resp: &Response<Body>) {
+        if self.responses {
+            let body = match resp.body().clone().collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(never) => match never {},
+            };
+            entry["response"] = json!({
+                "status": resp.status().as_u16(),
+                "headers": headers_to_json(resp.headers()),
+                "body": base64::engine::general_purpose::STANDARD.encode(&body),
+            });
+        }
+        let mut line = entry.to_string();
+        line.push('\n');
+        let file = match self.file.get() {
+            Some(file) => file,
+            None => return,
+        };
+        let mut file = file.lock().expect("record file lock");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("failed to record request: {}", e);
+        }
+    }
+}
+
+/// What's recorded of a request
+pub fn request<B>(req: &Request<B>) -> Value {
+    json!({
+        "time": httpdate::fmt_http_date(SystemTime::now()),
+        "client": req.extensions().get::<ClientAddr>().map(|addr| addr.0.to_string()),
+        "method": req.method().as_str(),
+        "uri": req.uri().to_string(),
+        "version": format!("{:?}", req.version()),
+        "headers": headers_to_json(req.headers()),
+    })
+}
+
+/// Headers as a list of name and value pairs, which keeps their order and
+/// any repeats
+fn headers_to_json(headers: &HeaderMap) -> Value {
+    headers
+        .iter()
+        .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+        .collect()
+}
+
+/// A request as recorded, with only what's needed to replay it
+#[derive(Deserialize)]
+struct Recorded {
+    method: String,
+    uri: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    response: Option<RecordedResponse>,
+}
+
+#[derive(Deserialize)]
+struct RecordedResponse {
+    status: u16,
+}
+
+/// Send each request recorded in the file to the target server, in order,
+/// and print the status of each response, and the recorded status if that
+/// was different.
+pub fn replay(path: &Path, target: &str) -> Result<()> {
+    let target = Target::parse(target).ok_or_else(|| Error::ReplayTarget(target.to_owned()))?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (mut replayed, mut differed) = (0, 0);
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let recorded: Recorded = serde_json::from_str(&line).map_err(Error::RecordParse)?;
+        let req = replayed_request(&recorded)?;
+        let summary = format!("{} {}", req.method(), req.uri());
+        let status = runtime.block_on(target.send(req))?;
+        replayed += 1;
+
+        match recorded.response {
+            Some(response) if response.status != status.as_u16() => {
+                differed += 1;
+                println!("{} -> {} (recorded {})", summary, status, response.status);
+            }
+            _ => println!("{} -> {}", summary, status),
+        }
+    }
+    info!(
+        "replayed {} requests, {} with a different status",
+        replayed, differed
+    );
+    Ok(())
+}
+
+/// Make a request from a recorded one, with the recorded headers as they
+/// were, and no body.
+fn replayed_request(recorded: &Recorded) -> Result<Request<Body>> {
+    let uri: Uri = recorded.uri.parse().map_err(http::Error::from)?;
+    let mut builder = Request::builder()
+        .method(recorded.method.as_str())
+        .uri(uri.path_and_query().map_or("/", |pq| pq.as_str()));
+    for (name, value) in &recorded.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    Ok(builder.body(Body::new(Bytes::new()))?)
+}
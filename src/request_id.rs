@@ -0,0 +1,68 @@
+//! An ID for each request, with `--request-ids`, sent back in the
+//! `X-Request-Id` header and added to request lines and the request log, so
+//! that a request can be followed from the client through proxies to the
+//! server. A request from a trusted proxy keeps the ID the proxy gave it.
+
+use crate::forwarded::{self, Cidr};
+use http::{HeaderMap, HeaderValue};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+pub const HEADER: &str = "x-request-id";
+
+/// The longest ID that's kept from a proxy
+const MAX_LEN: usize = 200;
+
+/// The ID of a request, kept in its extensions
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The ID for a request from `peer`: the one in its headers, if it's
+    /// from a trusted proxy, or else a new one.
+    pub fn new(headers: &HeaderMap, peer: Option<SocketAddr>, trusted: &[Cidr]) -> RequestId {
+        let forwarded = headers
+            .get(HEADER)
+            .filter(|_| peer.is_some_and(|peer| forwarded::is_trusted(peer, trusted)))
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid(id));
+        match forwarded {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId::generate(),
+        }
+    }
+
+    /// A new ID, unique to this run of the server, and unlikely to be seen
+    /// from any other: a random number for the run, and a count of requests.
+    fn generate() -> RequestId {
+        static RUN: OnceLock<u64> = OnceLock::new();
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let run = RUN.get_or_init(|| RandomState::new().build_hasher().finish());
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        RequestId(format!("{:016x}{:08x}", run, count))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0).expect("valid header value")
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether an ID from a proxy can be kept. It has to fit on a log line, as
+/// one word.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic() && b != b'"')
+}
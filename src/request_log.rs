@@ -0,0 +1,238 @@
+//! A log of the last few requests, kept in memory with `--request-log` and
+//! served at `/__log`, for seeing what a browser actually asked for without
+//! access to the server's terminal. Browsers get a page that follows new
+//! requests as they come, scripts that ask for JSON get the entries, and
+//! `text/event-stream` gets them as server-sent events.
+//!
+//! Responses are built whole, so an event stream can't stay open. Instead
+//! it waits a while for new requests and ends, and the browser's
+//! `EventSource` reconnects with the `Last-Event-ID` of the last entry it
+//! got, to get the ones after it.
+
+use crate::negotiate::{self, Format};
+use crate::{Body, Error, HtmlCfg, Result};
+use handlebars::html_escape;
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Where the log is served
+pub const PATH: &str = "/__log";
+
+/// How long an event stream waits for new requests before ending
+const WAIT: Duration = Duration::from_secs(25);
+
+/// How soon browsers reconnect to an event stream that ended, in
+/// milliseconds
+const RETRY_MS: u32 = 200;
+
+pub struct RequestLog {
+    entries: Mutex<Entries>,
+    added: Notify,
+    capacity: usize,
+}
+
+struct Entries {
+    /// The ID of the next entry, which counts up from 1
+    next_id: u64,
+    /// The entries, oldest first
+    entries: VecDeque<Value>,
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize) -> RequestLog {
+        RequestLog {
+            entries: Mutex::new(Entries {
+                next_id: 1,
+                entries: VecDeque::with_capacity(capacity),
+            }),
+            added: Notify::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Log a request, which was taken with `record::request` before it was
+    /// responded to, with how it was responded to.
+    pub fn add(&self, mut entry: Value, resp: &Response<Body>, elapsed: Duration) {
+        use hyper::body::Body as _;
+
+        entry["status"] = resp.status().as_u16().into();
+        entry["bytes"] = resp.body().size_hint().exact().unwrap_or(0).into();
+        entry["ms"] = (elapsed.as_secs_f64() * 1000.0).into();
+        let mut entries = self.entries.lock().expect("request log lock");
+        entry["id"] = entries.next_id.into();
+        entries.next_id += 1;
+        if entries.entries.len() == self.capacity {
+            entries.entries.pop_front();
+        }
+        entries.entries.push_back(entry);
+        drop(entries);
+        self.added.notify_waiters();
+    }
+
+    /// The entries after the one with this ID, oldest first
+    fn after(&self, id: u64) -> Vec<Value> {
+        let entries = self.entries.lock().expect("request log lock");
+        entries
+            .entries
+            .iter()
+            .filter(|entry| entry["id"].as_u64().is_some_and(|entry_id| entry_id > id))
+            .cloned()
+            .collect()
+    }
+
+    /// The entries after the one with this ID, waiting a while for some if
+    /// there are none yet
+    async fn wait_after(&self, id: u64) -> Vec<Value> {
+        let deadline = tokio::time::Instant::now() + WAIT;
+        loop {
+            let added = self.added.notified();
+            tokio::pin!(added);
+            // Waiting before looking means an entry added in between isn't
+            // missed
+            added.as_mut().enable();
+            let entries = self.after(id);
+            if !entries.is_empty() {
+                return entries;
+            }
+            if tokio::time::timeout_at(deadline, added).await.is_err() {
+                return entries;
+            }
+        }
+    }
+
+    /// Respond to a request for the log.
+    pub async fn respond<B>(&self, req: &Request<B>) -> Result<Response<Body>> {
+        let accept = crate::vary::header(req, header::ACCEPT);
+        let stream = accept
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/event-stream"));
+        let mut resp = if stream {
+            // Reconnections say where they were up to, and first connections
+            // can say with `?after=`
+            let after = req
+                .headers()
+                .get("last-event-id")
+                .and_then(|id| id.to_str().ok())
+                .or_else(|| {
+                    req.uri()
+                        .query()
+                        .and_then(|query| query.split('&').find_map(|p| p.strip_prefix("after=")))
+                })
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0);
+            let entries = self.wait_after(after).await;
+            let mut events = format!("retry: {}\n\n", RETRY_MS);
+            for entry in entries {
+                events.push_str(&format!("id: {}\ndata: {}\n\n", entry["id"], entry));
+            }
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, events.len())
+                .header(header::CONTENT_TYPE, mime::TEXT_EVENT_STREAM.as_ref())
+                .body(Body::from(events))?
+        } else {
+            let entries = self.after(0);
+            match negotiate::format(req) {
+                Format::Json => crate::json_to_response(&Value::from(entries), StatusCode::OK)?,
+                Format::Html => {
+                    let html = render(&entries, self.capacity)?;
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_LENGTH, html.len())
+                        .header(header::CONTENT_TYPE, mime::TEXT_HTML_UTF_8.as_ref())
+                        .body(Body::from(html))?
+                }
+            }
+        };
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        Ok(resp)
+    }
+}
+
+/// Follows the log with an `EventSource`, adding a row for each request, the
+/// same as `write_row` does
+static SCRIPT: &str = r#"<script>
+(function () {
+    var table = document.getElementById("log");
+    var events = new EventSource(location.pathname + "?after=" + table.dataset.after);
+    events.onmessage = function (event) {
+        var entry = JSON.parse(event.data);
+        var row = table.insertRow(1);
+        var headers = entry.headers.map(function (header) {
+            return header[0] + ": " + header[1];
+        }).join("\n");
+        [entry.time, entry.method, entry.uri, entry.status, entry.bytes, entry.ms.toFixed(1)]
+            .forEach(function (value, i) {
+                var cell = row.insertCell();
+                cell.textContent = value;
+                if (i === 2) cell.title = headers;
+            });
+        while (table.rows.length > Number(table.dataset.capacity) + 1) {
+            table.deleteRow(-1);
+        }
+    };
+})();
+</script>
+"#;
+
+/// The log as a table, newest first, which follows new requests
+fn render(entries: &[Value], capacity: usize) -> Result<String> {
+    let after = entries
+        .last()
+        .and_then(|entry| entry["id"].as_u64())
+        .unwrap_or(0);
+    let mut body = String::new();
+    writeln!(
+        body,
+        "<table id=\"log\" data-after=\"{}\" data-capacity=\"{}\">",
+        after, capacity
+    )
+    .map_err(Error::WriteInRequestLog)?;
+    body.push_str(
+        "<tr><th>Time</th><th>Method</th><th>URI</th><th>Status</th><th>Bytes</th><th>ms</th></tr>\n",
+    );
+    for entry in entries.iter().rev() {
+        write_row(&mut body, entry).map_err(Error::WriteInRequestLog)?;
+    }
+    body.push_str("</table>\n");
+    body.push_str(SCRIPT);
+    crate::render_html(HtmlCfg {
+        title: "Requests".to_owned(),
+        body,
+    })
+}
+
+/// A row for a request, with its headers in the URI's tooltip
+fn write_row(body: &mut String, entry: &Value) -> std::fmt::Result {
+    let text = |value: &Value| match value {
+        Value::String(value) => html_escape(value),
+        value => html_escape(&value.to_string()),
+    };
+    let headers: Vec<String> = entry["headers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|header| format!("{}: {}", text(&header[0]), text(&header[1])))
+        .collect();
+    writeln!(
+        body,
+        "<tr><td>{}</td><td>{}</td><td title=\"{}\">{}</td><td>{}</td><td>{}</td><td>{:.1}</td></tr>",
+        text(&entry["time"]),
+        text(&entry["method"]),
+        headers.join("\n"),
+        text(&entry["uri"]),
+        entry["status"],
+        entry["bytes"],
+        entry["ms"].as_f64().unwrap_or(0.0),
+    )
+}
@@ -0,0 +1,149 @@
+//! Settings for parts of the site, with `--route`, like rendering markdown
+//! only under `/docs`. A route is a URL path prefix and the settings to use
+//! under it, e.g. `--route="/docs ext=markdown,highlight no-cache"`, or in a
+//! config file:
+//!
+//! ```toml
+//! [route."/docs"]
+//! ext = "markdown,highlight"
+//! no-cache = true
+//! ```
+//!
+//! Every route that a request's path is under applies, the more specific
+//! ones overriding the less specific, and those overriding the settings for
+//! the whole site.
+
+use crate::ext::Extensions;
+use crate::{Config, Error, Result};
+use std::sync::Arc;
+
+struct Route {
+    prefix: String,
+    /// The developer extensions to enable, instead of `-x` or `--ext`
+    extensions: Option<Extensions>,
+    /// Whether to tell browsers not to cache responses without checking
+    no_cache: Option<bool>,
+}
+
+/// The routes, from the least to the most specific
+#[derive(Clone, Default)]
+pub struct Routes(Arc<Vec<Route>>);
+
+impl Routes {
+    pub fn new<'a>(values: impl IntoIterator<Item = &'a str>) -> Result<Routes> {
+        let mut routes: Vec<Route> = values
+            .into_iter()
+            .map(|value| parse(value).ok_or_else(|| Error::RouteParse(value.to_owned())))
+            .collect::<Result<_>>()?;
+        routes.sort_by_key(|route| route.prefix.len());
+        Ok(Routes(Arc::new(routes)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Change the configuration for a request to the settings of the routes
+    /// its URL path is under.
+    pub fn apply(&self, config: &mut Config, path: &str) {
+        for route in self.0.iter().filter(|route| is_under(path, &route.prefix)) {
+            trace!("using route {}", route.prefix);
+            if let Some(extensions) = route.extensions {
+                config.extensions = extensions;
+            }
+            if let Some(no_cache) = route.no_cache {
+                config.no_cache = no_cache;
+            }
+        }
+    }
+}
+
+/// Parse a route, a URL path prefix followed by space-separated settings:
+/// `ext=LIST`, and `no-cache`, which may be given as `no-cache=false` to
+/// turn it off again.
+fn parse(value: &str) -> Option<Route> {
+    let mut words = value.split_whitespace();
+    let prefix = words.next().filter(|prefix| prefix.starts_with('/'))?;
+    let mut route = Route {
+        prefix: prefix.trim_end_matches('/').to_string(),
+        extensions: None,
+        no_cache: None,
+    };
+    for setting in words {
+        let (key, value) = match setting.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (setting, None),
+        };
+        match (key, value) {
+            ("ext", Some(list)) => route.extensions = Some(Extensions::parse(list).ok()?),
+            ("no-cache", None) | ("no-cache", Some("true")) => route.no_cache = Some(true),
+            ("no-cache", Some("false")) => route.no_cache = Some(false),
+            _ => return None,
+        }
+    }
+    Some(route)
+}
+
+/// Whether a URL path is under a prefix, whole segment by segment, so that
+/// `/docs` is under `/docs` but `/docsite` isn't. The prefix has no trailing
+/// slash, so the root is the empty prefix.
+fn is_under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::Extension;
+    use std::ffi::OsString;
+
+    fn routes_from(cmdline: &[&str]) -> Routes {
+        let app = crate::serve_args(clap::App::new("basic-http-server"));
+        let matches = app.get_matches_from_safe(cmdline).unwrap();
+        Routes::new(matches.values_of("route").into_iter().flatten()).unwrap()
+    }
+
+    fn assert_docs_route(routes: &Routes) {
+        assert_eq!(routes.len(), 1);
+        let route = &routes.0[0];
+        assert_eq!(route.prefix, "/docs");
+        let extensions = route.extensions.unwrap();
+        assert!(extensions.contains(Extension::Markdown));
+        assert!(extensions.contains(Extension::Highlight));
+        assert_eq!(route.no_cache, Some(true));
+    }
+
+    #[test]
+    fn documented_example_parses() {
+        let routes = routes_from(&[
+            "basic-http-server",
+            "--route=/docs ext=markdown,highlight no-cache",
+        ]);
+        assert_docs_route(&routes);
+    }
+
+    #[test]
+    fn documented_config_parses() {
+        let path = std::env::temp_dir().join(format!("routes-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[route.\"/docs\"]\next = \"markdown,highlight\"\nno-cache = true\n",
+        )
+        .unwrap();
+        let app = crate::serve_args(clap::App::new("basic-http-server"));
+        let matches = app.get_matches_from(["basic-http-server"]);
+        let mut cmdline = vec![OsString::from("basic-http-server")];
+        let added = crate::config_file::add_args(&path, &matches, &mut cmdline);
+        std::fs::remove_file(&path).unwrap();
+        added.unwrap();
+        let cmdline: Vec<_> = cmdline.iter().map(|arg| arg.to_str().unwrap()).collect();
+        assert_docs_route(&routes_from(&cmdline));
+    }
+}
@@ -0,0 +1,113 @@
+//! Confining the server's filesystem access to the root directory with
+//! Landlock, enabled with `--sandbox` on Linux. This is defense in depth: a
+//! request that got past the path checks still couldn't read anything else.
+//!
+//! With `--read-only`, and without `--sandbox`, Landlock forbids writing to
+//! the filesystem instead, leaving reading alone.
+//!
+//! Landlock only restricts the thread that asks and the threads it starts,
+//! so the sandbox is entered before the runtime starts its threads.
+
+use crate::listen::ListenAddr;
+use crate::{Config, Error, Result};
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    ABI,
+};
+use std::path::Path;
+
+/// The newest Landlock version the server knows about. Older kernels enforce
+/// what they can of it.
+const LANDLOCK_ABI: ABI = ABI::V6;
+
+/// Restrict the process to reading the root directory, or the root archive,
+/// the `--maintenance-page` and the `--template-dir`, and to the few writes
+/// the server makes outside it: creating and removing unix sockets, writing
+/// the `--addr-file`, `--info-file`, `--pid-file` and `--summary-json`, and
+/// the `--compress-cache-dir`.
+pub fn enter(config: &Config) -> Result<()> {
+    let read = AccessFs::from_read(LANDLOCK_ABI);
+    let sockets = AccessFs::MakeSock | AccessFs::RemoveFile;
+    let write = AccessFs::from_write(LANDLOCK_ABI);
+
+    let written_dirs: Vec<&Path> = config
+        .addr_file
+        .iter()
+        .chain(config.info.as_ref().and_then(|info| info.file()))
+        .chain(&config.daemon.pid_file)
+        .chain(&config.summary_json)
+        .map(|path| parent(path))
+        .collect();
+    let cache_dir = config
+        .compression
+        .as_ref()
+        .and_then(|compression| compression.cache_dir());
+
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules([&config.root_dir], read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&config.maintenance_page, read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&config.template_dir, read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&config.mock, read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(config.injections.paths(), read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(socket_dirs(config), sockets)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(written_dirs, write)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(cache_dir, read | write)))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(Error::Sandbox)?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("sandboxed to {}", config.root_dir.display()),
+        RulesetStatus::PartiallyEnforced => warn!(
+            "sandboxed to {}, but this kernel can't enforce every restriction",
+            config.root_dir.display()
+        ),
+        RulesetStatus::NotEnforced => return Err(Error::SandboxUnsupported),
+    }
+    Ok(())
+}
+
+/// Forbid writing to the filesystem, but for creating and removing unix
+/// sockets, for `--read-only`. The arguments that write files have been
+/// refused already, so this is defense in depth, and a kernel without
+/// Landlock only gets a warning.
+pub fn forbid_writes(config: &Config) -> Result<()> {
+    let sockets = AccessFs::MakeSock | AccessFs::RemoveFile;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_write(LANDLOCK_ABI))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(socket_dirs(config), sockets)))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(Error::Sandbox)?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("forbidden from writing files"),
+        RulesetStatus::PartiallyEnforced => {
+            warn!("forbidden from writing files, but this kernel can't enforce every restriction")
+        }
+        RulesetStatus::NotEnforced => warn!("this kernel can't forbid writing files"),
+    }
+    Ok(())
+}
+
+/// The directories unix sockets are created in
+fn socket_dirs(config: &Config) -> Vec<&Path> {
+    config
+        .addrs
+        .iter()
+        .filter_map(|addr| match addr {
+            ListenAddr::Unix(path) => Some(parent(path)),
+            ListenAddr::Tcp(_) => None,
+        })
+        .collect()
+}
+
+/// The directory a file is created in, which for a bare file name is the
+/// current directory
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    }
+}
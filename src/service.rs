@@ -0,0 +1,137 @@
+//! Running as a native Windows service, for a small file server on an
+//! intranet. The `install-service` subcommand registers the service with the
+//! arguments to serve with, and the service control manager then starts the
+//! server with `--service` added.
+
+use crate::{Config, Error, Result};
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "basic-http-server";
+
+/// Notified when the service control manager asks the service to stop
+pub static STOP: Notify = Notify::const_new();
+
+/// The configuration, waiting for the thread that the service control
+/// manager runs the service on
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Run as the service, having been started by the service control manager,
+/// until the service is stopped.
+pub fn run(config: Config) -> Result<()> {
+    *CONFIG.lock().unwrap() = Some(config);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(Error::WindowsService)
+}
+
+fn service_main(_args: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        crate::log_error_chain(&e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let config = CONFIG.lock().unwrap().take().expect("configuration");
+    let handler = |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status =
+        service_control_handler::register(SERVICE_NAME, handler).map_err(Error::WindowsService)?;
+
+    set_status(&status, ServiceState::Running, 0)?;
+    let result = crate::start_runtime(config, Vec::new());
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    set_status(&status, ServiceState::Stopped, exit_code)?;
+    result
+}
+
+fn set_status(status: &ServiceStatusHandle, state: ServiceState, exit_code: u32) -> Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    status
+        .set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+        .map_err(Error::WindowsService)
+}
+
+/// Register the service, to start with Windows and serve with `args`, the
+/// server's usual arguments. Services start in the system directory, so any
+/// paths should be absolute.
+pub fn install(args: Vec<OsString>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(Error::WindowsService)?;
+
+    let mut launch_arguments = vec![OsString::from("--service")];
+    launch_arguments.extend(args);
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: "Basic HTTP Server".into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        // The local system account
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(Error::WindowsService)?;
+    service
+        .set_description(env!("CARGO_PKG_DESCRIPTION"))
+        .map_err(Error::WindowsService)?;
+    info!("installed service {}", SERVICE_NAME);
+    Ok(())
+}
+
+/// Stop the service if it's running, and remove it.
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(Error::WindowsService)?;
+    let access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE;
+    let service = manager
+        .open_service(SERVICE_NAME, access)
+        .map_err(Error::WindowsService)?;
+
+    let state = service
+        .query_status()
+        .map_err(Error::WindowsService)?
+        .current_state;
+    if state != ServiceState::Stopped {
+        service.stop().map_err(Error::WindowsService)?;
+    }
+    service.delete().map_err(Error::WindowsService)?;
+    info!("uninstalled service {}", SERVICE_NAME);
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! Counts of what the server has done since it started, for the admin API,
+//! the dashboard, the summary printed on exit with `--summary`, and the
+//! download counts served at `/__stats` with `--hit-counters`.
+
+use http::Method;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+/// Responses by the first digit of their status, 1xx to 5xx
+static RESPONSES: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static DETAIL: Mutex<Detail> = Mutex::new(Detail {
+    recent: VecDeque::new(),
+    paths: BTreeMap::new(),
+    downloads: BTreeMap::new(),
+    latencies: [0; LATENCY_BUCKETS],
+    countries: BTreeMap::new(),
+    networks: BTreeMap::new(),
+});
+
+/// How many requests `recent` remembers
+const RECENT_LEN: usize = 50;
+
+/// How many different paths are counted, so that a scan of made-up paths
+/// can't use up memory. Paths after these aren't counted.
+const MAX_PATHS: usize = 10_000;
+
+/// Where the download counts are served, with `--hit-counters`
+pub const HITS_PATH: &str = "/__stats";
+
+/// How many paths `Snapshot::top_paths` has
+const TOP_PATHS: usize = 10;
+
+/// Latencies are counted in buckets, eight to each doubling of microseconds,
+/// so that percentiles are within an eighth of the truth. The last bucket
+/// takes everything over an hour.
+const LATENCY_BUCKETS: usize = 8 * 30;
+
+/// What's kept about requests besides the counts
+struct Detail {
+    /// The last few requests, newest last
+    recent: VecDeque<Recent>,
+    /// Requests by path
+    paths: BTreeMap<String, u64>,
+    /// Whole files sent by path, which is `paths` without errors, `HEAD`
+    /// requests or parts of files
+    downloads: BTreeMap<String, u64>,
+    /// Requests by latency bucket
+    latencies: [u64; LATENCY_BUCKETS],
+    /// Requests by the client's country and network, with `--geoip`
+    countries: BTreeMap<String, u64>,
+    networks: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Snapshot {
+    pub uptime_secs: u64,
+    pub connections: u64,
+    pub requests: u64,
+    pub bytes_sent: u64,
+    /// Responses by the first digit of their status, like "2xx"
+    pub responses: BTreeMap<String, u64>,
+    /// The most requested paths, most first
+    pub top_paths: Vec<PathCount>,
+    pub latency_ms: Latency,
+    /// Requests by the client's country, with `--geoip`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub countries: BTreeMap<String, u64>,
+    /// Requests by the client's network, like "AS3320"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PathCount {
+    pub path: String,
+    pub requests: u64,
+}
+
+/// Percentiles of the time taken to respond, in milliseconds
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Latency {
+    pub p50: f64,
+    pub p95: f64,
+}
+
+/// A request that was recently responded to
+#[derive(Clone, Debug)]
+pub struct Recent {
+    pub method: Method,
+    pub path: String,
+    pub status: u16,
+    pub bytes: u64,
+}
+
+/// Note the time the server started serving.
+pub fn start() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub fn connection() {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a response, the size of its body and how long it took, and
+/// remember the request it was for.
+pub fn response<B: hyper::body::Body>(
+    method: Method,
+    path: String,
+    resp: &http::Response<B>,
+    elapsed: Duration,
+) {
+    let status = resp.status().as_u16();
+    if let Some(count) = RESPONSES.get((status / 100) as usize - 1) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    let bytes = resp.body().size_hint().exact().unwrap_or(0);
+    BYTES_SENT.fetch_add(bytes, Ordering::Relaxed);
+
+    let mut detail = DETAIL.lock().expect("stats lock");
+    let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+    detail.latencies[latency_bucket(micros)] += 1;
+    count_path(&mut detail.paths, &path);
+    if method == Method::GET && status == 200 {
+        count_path(&mut detail.downloads, &path);
+    }
+    if detail.recent.len() == RECENT_LEN {
+        detail.recent.pop_front();
+    }
+    detail.recent.push_back(Recent {
+        method,
+        path,
+        status,
+        bytes,
+    });
+}
+
+/// Count a request from a client at a location.
+pub fn location(location: &crate::geoip::Location) {
+    let mut detail = DETAIL.lock().expect("stats lock");
+    if let Some(country) = &location.country {
+        count_path(&mut detail.countries, country);
+    }
+    if let Some(asn) = location.asn {
+        count_path(&mut detail.networks, &format!("AS{}", asn));
+    }
+}
+
+fn count_path(counts: &mut BTreeMap<String, u64>, path: &str) {
+    if let Some(count) = counts.get_mut(path) {
+        *count += 1;
+    } else if counts.len() < MAX_PATHS {
+        counts.insert(path.to_owned(), 1);
+    }
+}
+
+/// The number of times each path has been downloaded, most first, as a JSON
+/// object of paths to counts
+pub fn downloads() -> serde_json::Value {
+    let detail = DETAIL.lock().expect("stats lock");
+    let mut downloads: Vec<(&String, &u64)> = detail.downloads.iter().collect();
+    downloads.sort_by_key(|&(_, &count)| std::cmp::Reverse(count));
+    downloads
+        .into_iter()
+        .map(|(path, &count)| (path.clone(), serde_json::Value::from(count)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The last few requests, newest last
+pub fn recent() -> Vec<Recent> {
+    let detail = DETAIL.lock().expect("stats lock");
+    detail.recent.iter().cloned().collect()
+}
+
+pub fn snapshot() -> Snapshot {
+    let responses: Vec<u64> = RESPONSES
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .collect();
+    let detail = DETAIL.lock().expect("stats lock");
+    let mut top_paths: Vec<PathCount> = detail
+        .paths
+        .iter()
+        .map(|(path, &requests)| PathCount {
+            path: path.clone(),
+            requests,
+        })
+        .collect();
+    top_paths.sort_by_key(|path| std::cmp::Reverse(path.requests));
+    top_paths.truncate(TOP_PATHS);
+    Snapshot {
+        uptime_secs: STARTED
+            .get()
+            .map_or(0, |started| started.elapsed().as_secs()),
+        connections: CONNECTIONS.load(Ordering::Relaxed),
+        requests: responses.iter().sum(),
+        bytes_sent: BYTES_SENT.load(Ordering::Relaxed),
+        responses: responses
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (format!("{}xx", i + 1), count))
+            .collect(),
+        top_paths,
+        latency_ms: Latency {
+            p50: percentile(&detail.latencies, 0.5),
+            p95: percentile(&detail.latencies, 0.95),
+        },
+        countries: detail.countries.clone(),
+        networks: detail.networks.clone(),
+    }
+}
+
+/// Print a summary of everything served, for when the server stops.
+pub fn print_summary() {
+    let stats = snapshot();
+    let uptime = stats.uptime_secs;
+    println!(
+        "served {} requests on {} connections in {}:{:02}:{:02}, sending {}",
+        stats.requests,
+        stats.connections,
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60,
+        format_bytes(stats.bytes_sent as f64),
+    );
+    if stats.requests == 0 {
+        return;
+    }
+    let responses: Vec<String> = stats
+        .responses
+        .iter()
+        .filter(|(_, &count)| count > 0)
+        .map(|(class, count)| format!("{} {}", class, count))
+        .collect();
+    println!("responses: {}", responses.join(", "));
+    println!(
+        "latency: p50 {:.1} ms, p95 {:.1} ms",
+        stats.latency_ms.p50, stats.latency_ms.p95
+    );
+    println!("top paths:");
+    for path in &stats.top_paths {
+        println!("{:>10}  {}", path.requests, path.path);
+    }
+}
+
+/// Write the summary printed by `print_summary` to a file, as JSON.
+pub fn write_summary(path: &Path) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(&snapshot()).expect("stats are JSON");
+    std::fs::write(path, json)
+}
+
+/// A size in bytes, in the largest binary unit it has at least one of
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{} B", bytes as u64);
+    }
+    let mut size = bytes / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// The bucket for a latency: the first eight are a microsecond each, and
+/// each doubling after that is split into eight.
+fn latency_bucket(micros: u64) -> usize {
+    if micros < 8 {
+        return micros as usize;
+    }
+    let log2 = 63 - micros.leading_zeros() as usize;
+    let eighth = (micros >> (log2 - 3)) as usize & 7;
+    ((log2 - 2) * 8 + eighth).min(LATENCY_BUCKETS - 1)
+}
+
+/// The smallest latency in a bucket, in microseconds
+fn latency_bucket_floor(bucket: usize) -> u64 {
+    if bucket < 8 {
+        return bucket as u64;
+    }
+    let log2 = bucket / 8 + 2;
+    (8 + (bucket % 8) as u64) << (log2 - 3)
+}
+
+/// The latency that the fraction `p` of requests took no longer than, in
+/// milliseconds. It's the top of the bucket it falls in, so never too low.
+fn percentile(latencies: &[u64], p: f64) -> f64 {
+    let total: u64 = latencies.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let rank = (total as f64 * p).ceil().max(1.0) as u64;
+    let mut seen = 0;
+    for (bucket, &count) in latencies.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return latency_bucket_floor(bucket + 1) as f64 / 1000.0;
+        }
+    }
+    latency_bucket_floor(latencies.len()) as f64 / 1000.0
+}
@@ -0,0 +1,117 @@
+//! Serving standard input with `--stdin`, for the end of a pipeline like
+//! `make-report | basic-http-server --stdin`. The input is read in full
+//! before serving, and is served at `/`, with `--once` stopping the server
+//! once it's been downloaded.
+
+use crate::{Body, Error, Result};
+use bytes::Bytes;
+use http::{header, HeaderValue, Response};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Set when the input has been sent in full with `--once`
+static SENT: AtomicBool = AtomicBool::new(false);
+
+pub struct Stdin {
+    /// The content type given, rather than sniffed
+    content_type: Option<HeaderValue>,
+    /// The input, once it's been read for serving
+    input: OnceLock<Input>,
+    once: bool,
+}
+
+struct Input {
+    body: Bytes,
+    content_type: HeaderValue,
+    read_at: SystemTime,
+}
+
+impl Stdin {
+    /// Serve standard input, once it's read. Without a content type, it's
+    /// served as HTML if it looks like HTML, or else as text or binary data.
+    pub fn new(content_type: Option<&str>, once: bool) -> Result<Stdin> {
+        let content_type = match content_type {
+            Some(content_type) => {
+                let mime = content_type
+                    .parse::<mime::Mime>()
+                    .map_err(Error::ContentTypeParse)?;
+                Some(HeaderValue::from_str(mime.as_ref()).map_err(http::Error::from)?)
+            }
+            None => None,
+        };
+        Ok(Stdin {
+            content_type,
+            input: OnceLock::new(),
+            once,
+        })
+    }
+
+    /// Read standard input to the end.
+    pub fn read(&self) -> Result<()> {
+        let mut body = Vec::new();
+        io::stdin().read_to_end(&mut body)?;
+        let content_type = match &self.content_type {
+            Some(content_type) => content_type.clone(),
+            None => HeaderValue::from_str(sniff(&body).as_ref()).map_err(http::Error::from)?,
+        };
+        let _ = self.input.set(Input {
+            body: body.into(),
+            content_type,
+            read_at: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    /// The response with the input. `whole` is whether it's the whole input
+    /// that's being sent, and not only its headers or a range of it.
+    pub fn response(&self, whole: bool) -> Result<Response<Body>> {
+        let input = self.input();
+        let mut builder = Response::builder()
+            .header(header::CONTENT_TYPE, input.content_type.clone())
+            .header(header::CONTENT_LENGTH, input.body.len())
+            .header(
+                header::LAST_MODIFIED,
+                httpdate::fmt_http_date(input.read_at),
+            );
+        if self.once && whole {
+            // The server stops when this connection closes, so it mustn't be
+            // kept open
+            builder = builder.header(header::CONNECTION, "close");
+            SENT.store(true, Ordering::Relaxed);
+        }
+        Ok(builder.body(Body::new(input.body.clone()))?)
+    }
+
+    /// The size of the input in bytes
+    pub fn size(&self) -> usize {
+        self.input().body.len()
+    }
+
+    fn input(&self) -> &Input {
+        self.input
+            .get()
+            .expect("standard input is read before serving")
+    }
+}
+
+/// Whether the server should stop, having sent the input with `--once`. It's
+/// asked as each connection closes, so the response isn't cut off.
+pub fn finished() -> bool {
+    SENT.load(Ordering::Relaxed)
+}
+
+fn sniff(body: &[u8]) -> mime::Mime {
+    let text = match std::str::from_utf8(body) {
+        Ok(text) => text,
+        Err(_) => return mime::APPLICATION_OCTET_STREAM,
+    };
+    let start: String = text.trim_start().chars().take(14).collect();
+    let start = start.to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        mime::TEXT_HTML_UTF_8
+    } else {
+        mime::TEXT_PLAIN_UTF_8
+    }
+}
@@ -0,0 +1,177 @@
+//! The handlebars templates that generated pages, like error pages and
+//! directory listings, are rendered with. They're compiled once, instead of
+//! for every page. `--template-dir` overrides the built-in page template
+//! with `page.hbs` from a directory, whose other `.hbs` files can be used
+//! as partials. The directory is checked for changes when pages are
+//! rendered, at most once a second, and its templates are recompiled when
+//! any of them change, so a theme can be worked on while the server runs.
+
+use crate::{Error, Result};
+use handlebars::Handlebars;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The template of a whole HTML page, given a `title` and a `body`
+pub const PAGE: &str = "page";
+
+static BUILTIN_PAGE: &str = include_str!("template.html");
+
+/// How long after checking the template directory to check it again
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts the times the templates have been compiled, so that pages
+/// rendered with older ones can be told apart
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+struct Templates {
+    registry: Handlebars,
+    dir: Option<Dir>,
+}
+
+/// A directory of templates overriding the built-in ones
+struct Dir {
+    path: PathBuf,
+    checked: Instant,
+    /// The templates, with their modification times, when last checked
+    templates: Vec<(PathBuf, SystemTime)>,
+}
+
+fn templates() -> &'static RwLock<Templates> {
+    static TEMPLATES: OnceLock<RwLock<Templates>> = OnceLock::new();
+    TEMPLATES.get_or_init(|| {
+        RwLock::new(Templates {
+            registry: builtin(),
+            dir: None,
+        })
+    })
+}
+
+fn builtin() -> Handlebars {
+    let mut registry = Handlebars::new();
+    registry
+        .register_template_string(PAGE, BUILTIN_PAGE)
+        .expect("valid built-in template");
+    registry
+}
+
+/// Render pages with the templates in a directory from now on, failing if
+/// they can't be read or compiled.
+pub fn use_dir(path: PathBuf) -> Result<()> {
+    let templates = scan(&path)?;
+    let registry = compile(&templates)?;
+    let dir = Dir {
+        path,
+        checked: Instant::now(),
+        templates,
+    };
+    *self::templates().write().unwrap() = Templates {
+        registry,
+        dir: Some(dir),
+    };
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Render a template with the data.
+pub fn render(name: &str, data: &impl Serialize) -> Result<String> {
+    reload_if_changed();
+    let templates = templates().read().unwrap();
+    templates
+        .registry
+        .render(name, data)
+        .map_err(|e| Error::TemplateRender(Box::new(e.into())))
+}
+
+/// Which templates pages are rendered with now, after recompiling them if
+/// they've changed
+pub fn generation() -> u64 {
+    reload_if_changed();
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Recompile the templates in the directory if any have changed, keeping
+/// the ones compiled before if they can't be read or compiled now.
+fn reload_if_changed() {
+    let due = |dir: &Option<Dir>| match dir {
+        Some(dir) => dir.checked.elapsed() >= CHECK_INTERVAL,
+        None => false,
+    };
+    if !due(&templates().read().unwrap().dir) {
+        return;
+    }
+    let mut templates = templates().write().unwrap();
+    // Another page may have been rendered in between, and checked already
+    if !due(&templates.dir) {
+        return;
+    }
+    let Templates { registry, dir } = &mut *templates;
+    let dir = dir.as_mut().expect("checked");
+    dir.checked = Instant::now();
+
+    let changed = match scan(&dir.path) {
+        Ok(templates) => templates,
+        Err(e) => {
+            warn!("failed to check templates in {}: {}", dir.path.display(), e);
+            return;
+        }
+    };
+    if changed == dir.templates {
+        return;
+    }
+    // Templates that fail to compile aren't tried again until they change
+    dir.templates = changed;
+    match compile(&dir.templates) {
+        Ok(changed) => {
+            info!("reloaded templates from {}", dir.path.display());
+            *registry = changed;
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => warn!(
+            "failed to reload templates from {}: {}",
+            dir.path.display(),
+            e
+        ),
+    }
+}
+
+/// The `.hbs` files in a directory, with their modification times
+fn scan(dir: &Path) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut templates = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("hbs")) {
+            let modified = fs::metadata(&path)?.modified()?;
+            templates.push((path, modified));
+        }
+    }
+    templates.sort();
+    Ok(templates)
+}
+
+/// The built-in templates, overridden by and along with the templates in
+/// the directory, each named by its file stem.
+fn compile(templates: &[(PathBuf, SystemTime)]) -> Result<Handlebars> {
+    let mut registry = builtin();
+    for (path, _) in templates {
+        let name = match path.file_stem().and_then(OsStr::to_str) {
+            Some(name) => name,
+            None => {
+                warn!("non-unicode template name: {}", path.display());
+                continue;
+            }
+        };
+        let template = fs::read_to_string(path)?;
+        registry
+            .register_template_string(name, template)
+            .map_err(|e| {
+                Error::TemplateParse(Box::new(e.in_template(path.display().to_string())))
+            })?;
+    }
+    Ok(registry)
+}
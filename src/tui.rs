@@ -0,0 +1,111 @@
+//! A dashboard of live traffic drawn in the terminal with `--tui`, for
+//! watching a site being demoed. It's redrawn every second from `stats`, on
+//! the terminal's alternate screen, and logging is turned off meanwhile so it
+//! doesn't scribble over it.
+
+use crate::listen::ListenAddr;
+use crate::stats::{self, Snapshot};
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+/// How often the dashboard is redrawn
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many recent requests are shown
+const RECENT_LINES: usize = 15;
+
+/// Switch to the alternate screen and hide the cursor
+const ENTER: &str = "\x1b[?1049h\x1b[?25l";
+/// Show the cursor and switch back to the normal screen
+const LEAVE: &str = "\x1b[?25h\x1b[?1049l";
+/// Move to the top left and clear the screen
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Whether the dashboard can be shown, warning if not.
+pub fn available() -> bool {
+    let terminal = io::stdout().is_terminal();
+    if !terminal {
+        warn!("--tui needs stdout to be a terminal");
+    }
+    terminal
+}
+
+/// Draw the dashboard until the task is dropped, which the caller should
+/// follow with `leave`.
+pub async fn run(addrs: Vec<ListenAddr>) {
+    log::set_max_level(log::LevelFilter::Off);
+    print(ENTER);
+    let mut interval = tokio::time::interval(INTERVAL);
+    let mut last = stats::snapshot();
+    loop {
+        interval.tick().await;
+        let now = stats::snapshot();
+        print(&render(&addrs, &last, &now));
+        last = now;
+    }
+}
+
+/// Restore the terminal, and logging.
+pub fn leave() {
+    print(LEAVE);
+    log::set_max_level(log::LevelFilter::Trace);
+}
+
+fn print(s: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(s.as_bytes());
+    let _ = stdout.flush();
+}
+
+fn render(addrs: &[ListenAddr], last: &Snapshot, now: &Snapshot) -> String {
+    let secs = INTERVAL.as_secs_f64();
+    let rate = (now.requests - last.requests) as f64 / secs;
+    let bandwidth = (now.bytes_sent - last.bytes_sent) as f64 / secs;
+    let uptime = now.uptime_secs;
+
+    let mut out = String::from(CLEAR);
+    let addrs: Vec<String> = addrs.iter().map(ListenAddr::to_string).collect();
+    let _ = writeln!(
+        out,
+        "basic-http-server {}  {}  up {}:{:02}:{:02}",
+        env!("CARGO_PKG_VERSION"),
+        addrs.join(" "),
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60,
+    );
+    out.push('\n');
+    let _ = writeln!(
+        out,
+        "requests    {:>10.1}/s  {:>10} total",
+        rate, now.requests
+    );
+    let _ = writeln!(
+        out,
+        "bandwidth   {:>10}/s  {:>10} total",
+        stats::format_bytes(bandwidth),
+        stats::format_bytes(now.bytes_sent as f64)
+    );
+    let _ = writeln!(out, "connections {:>10}", now.connections);
+    out.push('\n');
+    let statuses: Vec<String> = now
+        .responses
+        .iter()
+        .map(|(class, count)| format!("{} {}", class, count))
+        .collect();
+    let _ = writeln!(out, "status      {}", statuses.join("   "));
+    out.push('\n');
+    let _ = writeln!(out, "recent requests");
+    for recent in stats::recent().iter().rev().take(RECENT_LINES) {
+        let _ = writeln!(
+            out,
+            "  {} {:<7} {:>10}  {}",
+            recent.status,
+            recent.method,
+            stats::format_bytes(recent.bytes as f64),
+            recent.path
+        );
+    }
+    out
+}
@@ -0,0 +1,267 @@
+//! Reading files with io_uring, enabled by the `io-uring` feature and the
+//! `--io-uring` flag.
+//!
+//! The server shares one ring, driven by the runtime rather than the
+//! blocking pool: files are looked up, opened and read by the kernel, each
+//! task waiting for its own operations, and the ring's eventfd wakes a task
+//! that hands the results out. A whole file is read with as few submissions as the kernel
+//! allows, instead of the many small `read` calls of the usual file reader.
+
+use crate::vfs::{LocalFs, Metadata, Vfs, VfsFile, VfsFuture};
+use io_uring::{opcode, squeue, types, IoUring};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{oneshot, OnceCell};
+
+const RING_ENTRIES: u32 = 256;
+
+/// How much more to read than the file's length, to find its end
+const READ_AHEAD: usize = 64 << 10;
+
+static RING: OnceCell<Ring> = OnceCell::const_new();
+
+/// The local disk, read with io_uring. Listing directories, which io_uring
+/// can't do, is left to `LocalFs`.
+pub struct UringFs;
+
+impl Vfs for UringFs {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        Box::pin(async move {
+            let ring = RING.get_or_try_init(Ring::start).await?;
+            let buf = read_file(ring, path).await?;
+            Ok(Box::new(Cursor::new(buf)) as VfsFile)
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        Box::pin(async move {
+            let ring = RING.get_or_try_init(Ring::start).await?;
+            Ok(stat(ring, path).await?)
+        })
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        LocalFs.read_dir(path)
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+struct Ring {
+    state: Mutex<State>,
+}
+
+struct State {
+    ring: IoUring,
+    /// The operations the kernel hasn't finished, by their user data
+    ops: HashMap<u64, Op>,
+    next_id: u64,
+}
+
+/// An operation in progress
+struct Op {
+    /// The memory the kernel reads from or writes to, which is kept here
+    /// until it's done, even if the task waiting for it has gone
+    buf: Vec<u8>,
+    /// Whether the result is a file descriptor, to be closed if the task
+    /// waiting for it has gone
+    opens: bool,
+    done: oneshot::Sender<(i32, Vec<u8>)>,
+}
+
+impl Ring {
+    /// Create the ring, and start the task that hands out its results.
+    async fn start() -> io::Result<Ring> {
+        debug!("creating io_uring");
+        let ring = IoUring::new(RING_ENTRIES)?;
+        // Safety: eventfd only returns a new descriptor, or an error
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if eventfd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: the descriptor was just created, and nothing else owns it
+        let eventfd = unsafe { OwnedFd::from_raw_fd(eventfd) };
+        ring.submitter().register_eventfd(eventfd.as_raw_fd())?;
+        let eventfd = AsyncFd::new(eventfd)?;
+        tokio::spawn(drive(eventfd));
+        Ok(Ring {
+            state: Mutex::new(State {
+                ring,
+                ops: HashMap::new(),
+                next_id: 0,
+            }),
+        })
+    }
+
+    /// Submit an operation, and wait for its result and its buffer back.
+    ///
+    /// # Safety
+    ///
+    /// The operation mustn't use any memory but `buf`'s.
+    async unsafe fn submit(
+        &self,
+        entry: squeue::Entry,
+        buf: Vec<u8>,
+        opens: bool,
+    ) -> io::Result<(i32, Vec<u8>)> {
+        let (done, result) = oneshot::channel();
+        {
+            let mut state = self.state.lock().expect("ring lock");
+            let id = state.next_id;
+            state.next_id += 1;
+            let entry = entry.user_data(id);
+            // Safety: the buffer is kept with the operation until it's done
+            if unsafe { state.ring.submission().push(&entry) }.is_err() {
+                // The queue is full of entries not yet given to the kernel
+                state.ring.submit()?;
+                unsafe { state.ring.submission().push(&entry) }.map_err(io::Error::other)?;
+            }
+            state.ops.insert(id, Op { buf, opens, done });
+            state.ring.submit()?;
+        }
+        let (result, buf) = result
+            .await
+            .map_err(|_| io::Error::other("io_uring stopped"))?;
+        if result < 0 {
+            return Err(io::Error::from_raw_os_error(-result));
+        }
+        Ok((result, buf))
+    }
+
+    /// Hand out the results of the operations that are done.
+    fn complete(&self) {
+        let mut state = self.state.lock().expect("ring lock");
+        let results: Vec<_> = state
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect();
+        for (id, result) in results {
+            let op = match state.ops.remove(&id) {
+                Some(op) => op,
+                None => continue,
+            };
+            if op.done.send((result, op.buf)).is_err() && op.opens && result >= 0 {
+                // Safety: the descriptor was opened for the task that's gone
+                drop(unsafe { OwnedFd::from_raw_fd(result) });
+            }
+        }
+    }
+}
+
+/// Hand out the ring's results each time its eventfd says there are some.
+async fn drive(eventfd: AsyncFd<OwnedFd>) {
+    loop {
+        let mut guard = match eventfd.readable().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("io_uring eventfd error: {}", e);
+                return;
+            }
+        };
+        // Reset the count of completions before handing them out, so that
+        // any that come meanwhile wake this again
+        let mut count = [0u8; 8];
+        // Safety: the buffer is the 8 bytes an eventfd is read into
+        let read = unsafe { libc::read(eventfd.as_raw_fd(), count.as_mut_ptr().cast(), 8) };
+        if read < 0 {
+            guard.clear_ready();
+            continue;
+        }
+        if let Some(ring) = RING.get() {
+            ring.complete();
+        }
+    }
+}
+
+/// Read a whole file.
+async fn read_file(ring: &Ring, path: &Path) -> io::Result<Vec<u8>> {
+    let file = open(ring, path).await?;
+    // The length is only a guess at how much to read, as the file may change
+    let mut buf = vec![0; file.metadata()?.len() as usize];
+    let mut done = 0;
+    loop {
+        if done == buf.len() {
+            // Either the file has grown, or this read finds its end
+            buf.resize(buf.len() + READ_AHEAD, 0);
+        }
+        let remaining = (buf.len() - done).min(u32::MAX as usize) as u32;
+        let read = opcode::Read::new(
+            types::Fd(file.as_raw_fd()),
+            buf[done..].as_mut_ptr(),
+            remaining,
+        )
+        .offset(done as u64)
+        .build();
+        // Safety: the read is into the buffer. The kernel holds on to the
+        // file until it's done, even if the task has gone and closed it.
+        let (n, returned) = unsafe { ring.submit(read, buf, false) }.await?;
+        buf = returned;
+        match n {
+            0 => break,
+            n => done += n as usize,
+        }
+    }
+    buf.truncate(done);
+    Ok(buf)
+}
+
+/// Get a file's metadata.
+async fn stat(ring: &Ring, path: &Path) -> io::Result<Metadata> {
+    // The buffer holds the statx the kernel fills in, followed by the path
+    let len = mem::size_of::<libc::statx>();
+    let mut buf = vec![0; len];
+    buf.extend_from_slice(&path_bytes(path)?);
+    let statx = opcode::Statx::new(
+        types::Fd(libc::AT_FDCWD),
+        buf[len..].as_ptr().cast(),
+        buf.as_mut_ptr().cast(),
+    )
+    .mask(libc::STATX_TYPE | libc::STATX_SIZE | libc::STATX_MTIME)
+    .build();
+    // Safety: the path and the statx are in the buffer
+    let (_, buf) = unsafe { ring.submit(statx, buf, false) }.await?;
+    // Safety: the kernel filled in the statx, which may not be aligned
+    let statx: libc::statx = unsafe { ptr::read_unaligned(buf.as_ptr().cast()) };
+    let modified = Some(statx.stx_mtime)
+        .filter(|_| statx.stx_mask & libc::STATX_MTIME != 0)
+        .and_then(|mtime| {
+            let since_epoch = Duration::new(u64::try_from(mtime.tv_sec).ok()?, mtime.tv_nsec);
+            UNIX_EPOCH.checked_add(since_epoch)
+        });
+    Ok(Metadata {
+        is_dir: u32::from(statx.stx_mode) & libc::S_IFMT == libc::S_IFDIR,
+        len: statx.stx_size,
+        modified,
+    })
+}
+
+/// Open a file for reading.
+async fn open(ring: &Ring, path: &Path) -> io::Result<File> {
+    let path = path_bytes(path)?;
+    let open = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr().cast())
+        .flags(libc::O_RDONLY | libc::O_CLOEXEC)
+        .build();
+    // Safety: the path is the buffer
+    let (fd, _) = unsafe { ring.submit(open, path, true) }.await?;
+    // Safety: the descriptor was just opened, and nothing else owns it
+    Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// A path as the kernel takes it, ending with a nul
+fn path_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    Ok(CString::new(path.as_os_str().as_bytes())?.into_bytes_with_nul())
+}
@@ -0,0 +1,59 @@
+//! Keeping track of the request headers that a response depended on, to list
+//! them in its `Vary` header. Caches then keep the responses for different
+//! values of those headers apart, like a rendered page and the raw file.
+//!
+//! Code that chooses a response by a request header reads it with `header`,
+//! instead of from the request directly.
+
+use crate::Body;
+use http::header::{self, HeaderName, HeaderValue};
+use http::{Request, Response};
+use std::sync::{Arc, Mutex};
+
+/// The request headers read so far, kept in the request's extensions
+#[derive(Clone, Default)]
+pub struct Vary(Arc<Mutex<Vec<HeaderName>>>);
+
+impl Vary {
+    /// Record a request header that the response depends on, for when the
+    /// request itself is no longer at hand.
+    pub fn add(&self, name: HeaderName) {
+        let mut names = self.0.lock().unwrap();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    /// Add the headers read to the response's `Vary` header, after any it
+    /// already lists.
+    pub fn apply(&self, resp: &mut Response<Body>) {
+        let names = self.0.lock().unwrap();
+        if names.is_empty() {
+            return;
+        }
+        let mut vary: Vec<String> = resp
+            .headers()
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        for name in names.iter() {
+            if !vary.iter().any(|n| n == name.as_str()) {
+                vary.push(name.as_str().to_string());
+            }
+        }
+        let value = HeaderValue::from_str(&vary.join(", ")).expect("header names");
+        resp.headers_mut().insert(header::VARY, value);
+    }
+}
+
+/// Read a request header that the response will depend on.
+pub fn header<B>(req: &Request<B>, name: HeaderName) -> Option<&HeaderValue> {
+    if let Some(vary) = req.extensions().get::<Vary>() {
+        vary.add(name.clone());
+    }
+    req.headers().get(name)
+}
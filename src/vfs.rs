@@ -0,0 +1,178 @@
+//! The filesystem that files are served from.
+//!
+//! All file access goes through the `Vfs` trait, so that files can be served
+//! from places other than the local disk, like archives or memory. Paths are
+//! always the full paths that requests map to, i.e. they start with the root
+//! directory.
+
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::AsyncRead;
+
+/// The trait's methods can't be `async fn`s and still be called through
+/// `dyn Vfs`, so they return boxed futures instead.
+pub type VfsFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// An open file, ready to be read
+pub type VfsFile = Box<dyn AsyncRead + Send + Unpin>;
+
+#[derive(Clone, Debug)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+pub trait Vfs: Send + Sync {
+    /// Open a file. Directories can't be opened.
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile>;
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata>;
+
+    /// List the paths of a directory's entries, in no particular order.
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>>;
+
+    /// Whether the paths are paths on the local disk, for serving files in
+    /// ways that need a real file.
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    /// Forget anything cached about the filesystem.
+    fn purge(&self) {}
+}
+
+/// Missing files are reported as I/O errors, as from the local disk, so that
+/// all filesystems get the same 404 responses.
+pub fn not_found() -> Error {
+    Error::Io(io::Error::from(io::ErrorKind::NotFound))
+}
+
+/// The local disk
+pub struct LocalFs;
+
+impl Vfs for LocalFs {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        Box::pin(async move {
+            let file = fs::File::open(path).await?;
+            Ok(Box::new(file) as VfsFile)
+        })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        Box::pin(async move {
+            let m = fs::metadata(path).await?;
+            Ok(Metadata {
+                is_dir: m.is_dir(),
+                len: m.len(),
+                modified: m.modified().ok(),
+            })
+        })
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        Box::pin(async move {
+            let mut read_dir = fs::read_dir(path).await?;
+            let mut paths = Vec::new();
+            while let Some(dent) = read_dir.next_entry().await? {
+                paths.push(dent.path());
+            }
+            Ok(paths)
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+/// Files held in memory. Directories are implied by the files within them.
+pub struct MemoryFs {
+    root_dir: PathBuf,
+    files: BTreeMap<PathBuf, Arc<[u8]>>,
+    modified: SystemTime,
+}
+
+impl MemoryFs {
+    /// Create an empty filesystem. All of its files share the modification
+    /// time.
+    pub fn new(root_dir: &Path, modified: SystemTime) -> MemoryFs {
+        MemoryFs {
+            root_dir: root_dir.to_owned(),
+            files: BTreeMap::new(),
+            modified,
+        }
+    }
+
+    /// Add a file, given its path relative to the root directory.
+    pub fn insert(&mut self, path: &Path, data: Vec<u8>) {
+        self.files.insert(self.root_dir.join(path), data.into());
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path == self.root_dir
+            || self
+                .files
+                .range(path.to_owned()..)
+                .next()
+                .map(|(p, _)| p.starts_with(path) && p != path)
+                .unwrap_or(false)
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, VfsFile> {
+        let file = self
+            .files
+            .get(path)
+            .map(|data| Box::new(Cursor::new(data.clone())) as VfsFile)
+            .ok_or_else(not_found);
+        Box::pin(async { file })
+    }
+
+    fn metadata<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Metadata> {
+        let metadata = match self.files.get(path) {
+            Some(data) => Ok(Metadata {
+                is_dir: false,
+                len: data.len() as u64,
+                modified: Some(self.modified),
+            }),
+            None if self.is_dir(path) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: Some(self.modified),
+            }),
+            None => Err(not_found()),
+        };
+        Box::pin(async { metadata })
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> VfsFuture<'a, Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return Box::pin(async { Err(not_found()) });
+        }
+        let mut entries: Vec<PathBuf> = self
+            .files
+            .range(path.to_owned()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .filter_map(|p| p.strip_prefix(path).ok()?.components().next())
+            .map(|c| path.join(c))
+            .collect();
+        entries.dedup();
+        Box::pin(async { Ok(entries) })
+    }
+}
+
+/// Read a whole file.
+pub async fn read(vfs: &dyn Vfs, path: &Path) -> Result<Vec<u8>> {
+    let file = vfs.open(path).await?;
+    crate::read_file(file).await
+}
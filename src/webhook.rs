@@ -0,0 +1,203 @@
+//! Posting events to webhooks with `--webhook`, for telling chat or ops
+//! tools when the server starts and stops, or when a missing path is asked
+//! for. Each webhook is an `http://` URL, optionally preceded by the events
+//! it wants, like `started,stopped=http://ops.local/hook`, and gets a JSON
+//! object for each event. Events are posted in the background, and retried a
+//! few times if the webhook can't be reached or fails.
+
+use crate::client::Target;
+use crate::{Body, Error, Result};
+use http::uri::PathAndQuery;
+use http::{header, Method, Request, Uri};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Semaphore;
+
+/// How many events may be waiting to be posted at once
+const MAX_IN_FLIGHT: usize = 64;
+
+/// How long an attempt to post an event may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times an event is posted before giving up
+const ATTEMPTS: u32 = 3;
+
+/// How long to wait before the first retry, which doubles for each one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Started,
+    Stopped,
+    /// A request was answered with a 404
+    NotFound,
+}
+
+impl Event {
+    const ALL: [Event; 3] = [Event::Started, Event::Stopped, Event::NotFound];
+
+    fn name(self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            Event::Stopped => "stopped",
+            Event::NotFound => "not-found",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Webhook {
+    url: String,
+    target: Target,
+    path: PathAndQuery,
+    events: Vec<Event>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    webhooks: Arc<Vec<Webhook>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Webhooks {
+    pub fn new<'a>(values: impl IntoIterator<Item = &'a str>) -> Result<Webhooks> {
+        let webhooks = values
+            .into_iter()
+            .map(|value| parse(value).ok_or_else(|| Error::WebhookParse(value.to_owned())))
+            .collect::<Result<_>>()?;
+        Ok(Webhooks {
+            webhooks: Arc::new(webhooks),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.webhooks.len()
+    }
+
+    /// Post the event in the background to the webhooks that want it, with
+    /// the fields of `details` added to its JSON.
+    pub fn fire(&self, event: Event, details: Value) {
+        for (webhook, payload) in self.payloads(event, details) {
+            let permit = match self.in_flight.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    debug!("webhooks are behind, not posting {}", event.name());
+                    return;
+                }
+            };
+            let webhooks = self.webhooks.clone();
+            tokio::spawn(async move {
+                post(&webhooks[webhook], &payload, ATTEMPTS).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// Post the event to the webhooks that want it, once, and wait for them,
+    /// for events as the server stops.
+    pub async fn fire_and_wait(&self, event: Event, details: Value) {
+        let posts: Vec<_> = self
+            .payloads(event, details)
+            .map(|(webhook, payload)| {
+                let webhooks = self.webhooks.clone();
+                tokio::spawn(async move { post(&webhooks[webhook], &payload, 1).await })
+            })
+            .collect();
+        for post in posts {
+            let _ = post.await;
+        }
+    }
+
+    /// The webhooks that want the event, by index, and what to post to them
+    fn payloads(&self, event: Event, details: Value) -> impl Iterator<Item = (usize, String)> + '_ {
+        let mut payload = json!({
+            "event": event.name(),
+            "time": httpdate::fmt_http_date(SystemTime::now()),
+        });
+        if let (Some(payload), Value::Object(details)) = (payload.as_object_mut(), details) {
+            payload.extend(details);
+        }
+        let payload = payload.to_string();
+        self.webhooks
+            .iter()
+            .enumerate()
+            .filter(move |(_, webhook)| webhook.events.contains(&event))
+            .map(move |(i, _)| (i, payload.clone()))
+    }
+}
+
+/// Parse a webhook like `[EVENTS=]URL`. A URL with `=` in its query is told
+/// apart from events by its `://`.
+fn parse(value: &str) -> Option<Webhook> {
+    let (events, url) = match value.split_once('=') {
+        Some((events, url)) if !events.contains("://") => {
+            let events = events
+                .split(',')
+                .map(|name| {
+                    Event::ALL
+                        .iter()
+                        .copied()
+                        .find(|event| event.name() == name.trim())
+                })
+                .collect::<Option<Vec<_>>>()?;
+            (events, url)
+        }
+        _ => (Event::ALL.to_vec(), value),
+    };
+    let target = Target::parse(url)?;
+    let uri: Uri = url.parse().ok()?;
+    let path = uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static("/"));
+    Some(Webhook {
+        url: url.to_owned(),
+        target,
+        path,
+        events,
+    })
+}
+
+/// Post the payload, retrying with a growing delay while the webhook can't be
+/// reached, times out, or fails with a server error or a 429.
+async fn post(webhook: &Webhook, payload: &str, attempts: u32) {
+    let mut delay = RETRY_DELAY;
+    for attempt in 1..=attempts {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(webhook.path.clone())
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::CONTENT_LENGTH, payload.len())
+            .body(Body::from(payload.to_owned()))
+            .expect("valid request");
+        let failure = match tokio::time::timeout(TIMEOUT, webhook.target.send(req)).await {
+            Ok(Ok(status)) if status.is_server_error() || status.as_u16() == 429 => {
+                format!("status {}", status)
+            }
+            Ok(Ok(status)) => {
+                if !status.is_success() {
+                    warn!("webhook {} refused an event: {}", webhook.url, status);
+                }
+                return;
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timed out".to_owned(),
+        };
+        if attempt == attempts {
+            warn!("failed to post to webhook {}: {}", webhook.url, failure);
+            return;
+        }
+        debug!(
+            "failed to post to webhook {}, retrying: {}",
+            webhook.url, failure
+        );
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}